@@ -5,6 +5,8 @@ use axum::{
 };
 use serde::Serialize;
 
+use crate::auth::AuthError;
+
 pub type AppResult<T> = Result<AppJson<T>, AppError>;
 
 #[derive(FromRequest)]
@@ -29,8 +31,13 @@ impl IntoResponse for AppError {
             message: String,
         }
 
+        let status = match self.0.downcast_ref::<AuthError>() {
+            Some(err) => err.status(),
+            None => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
         (
-            StatusCode::INTERNAL_SERVER_ERROR,
+            status,
             AppJson(ErrorResponse {
                 message: self.0.to_string(),
             }),
@@ -0,0 +1,281 @@
+use std::collections::{HashMap, HashSet};
+
+use alloy::hex;
+use axum::http::{header::AUTHORIZATION, HeaderMap, StatusCode};
+use sha2::{Digest, Sha256};
+
+use crate::{signers::common::KeyRef, tls::ClientIdentity};
+
+#[derive(Debug)]
+pub enum AuthError {
+    /// The caller did not authenticate
+    Unauthorized(String),
+    /// The caller authenticated but may not use the key
+    Forbidden(String),
+}
+
+impl AuthError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AuthError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AuthError::Forbidden(_) => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+impl std::fmt::Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthError::Unauthorized(message) | AuthError::Forbidden(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Hex encoded SHA-256 of `token`, the form in which bearer tokens are configured.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// A bearer token and the keys and methods it may be used for.
+pub struct TokenScope {
+    pub name: String,
+    hash: [u8; 32],
+    /// Keys the token may use; any key if `None`
+    keys: Option<HashSet<KeyRef>>,
+    /// JSON-RPC methods the token may call; any method if `None`
+    methods: Option<HashSet<String>>,
+}
+
+/// Which callers may use which keys.
+///
+/// Without tokens, keys without an entry can be used by anyone who reaches the proxy.
+/// Once a token is configured, every request to a key needs a valid `Authorization: Bearer` header.
+#[derive(Default)]
+pub struct AccessControl {
+    clients: HashMap<KeyRef, Vec<String>>,
+    tokens: Vec<TokenScope>,
+}
+
+impl AccessControl {
+    /// Restricts `key` to clients whose certificate subject CN or SAN is one of `clients`.
+    pub fn allow_clients(&mut self, key: KeyRef, clients: &[String]) {
+        self.clients.entry(key).or_default().extend_from_slice(clients);
+    }
+
+    /// Accepts the token whose SHA-256 is `hash` for `keys` and `methods` (all if `None`).
+    pub fn add_token(
+        &mut self,
+        name: String,
+        hash: [u8; 32],
+        keys: Option<HashSet<KeyRef>>,
+        methods: Option<HashSet<String>>,
+    ) {
+        self.tokens.push(TokenScope {
+            name,
+            hash,
+            keys,
+            methods,
+        });
+    }
+
+    /// Finds the token presented in the `Authorization` header, if tokens are configured.
+    pub fn authenticate(&self, headers: &HeaderMap) -> Result<Option<&TokenScope>, AuthError> {
+        if self.tokens.is_empty() {
+            return Ok(None);
+        }
+
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| AuthError::Unauthorized("missing bearer token".to_string()))?;
+
+        // Only digests are compared, so the comparison does not leak the configured tokens
+        let hash: [u8; 32] = Sha256::digest(token.trim().as_bytes()).into();
+        self.tokens
+            .iter()
+            .find(|scope| scope.hash == hash)
+            .map(Some)
+            .ok_or_else(|| AuthError::Unauthorized("invalid bearer token".to_string()))
+    }
+
+    /// Checks that `token` may call `method`, for methods that do not use a key.
+    pub fn authorize_method(&self, token: Option<&TokenScope>, method: &str) -> Result<(), AuthError> {
+        if let Some(token) = token
+            && token.methods.as_ref().is_some_and(|methods| !methods.contains(method))
+        {
+            return Err(AuthError::Forbidden(format!(
+                "token {} is not allowed to call {}",
+                token.name, method
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the caller may use `key`, and call `method` on it when given.
+    pub fn authorize(
+        &self,
+        identity: Option<&ClientIdentity>,
+        token: Option<&TokenScope>,
+        key: &KeyRef,
+        method: Option<&str>,
+    ) -> Result<(), AuthError> {
+        if let Some(token) = token
+            && token.keys.as_ref().is_some_and(|keys| !keys.contains(key))
+        {
+            return Err(AuthError::Forbidden(format!(
+                "token {} is not allowed to use key {}",
+                token.name, key
+            )));
+        }
+
+        if let Some(method) = method {
+            self.authorize_method(token, method)?;
+        }
+
+        let Some(clients) = self.clients.get(key) else {
+            return Ok(());
+        };
+
+        let identity = identity.ok_or_else(|| {
+            AuthError::Unauthorized(format!("key {} requires a client certificate", key))
+        })?;
+
+        if clients.iter().any(|client| identity.matches(client)) {
+            Ok(())
+        } else {
+            Err(AuthError::Forbidden(format!(
+                "client certificate is not allowed to use key {}",
+                key
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+    use crate::signers::common::BackendKind;
+
+    fn key(key_id: &str) -> KeyRef {
+        KeyRef {
+            backend: BackendKind::Yubihsm,
+            key_id: key_id.to_string(),
+        }
+    }
+
+    fn bearer(token: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&format!("Bearer {}", token)).unwrap());
+        headers
+    }
+
+    fn hash(token: &str) -> [u8; 32] {
+        Sha256::digest(token.as_bytes()).into()
+    }
+
+    fn access() -> AccessControl {
+        let mut access = AccessControl::default();
+        access.add_token("admin".to_string(), hash("admin-token"), None, None);
+        access.add_token(
+            "batcher".to_string(),
+            hash("batcher-token"),
+            Some(HashSet::from([key("1")])),
+            Some(HashSet::from(["eth_signTransaction".to_string()])),
+        );
+        access
+    }
+
+    #[test]
+    fn hash_token_is_hex_sha256() {
+        assert_eq!(
+            hash_token("hunter2"),
+            "f52fbd32b2b3b86ff88ef6c490628285f482af15ddcb29541f94bcf526a3f6c7"
+        );
+    }
+
+    #[test]
+    fn no_tokens_needs_no_header() {
+        let access = AccessControl::default();
+
+        assert!(access.authenticate(&HeaderMap::new()).unwrap().is_none());
+        assert!(access.authorize(None, None, &key("1"), Some("eth_sign")).is_ok());
+    }
+
+    #[test]
+    fn tokens_require_a_valid_bearer_header() {
+        let access = access();
+
+        let missing = access.authenticate(&HeaderMap::new()).err().unwrap();
+        assert!(matches!(missing, AuthError::Unauthorized(_)));
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+
+        let mut basic = HeaderMap::new();
+        basic.insert(AUTHORIZATION, HeaderValue::from_static("Basic YWRtaW4tdG9rZW4="));
+        assert!(matches!(access.authenticate(&basic), Err(AuthError::Unauthorized(_))));
+        assert!(matches!(access.authenticate(&bearer("wrong")), Err(AuthError::Unauthorized(_))));
+
+        assert_eq!(access.authenticate(&bearer("batcher-token")).unwrap().unwrap().name, "batcher");
+    }
+
+    #[test]
+    fn token_scoped_to_keys() {
+        let access = access();
+        let batcher = access.authenticate(&bearer("batcher-token")).unwrap();
+        let admin = access.authenticate(&bearer("admin-token")).unwrap();
+
+        assert!(access.authorize(None, batcher, &key("1"), None).is_ok());
+        let err = access.authorize(None, batcher, &key("2"), None).unwrap_err();
+        assert!(matches!(err, AuthError::Forbidden(_)));
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+
+        let other_backend = KeyRef {
+            backend: BackendKind::AwsKms,
+            key_id: "1".to_string(),
+        };
+        assert!(access.authorize(None, batcher, &other_backend, None).is_err());
+        assert!(access.authorize(None, admin, &key("2"), None).is_ok());
+    }
+
+    #[test]
+    fn token_scoped_to_methods() {
+        let access = access();
+        let batcher = access.authenticate(&bearer("batcher-token")).unwrap();
+        let admin = access.authenticate(&bearer("admin-token")).unwrap();
+
+        assert!(access.authorize(None, batcher, &key("1"), Some("eth_signTransaction")).is_ok());
+        assert!(matches!(
+            access.authorize(None, batcher, &key("1"), Some("eth_sign")),
+            Err(AuthError::Forbidden(_))
+        ));
+        assert!(access.authorize_method(batcher, "eth_accounts").is_err());
+        assert!(access.authorize_method(admin, "eth_accounts").is_ok());
+    }
+
+    #[test]
+    fn client_restricted_key_needs_a_matching_certificate() {
+        let mut access = AccessControl::default();
+        access.allow_clients(key("1"), &["batcher.internal".to_string()]);
+        let batcher = ClientIdentity {
+            common_names: vec!["op-batcher".to_string()],
+            alt_names: vec!["batcher.internal".to_string()],
+        };
+        let proposer = ClientIdentity {
+            common_names: vec!["op-proposer".to_string()],
+            alt_names: vec![],
+        };
+
+        assert!(matches!(access.authorize(None, None, &key("1"), None), Err(AuthError::Unauthorized(_))));
+        assert!(matches!(
+            access.authorize(Some(&proposer), None, &key("1"), None),
+            Err(AuthError::Forbidden(_))
+        ));
+        assert!(access.authorize(Some(&batcher), None, &key("1"), None).is_ok());
+        assert!(access.authorize(None, None, &key("2"), None).is_ok());
+    }
+}
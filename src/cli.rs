@@ -1,6 +1,22 @@
+use std::path::PathBuf;
+
+use anyhow::Result as AnyhowResult;
 use structopt::StructOpt;
+use strum::VariantNames;
 
-use crate::signers::{aws_kms::AwsOpt, yubihsm::YubiOpt};
+use crate::{
+    config::{BackendsConfig, Config, ConfigOpt, ServerOpt},
+    signers::{
+        aws_kms::{AwsKmsConfig, AwsOpt},
+        azure_key_vault::{AzureKeyVaultConnOpt, AzureKeyVaultOpt},
+        common::BackendKind,
+        gcp_kms::{GcpKmsConnOpt, GcpKmsOpt},
+        keystore::{KeystoreConnOpt, KeystoreOpt},
+        pkcs11::{Pkcs11ConnOpt, Pkcs11Opt},
+        vault::{VaultConnOpt, VaultOpt},
+        yubihsm::{YubiConnOpt, YubiOpt},
+    },
+};
 
 #[derive(StructOpt)]
 pub struct Opt {
@@ -8,8 +24,114 @@ pub struct Opt {
     pub cmd: Command,
 }
 
+// Parsed once at startup, boxing the options would only complicate the StructOpt derive
+#[allow(clippy::large_enum_variant)]
 #[derive(StructOpt)]
 pub enum Command {
     Yubihsm(YubiOpt),
     AwsKms(AwsOpt),
+    /// Serves Web3 Secret Storage keystore files, for environments without an HSM
+    Keystore(KeystoreOpt),
+    /// Serves secp256k1 keys of a PKCS#11 token (SoftHSM2, Thales Luna, Utimaco, ...)
+    Pkcs11(Pkcs11Opt),
+    /// Serves ecdsa-p256k1 keys of a Vault or OpenBao transit engine
+    Vault(VaultOpt),
+    /// Serves EC_SIGN_SECP256K1_SHA256 keys of a Google Cloud KMS key ring
+    GcpKms(GcpKmsOpt),
+    /// Serves P-256K keys of an Azure Key Vault
+    AzureKeyVault(AzureKeyVaultOpt),
+    /// Serves several backends at once, each under its own path prefix
+    Serve(ServeOpt),
+    /// Configuration file utilities
+    Config(ConfigOpt),
+}
+
+#[derive(StructOpt)]
+pub struct ServeOpt {
+    /// Configuration file (TOML, or YAML with a .yaml/.yml extension)
+    #[structopt(short, long, env = "SIGNER_PROXY_CONFIG", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Backends to serve (yubihsm under /yubihsm, aws-kms under /aws-kms, keystore under /keystore,
+    /// pkcs11 under /pkcs11, vault under /vault, gcp-kms under /gcp-kms, azure-key-vault under
+    /// /azure-key-vault)
+    #[structopt(
+        short,
+        long = "backend",
+        possible_values = BackendKind::VARIANTS,
+        required_unless = "config",
+        conflicts_with = "config",
+        use_delimiter = true
+    )]
+    pub backends: Vec<BackendKind>,
+
+    #[structopt(flatten)]
+    pub server: ServerOpt,
+
+    #[structopt(flatten)]
+    pub yubihsm: YubiConnOpt,
+
+    #[structopt(flatten)]
+    pub aws_kms: AwsKmsConfig,
+
+    #[structopt(flatten)]
+    pub keystore: KeystoreConnOpt,
+
+    #[structopt(flatten)]
+    pub pkcs11: Pkcs11ConnOpt,
+
+    #[structopt(flatten)]
+    pub vault: VaultConnOpt,
+
+    #[structopt(flatten)]
+    pub gcp_kms: GcpKmsConnOpt,
+
+    #[structopt(flatten)]
+    pub azure_key_vault: AzureKeyVaultConnOpt,
+}
+
+impl ServeOpt {
+    /// Loads `--config` if given, otherwise builds the configuration from the CLI flags.
+    pub fn load_config(self) -> AnyhowResult<Config> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config {
+                backends: BackendsConfig {
+                    yubihsm: self
+                        .backends
+                        .contains(&BackendKind::Yubihsm)
+                        .then_some(self.yubihsm),
+                    aws_kms: self
+                        .backends
+                        .contains(&BackendKind::AwsKms)
+                        .then_some(self.aws_kms),
+                    keystore: self
+                        .backends
+                        .contains(&BackendKind::Keystore)
+                        .then_some(self.keystore),
+                    pkcs11: self
+                        .backends
+                        .contains(&BackendKind::Pkcs11)
+                        .then_some(self.pkcs11),
+                    vault: self
+                        .backends
+                        .contains(&BackendKind::Vault)
+                        .then_some(self.vault),
+                    gcp_kms: self
+                        .backends
+                        .contains(&BackendKind::GcpKms)
+                        .then_some(self.gcp_kms),
+                    azure_key_vault: self
+                        .backends
+                        .contains(&BackendKind::AzureKeyVault)
+                        .then_some(self.azure_key_vault),
+                },
+                ..Default::default()
+            },
+        };
+
+        self.server.apply(&mut config.server);
+        config.validate()?;
+        Ok(config)
+    }
 }
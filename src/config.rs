@@ -0,0 +1,636 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{self, BufRead},
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{anyhow, bail, Context, Result as AnyhowResult};
+use serde::Deserialize;
+use serde_with::{formats::PreferOne, serde_as, OneOrMany};
+use structopt::StructOpt;
+
+use crate::{
+    auth::hash_token,
+    policy::TxPolicy,
+    signers::{
+        aws_kms::{split_account, AwsKmsConfig},
+        azure_key_vault::{self, AzureKeyVaultConnOpt},
+        common::BackendKind,
+        gcp_kms::{self, GcpKmsConnOpt},
+        keystore::KeystoreConnOpt,
+        pkcs11::{self, Pkcs11ConnOpt},
+        vault::{self, VaultConnOpt},
+        yubihsm::{self, YubiConnOpt},
+    },
+    tls::{load_server_config, TlsConfig},
+};
+
+const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:4000";
+const DEFAULT_API_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+
+#[derive(StructOpt)]
+pub struct ConfigOpt {
+    #[structopt(subcommand)]
+    pub cmd: ConfigCommand,
+}
+
+#[derive(StructOpt)]
+pub enum ConfigCommand {
+    /// Parses and validates a configuration file without serving
+    Check {
+        /// Configuration file (TOML, or YAML with a .yaml/.yml extension)
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Reads a bearer token from stdin and prints the SHA-256 to put in the `tokens` section
+    HashToken,
+}
+
+#[derive(StructOpt)]
+pub struct ServerOpt {
+    /// Addresses to listen on, comma separated (e.g. 127.0.0.1:4000,[::1]:4000) [default: 127.0.0.1:4000]
+    #[structopt(short, long, env = "SIGNER_PROXY_LISTEN", use_delimiter = true)]
+    pub listen: Vec<SocketAddr>,
+
+    /// PEM certificate chain; enables TLS
+    #[structopt(long, env = "SIGNER_PROXY_TLS_CERT", parse(from_os_str), requires = "tls-key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the TLS certificate
+    #[structopt(long, env = "SIGNER_PROXY_TLS_KEY", parse(from_os_str), requires = "tls-cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM CA bundle used to verify client certificates; enables mutual TLS
+    #[structopt(long, env = "SIGNER_PROXY_TLS_CLIENT_CA", parse(from_os_str), requires = "tls-cert")]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Maximum number of requests in a JSON-RPC batch [default: 100]
+    #[structopt(long, env = "SIGNER_PROXY_MAX_BATCH_SIZE")]
+    pub max_batch_size: Option<usize>,
+}
+
+impl ServerOpt {
+    /// Overrides the settings of `server` with those given on the command line.
+    pub fn apply(self, server: &mut ServerConfig) {
+        if !self.listen.is_empty() {
+            server.listen = self.listen;
+        }
+
+        if let Some(max_batch_size) = self.max_batch_size {
+            server.max_batch_size = max_batch_size;
+        }
+
+        if let (Some(cert), Some(key)) = (self.tls_cert, self.tls_key) {
+            server.tls = Some(TlsConfig {
+                cert,
+                key,
+                client_ca: self.tls_client_ca,
+            });
+        }
+    }
+}
+
+/// Configuration of `signer-proxy serve`, loaded from `--config` or built from the CLI flags.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub backends: BackendsConfig,
+    /// Named keys, served under `/key/{name}`
+    #[serde(default)]
+    pub keys: BTreeMap<String, KeyConfig>,
+    /// Bearer tokens; when any is configured, every key request must present one
+    #[serde(default)]
+    pub tokens: Vec<TokenConfig>,
+}
+
+#[serde_as]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    /// One address or a list of addresses
+    #[serde_as(as = "OneOrMany<_, PreferOne>")]
+    #[serde(default = "default_listen")]
+    pub listen: Vec<SocketAddr>,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Maximum number of requests in a JSON-RPC batch
+    #[serde(default = "default_max_batch_size")]
+    pub max_batch_size: usize,
+    pub tls: Option<TlsConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BackendsConfig {
+    pub yubihsm: Option<YubiConnOpt>,
+    pub aws_kms: Option<AwsKmsConfig>,
+    pub keystore: Option<KeystoreConnOpt>,
+    pub pkcs11: Option<Pkcs11ConnOpt>,
+    pub vault: Option<VaultConnOpt>,
+    pub gcp_kms: Option<GcpKmsConnOpt>,
+    pub azure_key_vault: Option<AzureKeyVaultConnOpt>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyConfig {
+    pub backend: BackendKind,
+    pub key_id: String,
+    /// Client certificate subject CNs or SANs allowed to use the key; anyone if empty
+    #[serde(default)]
+    pub clients: Vec<String>,
+    /// Rules checked before signing a transaction with the key
+    pub policy: Option<TxPolicy>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenConfig {
+    /// Shown in logs and error messages, never the token itself
+    pub name: String,
+    /// Hex encoded SHA-256 of the token, as printed by `signer-proxy config hash-token`
+    pub sha256: String,
+    /// Named keys or `backend/key_id` references the token may use; all keys if empty
+    #[serde(default)]
+    pub keys: Vec<String>,
+    /// JSON-RPC methods the token may call; all methods if empty
+    #[serde(default)]
+    pub methods: Vec<String>,
+}
+
+impl TokenConfig {
+    pub fn hash(&self) -> AnyhowResult<[u8; 32]> {
+        let mut hash = [0u8; 32];
+        hex::decode_to_slice(self.sha256.trim_start_matches("0x"), &mut hash)
+            .map_err(|_| anyhow!("token {:?}: sha256 must be 32 hex encoded bytes", self.name))?;
+        Ok(hash)
+    }
+}
+
+/// Reads a password, PIN or token from `path`, without the trailing newline editors and `echo` add.
+pub fn read_secret_file(path: &Path) -> AnyhowResult<String> {
+    let secret = fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(secret.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn default_listen() -> Vec<SocketAddr> {
+    vec![DEFAULT_LISTEN_ADDR.parse().unwrap()]
+}
+
+fn default_timeout_secs() -> u64 {
+    DEFAULT_API_TIMEOUT_SECS
+}
+
+fn default_max_batch_size() -> usize {
+    DEFAULT_MAX_BATCH_SIZE
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: default_listen(),
+            timeout_secs: default_timeout_secs(),
+            max_batch_size: default_max_batch_size(),
+            tls: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn validate(&self) -> AnyhowResult<()> {
+        if self.listen.is_empty() {
+            bail!("server.listen must contain at least one address");
+        }
+
+        for (i, addr) in self.listen.iter().enumerate() {
+            if self.listen[..i].contains(addr) {
+                bail!("server.listen contains {} more than once", addr);
+            }
+        }
+
+        if self.timeout_secs == 0 {
+            bail!("server.timeout_secs must be greater than 0");
+        }
+
+        if self.max_batch_size == 0 {
+            bail!("server.max_batch_size must be greater than 0");
+        }
+
+        if let Some(tls) = &self.tls {
+            load_server_config(tls).context("invalid server.tls")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    /// Reads, parses and validates the configuration file at `path`.
+    pub fn load(path: &Path) -> AnyhowResult<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+
+        let config: Config = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&contents).map_err(|err| anyhow!(err)),
+            _ => toml::from_str(&contents).map_err(|err| anyhow!(err)),
+        }
+        .with_context(|| format!("failed to parse config file {}", path.display()))?;
+
+        config
+            .validate()
+            .with_context(|| format!("invalid config file {}", path.display()))?;
+
+        Ok(config)
+    }
+
+    /// Resolves a named key or a `backend/key_id` reference to its backend and key id.
+    pub fn key_target<'a>(&'a self, reference: &'a str) -> AnyhowResult<(BackendKind, &'a str)> {
+        if let Some(key) = self.keys.get(reference) {
+            return Ok((key.backend, key.key_id.as_str()));
+        }
+
+        let (backend, key_id) = reference
+            .split_once('/')
+            .ok_or_else(|| anyhow!("unknown key {:?}", reference))?;
+        let backend = BackendKind::from_str(backend)
+            .map_err(|_| anyhow!("unknown backend in key reference {:?}", reference))?;
+
+        Ok((backend, key_id))
+    }
+
+    /// Checks `key_id` with the same rules as the backend, so that a valid config is not refused by `serve`.
+    fn check_key(&self, backend: BackendKind, key_id: &str) -> AnyhowResult<()> {
+        match backend {
+            BackendKind::Yubihsm => {
+                if self.backends.yubihsm.is_none() {
+                    bail!("the yubihsm backend is not configured");
+                }
+                yubihsm::parse_key_id(key_id)?;
+            }
+            BackendKind::AwsKms => {
+                let Some(aws_kms) = &self.backends.aws_kms else {
+                    bail!("the aws-kms backend is not configured");
+                };
+                let (account, kms_key_id) = split_account(key_id);
+                if !account.is_empty() && !aws_kms.accounts.contains_key(account) {
+                    bail!("unknown AWS account {:?}", account);
+                }
+                if kms_key_id.is_empty() {
+                    bail!("key_id is empty");
+                }
+            }
+            BackendKind::Keystore => {
+                if self.backends.keystore.is_none() {
+                    bail!("the keystore backend is not configured");
+                }
+                if key_id.is_empty() {
+                    bail!("key_id is empty");
+                }
+            }
+            BackendKind::Pkcs11 => {
+                if self.backends.pkcs11.is_none() {
+                    bail!("the pkcs11 backend is not configured");
+                }
+                pkcs11::check_key_id(key_id)?;
+            }
+            BackendKind::Vault => {
+                if self.backends.vault.is_none() {
+                    bail!("the vault backend is not configured");
+                }
+                vault::check_key_name(key_id)?;
+            }
+            BackendKind::GcpKms => {
+                if self.backends.gcp_kms.is_none() {
+                    bail!("the gcp-kms backend is not configured");
+                }
+                gcp_kms::parse_key_id(key_id)?;
+            }
+            BackendKind::AzureKeyVault => {
+                if self.backends.azure_key_vault.is_none() {
+                    bail!("the azure-key-vault backend is not configured");
+                }
+                azure_key_vault::parse_key_id(key_id)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the configuration for consistency without connecting to any backend.
+    pub fn validate(&self) -> AnyhowResult<()> {
+        if self.backends.yubihsm.is_none()
+            && self.backends.aws_kms.is_none()
+            && self.backends.keystore.is_none()
+            && self.backends.pkcs11.is_none()
+            && self.backends.vault.is_none()
+            && self.backends.gcp_kms.is_none()
+            && self.backends.azure_key_vault.is_none()
+        {
+            bail!("no backends configured");
+        }
+
+        self.server.validate()?;
+
+        if let Some(yubihsm) = &self.backends.yubihsm {
+            yubihsm.validate().context("invalid backends.yubihsm")?;
+        }
+        if let Some(aws_kms) = &self.backends.aws_kms {
+            aws_kms.validate().context("invalid backends.aws-kms")?;
+        }
+        if let Some(keystore) = &self.backends.keystore {
+            keystore.validate().context("invalid backends.keystore")?;
+        }
+        if let Some(pkcs11) = &self.backends.pkcs11 {
+            pkcs11.validate().context("invalid backends.pkcs11")?;
+        }
+        if let Some(vault) = &self.backends.vault {
+            vault.validate().context("invalid backends.vault")?;
+        }
+        if let Some(gcp_kms) = &self.backends.gcp_kms {
+            gcp_kms.validate().context("invalid backends.gcp-kms")?;
+        }
+        if let Some(azure_key_vault) = &self.backends.azure_key_vault {
+            azure_key_vault.validate().context("invalid backends.azure-key-vault")?;
+        }
+
+        for (name, key) in &self.keys {
+            if name.is_empty() || name.contains('/') {
+                bail!("invalid key name {:?}", name);
+            }
+
+            let client_auth = self.server.tls.as_ref().is_some_and(|tls| tls.client_ca.is_some());
+            if !key.clients.is_empty() && !client_auth {
+                bail!("key {:?}: clients requires server.tls.client_ca", name);
+            }
+
+            self.check_key(key.backend, &key.key_id)
+                .with_context(|| format!("invalid key {:?}", name))?;
+            if let Some(policy) = &key.policy {
+                policy
+                    .validate()
+                    .with_context(|| format!("key {:?}: invalid policy", name))?;
+            }
+        }
+
+        let mut names = HashSet::new();
+        let mut hashes = HashSet::new();
+        for token in &self.tokens {
+            if token.name.is_empty() || !names.insert(token.name.as_str()) {
+                bail!("token names must be unique and non-empty: {:?}", token.name);
+            }
+            if !hashes.insert(token.hash()?) {
+                bail!("token {:?} has the same sha256 as another token", token.name);
+            }
+
+            for reference in &token.keys {
+                let (backend, key_id) = self.key_target(reference)?;
+                self.check_key(backend, key_id)
+                    .with_context(|| format!("token {:?}: invalid key {:?}", token.name, reference))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn handle_config(opt: ConfigOpt) -> AnyhowResult<()> {
+    match opt.cmd {
+        ConfigCommand::Check { path } => {
+            let config = Config::load(&path)?;
+            let backends = [
+                config.backends.yubihsm.as_ref().map(|_| "yubihsm"),
+                config.backends.aws_kms.as_ref().map(|_| "aws-kms"),
+                config.backends.keystore.as_ref().map(|_| "keystore"),
+                config.backends.pkcs11.as_ref().map(|_| "pkcs11"),
+                config.backends.vault.as_ref().map(|_| "vault"),
+                config.backends.gcp_kms.as_ref().map(|_| "gcp-kms"),
+                config.backends.azure_key_vault.as_ref().map(|_| "azure-key-vault"),
+            ];
+            let listen: Vec<String> = config.server.listen.iter().map(|addr| addr.to_string()).collect();
+
+            println!("{} is valid", path.display());
+            println!(
+                "Backends: {}",
+                backends.into_iter().flatten().collect::<Vec<_>>().join(", ")
+            );
+            println!("Keys: {}", config.keys.len());
+            println!("Tokens: {}", config.tokens.len());
+            println!("Listen: {}", listen.join(", "));
+        }
+        ConfigCommand::HashToken => {
+            let mut token = String::new();
+            io::stdin().lock().read_line(&mut token)?;
+
+            let token = token.trim();
+            if token.is_empty() {
+                bail!("no token given on stdin");
+            }
+            println!("{}", hash_token(token));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// Token whose hash is in the test configs.
+    const TOKEN: &str = "secret";
+
+    fn toml_config() -> String {
+        format!(
+            r#"
+[server]
+listen = ["127.0.0.1:4001", "127.0.0.1:4002"]
+
+[backends.aws-kms]
+region = "eu-west-1"
+
+[backends.vault]
+address = "http://127.0.0.1:8200"
+token = "root"
+
+[backends.gcp-kms]
+key_ring = "projects/test/locations/global/keyRings/test"
+
+[keys.batcher]
+backend = "aws-kms"
+key_id = "alias/batcher"
+
+[keys.proposer]
+backend = "gcp-kms"
+key_id = "proposer@2"
+
+[[tokens]]
+name = "ops"
+sha256 = "{}"
+keys = ["batcher", "vault/sequencer"]
+methods = ["eth_signTransaction"]
+"#,
+            hash_token(TOKEN)
+        )
+    }
+
+    fn yaml_config() -> String {
+        format!(
+            r#"
+server:
+  listen: 127.0.0.1:4001
+backends:
+  aws-kms:
+    region: eu-west-1
+  vault:
+    address: http://127.0.0.1:8200
+    token: root
+keys:
+  batcher:
+    backend: aws-kms
+    key_id: alias/batcher
+tokens:
+  - name: ops
+    sha256: "0x{}"
+    keys: [batcher, vault/sequencer]
+"#,
+            hash_token(TOKEN)
+        )
+    }
+
+    fn write_config(contents: &str, suffix: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::Builder::new().suffix(suffix).tempfile().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    fn parse(contents: &str) -> AnyhowResult<Config> {
+        let config: Config = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// The error of `parse` with its causes.
+    fn parse_err(contents: &str) -> String {
+        format!("{:#}", parse(contents).err().unwrap())
+    }
+
+    #[test]
+    fn loads_toml() {
+        let file = write_config(&toml_config(), ".toml");
+        let config = Config::load(file.path()).unwrap();
+
+        assert_eq!(config.server.listen.len(), 2);
+        assert_eq!(config.server.timeout_secs, DEFAULT_API_TIMEOUT_SECS);
+        assert_eq!(config.server.max_batch_size, DEFAULT_MAX_BATCH_SIZE);
+        assert!(config.backends.aws_kms.is_some() && config.backends.vault.is_some());
+        assert!(config.backends.yubihsm.is_none());
+        assert_eq!(config.keys["proposer"].key_id, "proposer@2");
+        assert_eq!(config.tokens[0].methods, ["eth_signTransaction"]);
+    }
+
+    #[test]
+    fn loads_yaml() {
+        for suffix in [".yaml", ".yml"] {
+            let file = write_config(&yaml_config(), suffix);
+            let config = Config::load(file.path()).unwrap();
+
+            assert_eq!(config.server.listen, ["127.0.0.1:4001".parse::<SocketAddr>().unwrap()]);
+            assert_eq!(config.keys["batcher"].backend, BackendKind::AwsKms);
+            assert_eq!(config.tokens[0].keys, ["batcher", "vault/sequencer"]);
+        }
+
+        // YAML is only recognized by its extension
+        let file = write_config(&yaml_config(), ".toml");
+        assert!(Config::load(file.path()).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let err = parse_err(&format!("{}\nlisten = \"127.0.0.1:4000\"", toml_config()));
+        assert!(err.contains("unknown field"), "{}", err);
+
+        let config = toml_config().replace("region = \"eu-west-1\"", "regoin = \"eu-west-1\"");
+        assert!(parse_err(&config).contains("unknown field `regoin`"));
+
+        let config = toml_config().replace("[backends.vault]", "[backends.hashicorp]");
+        assert!(parse_err(&config).contains("unknown field `hashicorp`"));
+    }
+
+    #[test]
+    fn validates() {
+        assert!(parse(&toml_config()).is_ok());
+        assert!(parse_err("").contains("no backends configured"));
+
+        let config = toml_config().replace("listen = [\"127.0.0.1:4001\", \"127.0.0.1:4002\"]", "timeout_secs = 0");
+        assert!(parse_err(&config).contains("timeout_secs"));
+
+        let config = toml_config().replace("key_id = \"alias/batcher\"", "key_id = \"unknown:alias/batcher\"");
+        assert!(parse_err(&config).contains("unknown AWS account"));
+
+        let config = format!("{}\n[keys.proposer.policy]\nallowed_methods = []\n", toml_config());
+        assert!(parse_err(&config).contains("key \"proposer\": invalid policy"));
+
+        let config = format!("{}\n[keys.signer]\nbackend = \"keystore\"\nkey_id = \"signer\"\n", toml_config());
+        assert!(parse_err(&config).contains("the keystore backend is not configured"));
+
+        let config = format!("{}\n[keys.signer]\nbackend = \"aws-kms\"\nkey_id = \"a\"\nclients = [\"ops\"]\n", toml_config());
+        assert!(parse_err(&config).contains("clients requires server.tls.client_ca"));
+
+        let config = format!("{}\n[[tokens]]\nname = \"copy\"\nsha256 = \"{}\"\n", toml_config(), hash_token(TOKEN));
+        assert!(parse_err(&config).contains("same sha256"));
+    }
+
+    #[test]
+    fn checks_key_ids_like_the_backends() {
+        let key = |backend: &str, key_id: &str| {
+            format!("{}\n[keys.signer]\nbackend = \"{}\"\nkey_id = \"{}\"\n", toml_config(), backend, key_id)
+        };
+
+        assert!(parse(&key("vault", "sequencer_1")).is_ok());
+        assert!(parse(&key("gcp-kms", "batcher@3")).is_ok());
+        // Refused by the backends, so by `config check` too
+        for (backend, key_id) in [("vault", "a.b"), ("vault", "a b"), ("gcp-kms", "batcher@latest"), ("gcp-kms", "a.b")] {
+            assert!(parse(&key(backend, key_id)).is_err(), "{} {}", backend, key_id);
+        }
+
+        let config = toml_config().replace("vault/sequencer", "vault/a.b");
+        assert!(parse_err(&config).contains("token \"ops\": invalid key"));
+    }
+
+    #[test]
+    fn resolves_key_targets() {
+        let config = parse(&toml_config()).unwrap();
+
+        assert_eq!(config.key_target("batcher").unwrap(), (BackendKind::AwsKms, "alias/batcher"));
+        assert_eq!(config.key_target("vault/sequencer").unwrap(), (BackendKind::Vault, "sequencer"));
+        assert_eq!(config.key_target("aws-kms/alias/batcher").unwrap(), (BackendKind::AwsKms, "alias/batcher"));
+        assert!(config.key_target("sequencer").is_err());
+        assert!(config.key_target("hashicorp/sequencer").is_err());
+    }
+
+    #[test]
+    fn parses_token_hashes() {
+        let token = |sha256: &str| TokenConfig {
+            name: "ops".to_string(),
+            sha256: sha256.to_string(),
+            keys: Vec::new(),
+            methods: Vec::new(),
+        };
+
+        let hash = hash_token(TOKEN);
+        let expected = hex::decode(&hash).unwrap();
+        assert_eq!(token(&hash).hash().unwrap().as_slice(), expected);
+        assert_eq!(token(&format!("0x{}", hash)).hash().unwrap().as_slice(), expected);
+        assert!(token(&hash[2..]).hash().is_err());
+        assert!(token(&hash.replace(&hash[..2], "zz")).hash().is_err());
+    }
+}
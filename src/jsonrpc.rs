@@ -1,17 +1,95 @@
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::{Map, Number, Value};
+
+use crate::auth::AuthError;
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// The backend failed to produce a signature
+pub const SIGNER_ERROR: i64 = -32000;
+/// Missing or invalid credentials (HTTP 401)
+pub const UNAUTHORIZED: i64 = -32001;
+/// Valid credentials that may not use the key or method (HTTP 403)
+pub const FORBIDDEN: i64 = -32002;
+/// A transaction policy rejected the transaction (EIP-1474 "transaction rejected")
+pub const POLICY_REJECTED: i64 = -32003;
+
+/// Request id, echoed back in the reply.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, expecting = "id must be a string, a number or null")]
+pub enum Id {
+    Number(Number),
+    String(String),
+    #[default]
+    Null,
+}
+
+impl Id {
+    /// Id of a request that could not be parsed, `null` if it has none or an invalid one.
+    pub fn of(request: &Value) -> Self {
+        request
+            .get("id")
+            .and_then(|id| Id::deserialize(id).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Keeps `"id": null` apart from a missing id, which makes the request a notification.
+fn deserialize_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Id>, D::Error> {
+    Id::deserialize(deserializer).map(Some)
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct JsonRpcRequest<T> {
     pub jsonrpc: String,
     pub method: String,
-    pub id: u64,
+    /// `None` for notifications, which get no reply
+    #[serde(default, deserialize_with = "deserialize_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<Id>,
     pub params: Option<T>,
 }
 
+/// By-position (`[...]`) or by-name (`{...}`) request params.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged, expecting = "params must be an array or an object")]
+pub enum Params {
+    Positional(Vec<Value>),
+    Named(Map<String, Value>),
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params::Positional(Vec::new())
+    }
+}
+
+impl Params {
+    pub fn get(&self, position: usize, name: &str) -> Option<&Value> {
+        match self {
+            Params::Positional(params) => params.get(position),
+            Params::Named(params) => params.get(name),
+        }
+    }
+
+    /// Deserializes the param at `position`, or called `name` when params are given by name.
+    pub fn parse<T: DeserializeOwned>(&self, position: usize, name: &str) -> Result<T, JsonRpcError> {
+        let param = self
+            .get(position, name)
+            .ok_or_else(|| JsonRpcError::invalid_params(format!("missing {}", name)))?;
+
+        T::deserialize(param).map_err(|err| JsonRpcError::invalid_params(format!("{}: {}", name, err)))
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct JsonRpcReply<T> {
-    pub id: u64,
+    /// `null` when the id of the request could not be read
+    pub id: Id,
     pub jsonrpc: String,
     #[serde(flatten)]
     pub result: JsonRpcResult<T>,
@@ -21,7 +99,130 @@ pub struct JsonRpcReply<T> {
 #[serde(rename_all = "camelCase")]
 pub enum JsonRpcResult<T> {
     Result(T),
-    Error { code: i64, message: String },
+    Error {
+        code: i64,
+        message: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        data: Option<Value>,
+    },
+}
+
+impl<T> JsonRpcReply<T> {
+    pub fn error(id: Id, jsonrpc: String, err: JsonRpcError) -> Self {
+        Self {
+            id,
+            jsonrpc,
+            result: JsonRpcResult::Error {
+                code: err.code,
+                message: err.message,
+                data: err.data,
+            },
+        }
+    }
+}
+
+/// An error to report in the `error` member of a reply.
+#[derive(Debug)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl JsonRpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    pub fn invalid_params(message: impl std::fmt::Display) -> Self {
+        Self::new(INVALID_PARAMS, format!("invalid params: {}", message))
+    }
+}
+
+impl std::fmt::Display for JsonRpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for JsonRpcError {}
+
+/// Errors that are not already a [`JsonRpcError`] are reported as access or signer failures.
+impl From<anyhow::Error> for JsonRpcError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<JsonRpcError>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+
+        match err.downcast::<AuthError>() {
+            Ok(err) => err.into(),
+            Err(err) => Self::new(SIGNER_ERROR, err.to_string()),
+        }
+    }
+}
+
+impl From<AuthError> for JsonRpcError {
+    fn from(err: AuthError) -> Self {
+        let code = match err {
+            AuthError::Unauthorized(_) => UNAUTHORIZED,
+            AuthError::Forbidden(_) => FORBIDDEN,
+        };
+
+        Self::new(code, err.to_string())
+    }
+}
+
+pub fn error_reply(id: Id, code: i64, message: String) -> JsonRpcReply<Value> {
+    JsonRpcReply::error(id, JSONRPC_VERSION.to_string(), JsonRpcError::new(code, message))
+}
+
+/// Parses a request body, holding either a single request or a batch, or builds the error reply.
+pub fn parse_body(body: &[u8]) -> Result<Value, JsonRpcReply<Value>> {
+    serde_json::from_slice(body).map_err(|err| error_reply(Id::Null, PARSE_ERROR, format!("parse error: {}", err)))
+}
+
+/// Splits a parsed body into its requests, rejecting empty batches and batches over `max_batch_size`.
+///
+/// The flag tells whether the body was a batch, whose replies must be sent back as an array.
+pub fn split_batch(body: Value, max_batch_size: usize) -> Result<(Vec<Value>, bool), JsonRpcReply<Value>> {
+    match body {
+        Value::Array(requests) if requests.is_empty() => {
+            Err(error_reply(Id::Null, INVALID_REQUEST, "invalid request: empty batch".to_string()))
+        }
+        Value::Array(requests) if requests.len() > max_batch_size => Err(error_reply(
+            Id::Null,
+            INVALID_REQUEST,
+            format!(
+                "invalid request: batch of {} requests exceeds the maximum of {}",
+                requests.len(),
+                max_batch_size
+            ),
+        )),
+        Value::Array(requests) => Ok((requests, true)),
+        request => Ok((vec![request], false)),
+    }
+}
+
+/// Parses a single request, or builds the error reply for a malformed one.
+pub fn parse_request<T: DeserializeOwned>(value: Value) -> Result<JsonRpcRequest<T>, JsonRpcReply<Value>> {
+    let id = Id::of(&value);
+
+    let request: JsonRpcRequest<T> = serde_json::from_value(value)
+        .map_err(|err| error_reply(id.clone(), INVALID_REQUEST, format!("invalid request: {}", err)))?;
+    if request.jsonrpc != JSONRPC_VERSION {
+        return Err(error_reply(
+            id,
+            INVALID_REQUEST,
+            format!("invalid request: unsupported jsonrpc version {:?}", request.jsonrpc),
+        ));
+    }
+
+    Ok(request)
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
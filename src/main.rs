@@ -1,11 +1,20 @@
 mod app_types;
+mod auth;
 mod cli;
+mod config;
 mod jsonrpc;
+mod policy;
 mod shutdown_signal;
 mod signers;
+mod tls;
 
 use cli::{Command, Opt};
-use signers::{aws_kms::handle_aws_kms, yubihsm::handle_yubihsm};
+use config::handle_config;
+use signers::{
+    aws_kms::handle_aws_kms, azure_key_vault::handle_azure_key_vault, common::handle_serve,
+    gcp_kms::handle_gcp_kms, keystore::handle_keystore, pkcs11::handle_pkcs11, vault::handle_vault,
+    yubihsm::handle_yubihsm,
+};
 use structopt::StructOpt;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -22,12 +31,20 @@ async fn main() {
 
     let opt = Opt::from_args();
 
-    match opt.cmd {
-        Command::Yubihsm(yubi_opt) => {
-            handle_yubihsm(yubi_opt).await;
-        }
-        Command::AwsKms(aws_opt) => {
-            handle_aws_kms(aws_opt).await;
-        }
+    let result = match opt.cmd {
+        Command::Yubihsm(yubi_opt) => handle_yubihsm(yubi_opt).await,
+        Command::AwsKms(aws_opt) => handle_aws_kms(aws_opt).await,
+        Command::Keystore(keystore_opt) => handle_keystore(keystore_opt).await,
+        Command::Pkcs11(pkcs11_opt) => handle_pkcs11(pkcs11_opt).await,
+        Command::Vault(vault_opt) => handle_vault(vault_opt).await,
+        Command::GcpKms(gcp_opt) => handle_gcp_kms(gcp_opt).await,
+        Command::AzureKeyVault(azure_opt) => handle_azure_key_vault(azure_opt).await,
+        Command::Serve(serve_opt) => handle_serve(serve_opt).await,
+        Command::Config(config_opt) => handle_config(config_opt),
+    };
+
+    if let Err(err) = result {
+        eprintln!("Error: {:#}", err);
+        std::process::exit(1);
     }
 }
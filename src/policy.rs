@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+
+use alloy::{
+    primitives::{Address, Selector, U256},
+    rpc::types::TransactionRequest,
+};
+use anyhow::{bail, Result as AnyhowResult};
+use serde::{Deserialize, Deserializer};
+use serde_json::json;
+
+use crate::{
+    jsonrpc::{JsonRpcError, POLICY_REJECTED},
+    signers::common::{KeyRef, METHODS},
+};
+
+/// Transaction policies by key; keys without a policy sign any transaction.
+pub type Policies = HashMap<KeyRef, TxPolicy>;
+
+/// Rules a transaction must satisfy before `eth_signTransaction` signs it.
+///
+/// Every rule is optional, a transaction is signed only if it passes all the configured ones. A key with a policy
+/// serves no other signing method unless it is listed in `allowed_methods`, since messages, typed data and block
+/// payloads are not checked against these rules.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TxPolicy {
+    /// Recipients the key may send to
+    pub allowed_to: Option<Vec<Address>>,
+    /// Chain ids the key may sign for; transactions without a chain id are rejected
+    pub chain_ids: Option<Vec<u64>>,
+    /// Maximum value in wei
+    #[serde(default, deserialize_with = "deserialize_wei")]
+    pub max_value: Option<U256>,
+    /// Maximum gas limit
+    pub max_gas: Option<u64>,
+    /// Maximum `maxFeePerGas`, or `gasPrice` for legacy transactions, in wei
+    #[serde(default, deserialize_with = "deserialize_wei")]
+    pub max_fee_per_gas: Option<U256>,
+    /// 4-byte function selectors the calldata must start with
+    pub allowed_selectors: Option<Vec<Selector>>,
+    #[serde(default)]
+    pub deny_contract_creation: bool,
+    /// EIP-7702 authorization lists, which delegate the account of the key to contract code, are rejected unless set
+    #[serde(default)]
+    pub allow_authorization_list: bool,
+    /// EIP-4844 blob transactions are rejected unless set
+    #[serde(default)]
+    pub allow_blobs: bool,
+    /// Signing methods served besides `eth_signTransaction`, which sign without any of the rules above
+    pub allowed_methods: Option<Vec<String>>,
+}
+
+/// Methods that sign nothing, served whatever the policy.
+const UNSIGNED_METHODS: &[&str] = &["eth_accounts", "health_status"];
+
+/// Reads a wei amount given as an integer, or as a decimal or `0x` hex string for values beyond 64 bits.
+fn deserialize_wei<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<U256>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Wei {
+        Integer(u64),
+        String(String),
+    }
+
+    match Wei::deserialize(deserializer)? {
+        Wei::Integer(wei) => Ok(Some(U256::from(wei))),
+        Wei::String(wei) => wei
+            .parse()
+            .map(Some)
+            .map_err(|_| serde::de::Error::custom(format!("invalid wei amount {:?}", wei))),
+    }
+}
+
+/// A transaction rejected by a [`TxPolicy`] rule.
+#[derive(Debug)]
+pub struct PolicyViolation {
+    /// Name of the rule, as written in the configuration
+    pub rule: &'static str,
+    pub message: String,
+}
+
+impl PolicyViolation {
+    fn new(rule: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            rule,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "transaction rejected by policy rule {}: {}", self.rule, self.message)
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
+
+/// Reported with the rule in `data`, so clients can tell rejections apart without parsing the message.
+impl From<PolicyViolation> for JsonRpcError {
+    fn from(violation: PolicyViolation) -> Self {
+        JsonRpcError {
+            code: POLICY_REJECTED,
+            message: violation.to_string(),
+            data: Some(json!({ "rule": violation.rule })),
+        }
+    }
+}
+
+impl TxPolicy {
+    pub fn validate(&self) -> AnyhowResult<()> {
+        let empty = [
+            ("allowed_to", self.allowed_to.as_ref().is_some_and(Vec::is_empty)),
+            ("chain_ids", self.chain_ids.as_ref().is_some_and(Vec::is_empty)),
+            ("allowed_selectors", self.allowed_selectors.as_ref().is_some_and(Vec::is_empty)),
+            ("allowed_methods", self.allowed_methods.as_ref().is_some_and(Vec::is_empty)),
+        ];
+
+        for (rule, is_empty) in empty {
+            if is_empty {
+                bail!("{} is empty; omit it to allow any value", rule);
+            }
+        }
+
+        for method in self.allowed_methods.iter().flatten() {
+            if !METHODS.contains(&method.as_str()) {
+                bail!("allowed_methods: unknown method {} (expected one of {})", method, METHODS.join(", "));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that a key with this policy may serve `method`.
+    pub fn check_method(&self, method: &str) -> Result<(), PolicyViolation> {
+        let allowed = method == "eth_signTransaction"
+            || UNSIGNED_METHODS.contains(&method)
+            || self.allowed_methods.iter().flatten().any(|allowed| allowed == method);
+        if !allowed {
+            return Err(PolicyViolation::new(
+                "allowed_methods",
+                format!("{} is not allowed for a key with a transaction policy", method),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks `tx` against every configured rule, returning the first one it breaks.
+    pub fn check(&self, tx: &TransactionRequest) -> Result<(), PolicyViolation> {
+        let to = tx.to.and_then(|kind| kind.to().copied());
+
+        if tx.authorization_list.is_some() && !self.allow_authorization_list {
+            return Err(PolicyViolation::new(
+                "allow_authorization_list",
+                "EIP-7702 authorization lists are not allowed",
+            ));
+        }
+
+        let has_blobs =
+            tx.blob_versioned_hashes.is_some() || tx.max_fee_per_blob_gas.is_some() || tx.sidecar.is_some();
+        if has_blobs && !self.allow_blobs {
+            return Err(PolicyViolation::new("allow_blobs", "blob transactions are not allowed"));
+        }
+
+        if to.is_none() && self.deny_contract_creation {
+            return Err(PolicyViolation::new(
+                "deny_contract_creation",
+                "contract creation is not allowed",
+            ));
+        }
+
+        if let Some(allowed_to) = &self.allowed_to {
+            match to {
+                Some(to) if allowed_to.contains(&to) => {}
+                Some(to) => {
+                    return Err(PolicyViolation::new(
+                        "allowed_to",
+                        format!("{} is not an allowed recipient", to),
+                    ))
+                }
+                None => {
+                    return Err(PolicyViolation::new(
+                        "allowed_to",
+                        "contract creation has no allowed recipient",
+                    ))
+                }
+            }
+        }
+
+        if let Some(chain_ids) = &self.chain_ids {
+            match tx.chain_id {
+                Some(chain_id) if chain_ids.contains(&chain_id) => {}
+                Some(chain_id) => {
+                    return Err(PolicyViolation::new(
+                        "chain_ids",
+                        format!("chain id {} is not allowed", chain_id),
+                    ))
+                }
+                None => return Err(PolicyViolation::new("chain_ids", "transaction has no chain id")),
+            }
+        }
+
+        if let Some(max_value) = self.max_value {
+            let value = tx.value.unwrap_or_default();
+            if value > max_value {
+                return Err(PolicyViolation::new(
+                    "max_value",
+                    format!("value {} exceeds {}", value, max_value),
+                ));
+            }
+        }
+
+        if let Some(max_gas) = self.max_gas {
+            let gas = tx
+                .gas
+                .ok_or_else(|| PolicyViolation::new("max_gas", "transaction has no gas limit"))?;
+            if gas > max_gas {
+                return Err(PolicyViolation::new(
+                    "max_gas",
+                    format!("gas limit {} exceeds {}", gas, max_gas),
+                ));
+            }
+        }
+
+        if let Some(max_fee_per_gas) = self.max_fee_per_gas {
+            let fee = tx.max_fee_per_gas.or(tx.gas_price).ok_or_else(|| {
+                PolicyViolation::new("max_fee_per_gas", "transaction has no fee per gas")
+            })?;
+            if U256::from(fee) > max_fee_per_gas {
+                return Err(PolicyViolation::new(
+                    "max_fee_per_gas",
+                    format!("fee per gas {} exceeds {}", fee, max_fee_per_gas),
+                ));
+            }
+        }
+
+        if let Some(allowed_selectors) = &self.allowed_selectors {
+            let input = tx.input.input().map(|input| input.as_ref()).unwrap_or_default();
+            let selector = input
+                .get(..4)
+                .map(Selector::from_slice)
+                .ok_or_else(|| PolicyViolation::new("allowed_selectors", "calldata has no function selector"))?;
+            if !allowed_selectors.contains(&selector) {
+                return Err(PolicyViolation::new(
+                    "allowed_selectors",
+                    format!("function selector {} is not allowed", selector),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        eips::eip7702::SignedAuthorization,
+        primitives::{address, b256, bytes, TxKind},
+        rpc::types::TransactionInput,
+    };
+
+    use super::*;
+
+    const TO: Address = address!("ff00000000000000000000000000000000000010");
+
+    fn policy(toml: &str) -> TxPolicy {
+        let policy: TxPolicy = toml::from_str(toml).unwrap();
+        policy.validate().unwrap();
+        policy
+    }
+
+    fn tx() -> TransactionRequest {
+        TransactionRequest {
+            to: Some(TxKind::Call(TO)),
+            chain_id: Some(10),
+            gas: Some(21_000),
+            max_fee_per_gas: Some(1_000_000_000),
+            value: Some(U256::from(1)),
+            input: TransactionInput::new(bytes!("a9059cbb00")),
+            ..Default::default()
+        }
+    }
+
+    fn rule(policy: &TxPolicy, tx: &TransactionRequest) -> Option<&'static str> {
+        policy.check(tx).err().map(|violation| violation.rule)
+    }
+
+    #[test]
+    fn empty_policy_signs_plain_transactions() {
+        let policy = policy("");
+        assert_eq!(rule(&policy, &tx()), None);
+        assert_eq!(rule(&policy, &TransactionRequest::default()), None);
+    }
+
+    #[test]
+    fn allowed_to() {
+        let policy = policy(r#"allowed_to = ["0xff00000000000000000000000000000000000010"]"#);
+        assert_eq!(rule(&policy, &tx()), None);
+
+        let other = TransactionRequest { to: Some(TxKind::Call(Address::ZERO)), ..tx() };
+        assert_eq!(rule(&policy, &other), Some("allowed_to"));
+
+        let create = TransactionRequest { to: Some(TxKind::Create), ..tx() };
+        assert_eq!(rule(&policy, &create), Some("allowed_to"));
+        let no_to = TransactionRequest { to: None, ..tx() };
+        assert_eq!(rule(&policy, &no_to), Some("allowed_to"));
+    }
+
+    #[test]
+    fn deny_contract_creation() {
+        let policy = policy("deny_contract_creation = true");
+        assert_eq!(rule(&policy, &tx()), None);
+
+        let create = TransactionRequest { to: Some(TxKind::Create), ..tx() };
+        assert_eq!(rule(&policy, &create), Some("deny_contract_creation"));
+        let no_to = TransactionRequest { to: None, ..tx() };
+        assert_eq!(rule(&policy, &no_to), Some("deny_contract_creation"));
+    }
+
+    #[test]
+    fn chain_ids() {
+        let policy = policy("chain_ids = [10, 8453]");
+        assert_eq!(rule(&policy, &tx()), None);
+
+        let mainnet = TransactionRequest { chain_id: Some(1), ..tx() };
+        assert_eq!(rule(&policy, &mainnet), Some("chain_ids"));
+        let no_chain_id = TransactionRequest { chain_id: None, ..tx() };
+        assert_eq!(rule(&policy, &no_chain_id), Some("chain_ids"));
+    }
+
+    #[test]
+    fn max_value() {
+        let policy = policy(r#"max_value = "0x10""#);
+        assert_eq!(policy.max_value, Some(U256::from(16)));
+        assert_eq!(rule(&policy, &TransactionRequest { value: Some(U256::from(16)), ..tx() }), None);
+        assert_eq!(rule(&policy, &TransactionRequest { value: None, ..tx() }), None);
+
+        let over = TransactionRequest { value: Some(U256::from(17)), ..tx() };
+        assert_eq!(rule(&policy, &over), Some("max_value"));
+
+        let decimal = self::policy(r#"max_value = "1000000000000000000000""#);
+        assert_eq!(decimal.max_value, Some(U256::from(10).pow(U256::from(21))));
+        assert!(toml::from_str::<TxPolicy>(r#"max_value = "ten""#).is_err());
+    }
+
+    #[test]
+    fn max_gas() {
+        let policy = policy("max_gas = 21000");
+        assert_eq!(rule(&policy, &tx()), None);
+
+        assert_eq!(rule(&policy, &TransactionRequest { gas: Some(21_001), ..tx() }), Some("max_gas"));
+        assert_eq!(rule(&policy, &TransactionRequest { gas: None, ..tx() }), Some("max_gas"));
+    }
+
+    #[test]
+    fn max_fee_per_gas() {
+        let policy = policy("max_fee_per_gas = 1000000000");
+        assert_eq!(rule(&policy, &tx()), None);
+
+        let over = TransactionRequest { max_fee_per_gas: Some(1_000_000_001), ..tx() };
+        assert_eq!(rule(&policy, &over), Some("max_fee_per_gas"));
+
+        let legacy = TransactionRequest { max_fee_per_gas: None, gas_price: Some(2_000_000_000), ..tx() };
+        assert_eq!(rule(&policy, &legacy), Some("max_fee_per_gas"));
+
+        let no_fee = TransactionRequest { max_fee_per_gas: None, ..tx() };
+        assert_eq!(rule(&policy, &no_fee), Some("max_fee_per_gas"));
+    }
+
+    #[test]
+    fn allowed_selectors() {
+        let policy = policy(r#"allowed_selectors = ["0xa9059cbb"]"#);
+        assert_eq!(rule(&policy, &tx()), None);
+
+        let other = TransactionRequest { input: TransactionInput::new(bytes!("095ea7b3")), ..tx() };
+        assert_eq!(rule(&policy, &other), Some("allowed_selectors"));
+
+        let short = TransactionRequest { input: TransactionInput::new(bytes!("a905")), ..tx() };
+        assert_eq!(rule(&policy, &short), Some("allowed_selectors"));
+        let transfer = TransactionRequest { input: TransactionInput::default(), ..tx() };
+        assert_eq!(rule(&policy, &transfer), Some("allowed_selectors"));
+    }
+
+    #[test]
+    fn authorization_list() {
+        let authorization = SignedAuthorization::new_unchecked(
+            alloy::eips::eip7702::Authorization { chain_id: U256::from(10), address: TO, nonce: 0 },
+            0,
+            U256::from(1),
+            U256::from(1),
+        );
+        let delegating = TransactionRequest { authorization_list: Some(vec![authorization]), ..tx() };
+
+        assert_eq!(rule(&policy(""), &delegating), Some("allow_authorization_list"));
+        assert_eq!(rule(&policy("allow_authorization_list = true"), &delegating), None);
+    }
+
+    #[test]
+    fn blobs() {
+        let hashes = TransactionRequest {
+            blob_versioned_hashes: Some(vec![b256!(
+                "0100000000000000000000000000000000000000000000000000000000000000"
+            )]),
+            ..tx()
+        };
+        let blob_fee = TransactionRequest { max_fee_per_blob_gas: Some(1), ..tx() };
+
+        for blob_tx in [&hashes, &blob_fee] {
+            assert_eq!(rule(&policy(""), blob_tx), Some("allow_blobs"));
+            assert_eq!(rule(&policy("allow_blobs = true"), blob_tx), None);
+        }
+    }
+
+    #[test]
+    fn allowed_methods() {
+        let strict = policy("");
+        for method in ["eth_signTransaction", "eth_accounts", "health_status"] {
+            assert!(strict.check_method(method).is_ok(), "{}", method);
+        }
+        for method in ["eth_sign", "personal_sign", "eth_signTypedData_v4", "opsigner_signBlockPayload"] {
+            assert_eq!(strict.check_method(method).unwrap_err().rule, "allowed_methods");
+        }
+
+        let sequencer = policy(r#"allowed_methods = ["opsigner_signBlockPayload"]"#);
+        assert!(sequencer.check_method("opsigner_signBlockPayload").is_ok());
+        assert!(sequencer.check_method("eth_sign").is_err());
+    }
+
+    #[test]
+    fn validate_rejects_empty_lists_and_unknown_methods() {
+        for toml in [
+            "allowed_to = []",
+            "chain_ids = []",
+            "allowed_selectors = []",
+            "allowed_methods = []",
+            r#"allowed_methods = ["eth_sendTransaction"]"#,
+        ] {
+            let policy: TxPolicy = toml::from_str(toml).unwrap();
+            assert!(policy.validate().is_err(), "{}", toml);
+        }
+    }
+
+    #[test]
+    fn violation_reports_the_rule() {
+        let violation = policy("chain_ids = [10]")
+            .check(&TransactionRequest { chain_id: Some(1), ..tx() })
+            .unwrap_err();
+        let err = JsonRpcError::from(violation);
+
+        assert_eq!(err.code, POLICY_REJECTED);
+        assert_eq!(err.message, "transaction rejected by policy rule chain_ids: chain id 1 is not allowed");
+        assert_eq!(err.data, Some(json!({ "rule": "chain_ids" })));
+    }
+}
@@ -1,140 +1,988 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use anyhow::{Result as AnyhowResult};
+use anyhow::{anyhow, bail, Context, Result as AnyhowResult};
 use alloy::{
-    primitives::{Address},
+    primitives::Address,
     signers::{aws::AwsSigner, Signer},
 };
-use aws_config::BehaviorVersion;
-use aws_sdk_kms::Client;
-use axum::http::StatusCode;
-use axum::routing::get;
-use axum::Json;
-use axum::{
-    debug_handler,
-    extract::{Path, State},
-    routing::post,
-    Router,
+use async_trait::async_trait;
+use aws_config::{sts::AssumeRoleProvider, BehaviorVersion, Region, SdkConfig};
+use aws_sdk_kms::{
+    primitives::DateTimeFormat,
+    types::{KeyMetadata, KeySpec, KeyUsageType, Tag},
+    Client,
 };
-use serde_json::Value;
+use serde::Deserialize;
 use structopt::StructOpt;
-use tokio::net::TcpListener;
 use tokio::sync::Mutex;
-use tower_http::{timeout::TimeoutLayer, trace::TraceLayer};
-use tracing::info;
+use tracing::{info, warn};
 
-use crate::jsonrpc::AddressResponse;
 use crate::{
-    app_types::{AppJson, AppResult},
-    jsonrpc::{JsonRpcReply, JsonRpcRequest},
-    shutdown_signal::shutdown_signal,
-    signers::common::{handle_eth_sign_jsonrpc},
+    config::ServerOpt,
+    signers::common::{serve_backend, BackendKind, EthSigner, KeyInfo, KeyRef, SignerBackend},
 };
 
+const DEFAULT_SESSION_NAME: &str = "signer-proxy";
+/// How long `GET /keys` reuses the keys found by tag.
+const LISTED_KEYS_CACHE_SECS: u64 = 300;
 
 #[derive(StructOpt)]
 pub struct AwsOpt {
+    #[structopt(flatten)]
+    pub conn: AwsKmsConfig,
+
     #[structopt(subcommand)] // Note that we mark a field as a subcommand
     pub cmd: AwsCommand,
 }
 
 #[derive(StructOpt)]
 pub enum AwsCommand {
-    Serve,
+    Serve(ServerOpt),
+    /// Creates an ECC_SECG_P256K1 signing key and prints its address
+    CreateKey {
+        /// Key description
+        #[structopt(short, long)]
+        description: Option<String>,
+        /// Tag of the key, as `Key=Value` (can be repeated)
+        #[structopt(short, long = "tag", number_of_values = 1)]
+        tags: Vec<String>,
+        /// Alias of the key, e.g. `batcher` or `alias/batcher`
+        #[structopt(short, long)]
+        alias: Option<String>,
+    },
+    /// Lists the ECC_SECG_P256K1 keys with their address and aliases
+    ListKeys {
+        /// Only lists the keys with this tag, as `Key=Value` or `Key` for any value
+        #[structopt(short, long)]
+        tag: Option<String>,
+    },
+    /// Prints the metadata, aliases, tags and address of a key
+    Describe {
+        /// Key id, ARN or alias
+        key_id: String,
+    },
+    /// Points an alias to a key
+    Alias {
+        /// Alias name, e.g. `batcher` or `alias/batcher`
+        alias: String,
+        /// Key id or ARN
+        key_id: String,
+        /// Moves an existing alias to the key instead of creating it
+        #[structopt(short, long)]
+        update: bool,
+    },
+}
+
+/// AWS KMS connection settings; anything unset falls back to the default AWS config chain.
+///
+/// The flags are prefixed with `aws-` so that they do not clash with those of the other backends under `serve`.
+/// The role settings are read from `AWS_KMS_*` rather than the `AWS_ROLE_*` variables of EKS web identities,
+/// which name the role of the pod itself.
+#[derive(StructOpt, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AwsKmsConfig {
+    /// AWS region of the keys
+    #[structopt(name = "aws-region", long = "aws-region", env = "AWS_REGION")]
+    pub region: Option<String>,
+
+    /// KMS endpoint, e.g. LocalStack's http://localhost:4566
+    #[structopt(name = "aws-endpoint-url", long = "aws-endpoint-url", env = "AWS_ENDPOINT_URL")]
+    pub endpoint_url: Option<String>,
+
+    /// Profile of the shared AWS config and credentials files
+    #[structopt(name = "aws-profile", long = "aws-profile", env = "AWS_PROFILE")]
+    pub profile: Option<String>,
+
+    /// IAM role to assume, e.g. in the account holding the keys
+    #[structopt(name = "aws-role-arn", long = "aws-role-arn", env = "AWS_KMS_ROLE_ARN")]
+    pub role_arn: Option<String>,
+
+    /// External id required by the trust policy of the role
+    #[structopt(name = "aws-external-id", long = "aws-external-id", env = "AWS_KMS_EXTERNAL_ID", requires = "aws-role-arn")]
+    pub external_id: Option<String>,
+
+    /// Session name of the assumed role [default: signer-proxy]
+    #[structopt(
+        name = "aws-role-session-name",
+        long = "aws-role-session-name",
+        env = "AWS_KMS_ROLE_SESSION_NAME",
+        requires = "aws-role-arn"
+    )]
+    pub role_session_name: Option<String>,
+
+    /// Lists the keys with an alias starting with this prefix (e.g. `alias/signer-`)
+    #[structopt(name = "aws-alias-prefix", long = "aws-alias-prefix", env = "AWS_KMS_ALIAS_PREFIX")]
+    pub alias_prefix: Option<String>,
+
+    /// Lists the keys with this tag, as `Key=Value` or `Key` for any value
+    #[structopt(name = "aws-tag", long = "aws-tag", env = "AWS_KMS_TAG")]
+    pub tag: Option<String>,
+
+    /// Other accounts or regions, whose keys are served as `{account}:{key_id}`
+    #[structopt(skip)]
+    #[serde(default)]
+    pub accounts: BTreeMap<String, AwsKmsConfig>,
+}
+
+impl AwsKmsConfig {
+    pub fn validate(&self) -> AnyhowResult<()> {
+        if self.role_arn.is_none() && (self.external_id.is_some() || self.role_session_name.is_some()) {
+            bail!("external_id and role_session_name require role_arn");
+        }
+
+        for (name, account) in &self.accounts {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                bail!("invalid account name {:?}", name);
+            }
+            if name == "arn" {
+                bail!("the account name \"arn\" is reserved for key ARNs");
+            }
+            if !account.accounts.is_empty() {
+                bail!("account {:?}: accounts cannot be nested", name);
+            }
+            account
+                .validate()
+                .with_context(|| format!("invalid account {:?}", name))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits `{account}:{key_id}` into the account name and the key id of that account.
+///
+/// Key ARNs, which contain colons, belong to the default account.
+pub fn split_account(key_id: &str) -> (&str, &str) {
+    if key_id.starts_with("arn:") {
+        return ("", key_id);
+    }
+
+    key_id.split_once(':').unwrap_or(("", key_id))
+}
+
+/// Whether `kms_key_id` names a key by alias or ARN rather than by its key id.
+fn is_alias_or_arn(kms_key_id: &str) -> bool {
+    kms_key_id.starts_with("alias/") || kms_key_id.starts_with("arn:")
+}
+
+/// Adds the `alias/` prefix that KMS requires to alias names.
+fn alias_name(alias: String) -> String {
+    match alias.starts_with("alias/") {
+        true => alias,
+        false => format!("alias/{}", alias),
+    }
+}
+
+/// Splits a `Key=Value` tag, a bare `Key` having no value.
+fn split_tag(tag: &str) -> (&str, Option<&str>) {
+    match tag.split_once('=') {
+        Some((tag_key, tag_value)) => (tag_key, Some(tag_value)),
+        None => (tag, None),
+    }
 }
 
-#[derive(Clone)]
-struct AppState {
+/// A KMS client with the keys it lists.
+pub struct AwsAccount {
     client: Client,
-    signers: Arc<Mutex<HashMap<String, Arc<AwsSigner>>>>,
+    alias_prefix: Option<String>,
+    tag: Option<String>,
+    /// Keys found with `tag`, and when they were listed
+    listed: Mutex<Option<(Instant, Vec<String>)>>,
+}
+
+pub struct AwsBackend {
+    /// By account name, `""` being the default account
+    accounts: BTreeMap<String, AwsAccount>,
+    /// Aliases and ARNs already looked up, with their canonical key id and key ARN
+    names: Mutex<HashMap<String, (String, String)>>,
+    /// By canonical key id, `{account}:{key id}` or the key id for the default account
+    signers: Mutex<HashMap<String, Arc<AwsSigner>>>,
 }
 
-const API_TIMEOUT_SECS: u64 = 30;
+impl AwsAccount {
+    /// `alias_prefix` and `tag` select the keys listed besides the cached ones; without either, only the cached
+    /// ones are listed.
+    pub fn new(client: Client, alias_prefix: Option<String>, tag: Option<String>) -> Self {
+        Self {
+            client,
+            alias_prefix: alias_prefix.map(alias_name),
+            tag,
+            listed: Mutex::new(None),
+        }
+    }
+
+    /// The aliases of the account, or of `key_id` only, as (alias name, target key id).
+    async fn aliases(&self, key_id: Option<&str>) -> AnyhowResult<Vec<(String, String)>> {
+        let mut aliases = Vec::new();
+        let mut marker = None;
 
-#[debug_handler]
-async fn handle_ping() -> &'static str {
-    "pong"
+        loop {
+            let page = self
+                .client
+                .list_aliases()
+                .set_key_id(key_id.map(str::to_string))
+                .set_marker(marker)
+                .send()
+                .await
+                .context("failed to list the KMS aliases")?;
+
+            for alias in page.aliases() {
+                if let (Some(name), Some(target_key_id)) = (alias.alias_name(), alias.target_key_id()) {
+                    aliases.push((name.to_string(), target_key_id.to_string()));
+                }
+            }
+
+            marker = page.next_marker().map(str::to_string);
+            if marker.is_none() {
+                return Ok(aliases);
+            }
+        }
+    }
+
+    /// Ids of all the keys of the account.
+    async fn key_ids(&self) -> AnyhowResult<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut marker = None;
+
+        loop {
+            let page = self
+                .client
+                .list_keys()
+                .set_marker(marker)
+                .send()
+                .await
+                .context("failed to list the KMS keys")?;
+
+            keys.extend(page.keys().iter().filter_map(|key| key.key_id()).map(str::to_string));
+
+            marker = page.next_marker().map(str::to_string);
+            if marker.is_none() {
+                return Ok(keys);
+            }
+        }
+    }
+
+    /// Whether the key is tagged with `tag`, as `Key=Value` or `Key` for any value.
+    async fn has_tag(&self, key_id: &str, tag: &str) -> bool {
+        let (tag_key, tag_value) = split_tag(tag);
+
+        match self.client.list_resource_tags().key_id(key_id).send().await {
+            Ok(tags) => tags.tags().iter().any(|tag| {
+                tag.tag_key() == tag_key && tag_value.is_none_or(|value| tag.tag_value() == value)
+            }),
+            Err(err) => {
+                warn!("failed to list the tags of KMS key {}: {}", key_id, err);
+                false
+            }
+        }
+    }
+
+    /// Target key ids of the aliases starting with `prefix`, with the alias they were found by.
+    async fn find_aliased_keys(&self, prefix: &str) -> AnyhowResult<Vec<(String, String)>> {
+        Ok(self
+            .aliases(None)
+            .await?
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, key_id)| (key_id, name))
+            .collect())
+    }
+
+    /// Ids of the keys tagged with `tag`.
+    async fn find_tagged_keys(&self, tag: &str) -> AnyhowResult<Vec<String>> {
+        let mut keys = Vec::new();
+        for key_id in self.key_ids().await? {
+            if self.has_tag(&key_id, tag).await {
+                keys.push(key_id);
+            }
+        }
+
+        Ok(keys)
+    }
+
+    /// Ids of the keys tagged with the configured tag, none without a tag. Looked up again once the previous
+    /// lookup is `LISTED_KEYS_CACHE_SECS` old, as it takes a request per key of the account.
+    async fn listed_keys(&self) -> AnyhowResult<Vec<String>> {
+        let Some(tag) = &self.tag else {
+            return Ok(Vec::new());
+        };
+
+        let mut listed = self.listed.lock().await;
+        if let Some((listed_at, keys)) = listed.as_ref()
+            && listed_at.elapsed() < Duration::from_secs(LISTED_KEYS_CACHE_SECS)
+        {
+            return Ok(keys.clone());
+        }
+
+        let keys = self.find_tagged_keys(tag).await?;
+        *listed = Some((Instant::now(), keys.clone()));
+        Ok(keys)
+    }
+
+    async fn key_metadata(&self, key_id: &str) -> AnyhowResult<KeyMetadata> {
+        self.client
+            .describe_key()
+            .key_id(key_id)
+            .send()
+            .await
+            .with_context(|| format!("failed to describe KMS key {}", key_id))?
+            .key_metadata()
+            .cloned()
+            .ok_or_else(|| anyhow!("no metadata returned for KMS key {}", key_id))
+    }
+
+    /// Address of the key, derived from its public key.
+    async fn key_address(&self, key_id: &str) -> AnyhowResult<Address> {
+        let signer = AwsSigner::new(self.client.clone(), key_id.to_string(), None)
+            .await
+            .with_context(|| format!("failed to get the public key of KMS key {}", key_id))?;
+        Ok(signer.address())
+    }
 }
 
-#[debug_handler]
-async fn handle_request(
-    Path(key_id): Path<String>,
-    State(state): State<Arc<AppState>>,
-    AppJson(payload): AppJson<JsonRpcRequest<Vec<Value>>>,
-) -> AppResult<JsonRpcReply<Value>> {
-    let signer = get_signer(state.clone(), key_id).await?;
-    handle_eth_sign_jsonrpc(payload, signer).await
+impl AwsBackend {
+    pub fn new(accounts: BTreeMap<String, AwsAccount>) -> Self {
+        Self {
+            accounts,
+            names: Mutex::new(HashMap::new()),
+            signers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn account(&self, name: &str) -> AnyhowResult<&AwsAccount> {
+        self.accounts
+            .get(name)
+            .ok_or_else(|| anyhow!("unknown AWS account {:?}", name))
+    }
+
+    /// Resolves `key_id` to its canonical key id and the KMS key id to sign with.
+    ///
+    /// Aliases and ARNs are looked up once, so an alias moved to another key afterwards keeps
+    /// pointing to the key it was first resolved to until the proxy restarts.
+    async fn resolve(&self, key_id: &str) -> AnyhowResult<(String, String)> {
+        let (account_name, kms_key_id) = split_account(key_id);
+        let account = self.account(account_name)?;
+
+        if !is_alias_or_arn(kms_key_id) {
+            return Ok((key_id.to_string(), kms_key_id.to_string()));
+        }
+
+        let mut names = self.names.lock().await;
+        if let Some(resolved) = names.get(key_id) {
+            return Ok(resolved.clone());
+        }
+
+        let metadata = account.key_metadata(kms_key_id).await?;
+        let canonical = match account_name.is_empty() {
+            true => metadata.key_id().to_string(),
+            false => format!("{}:{}", account_name, metadata.key_id()),
+        };
+        let arn = metadata
+            .arn()
+            .ok_or_else(|| anyhow!("no ARN returned for KMS key {}", kms_key_id))?
+            .to_string();
+
+        names.insert(key_id.to_string(), (canonical.clone(), arn.clone()));
+        Ok((canonical, arn))
+    }
+
+    async fn get_signer(&self, key_id: &str) -> AnyhowResult<Arc<AwsSigner>> {
+        let (canonical, kms_key_id) = self.resolve(key_id).await?;
+        let mut signers = self.signers.lock().await;
+
+        if let Some(signer) = signers.get(&canonical) {
+            return Ok(signer.clone());
+        }
+
+        let client = self.account(split_account(key_id).0)?.client.clone();
+        let signer = Arc::new(AwsSigner::new(client, kms_key_id, None).await?);
+        signers.insert(canonical, signer.clone());
+        Ok(signer)
+    }
 }
 
-async fn get_signer(state: Arc<AppState>, key_id: String) -> AnyhowResult<Arc<AwsSigner>> {
-    let mut signers = state.signers.lock().await;
+#[async_trait]
+impl SignerBackend for AwsBackend {
+    fn key_ref(&self, key_id: &str) -> AnyhowResult<KeyRef> {
+        self.account(split_account(key_id).0)?;
+        Ok(KeyRef {
+            backend: BackendKind::AwsKms,
+            key_id: key_id.to_string(),
+        })
+    }
+
+    async fn resolve_key_ref(&self, key_id: &str) -> AnyhowResult<KeyRef> {
+        Ok(KeyRef {
+            backend: BackendKind::AwsKms,
+            key_id: self.resolve(key_id).await?.0,
+        })
+    }
 
-    if let Some(signer) = signers.get(&key_id) {
-        return Ok(signer.clone());
+    async fn signer(&self, key_id: &str) -> AnyhowResult<Arc<dyn EthSigner>> {
+        Ok(self.get_signer(key_id).await?)
     }
 
-    let signer = Arc::new(AwsSigner::new(state.client.clone(), key_id.clone(), None).await?);
-    signers.insert(key_id.clone(), signer.clone());
-    Ok(signer)
+    /// The keys matching `alias_prefix` or `tag` in each account, along with the cached ones.
+    async fn list_keys(&self) -> AnyhowResult<Vec<KeyInfo>> {
+        let mut labels: BTreeMap<String, Option<String>> =
+            self.signers.lock().await.keys().map(|key_id| (key_id.clone(), None)).collect();
+
+        for (name, account) in &self.accounts {
+            let key_id = |kms_key_id: String| match name.is_empty() {
+                true => kms_key_id,
+                false => format!("{}:{}", name, kms_key_id),
+            };
+
+            if let Some(prefix) = &account.alias_prefix {
+                for (kms_key_id, alias) in account.find_aliased_keys(prefix).await? {
+                    labels.insert(key_id(kms_key_id), Some(alias));
+                }
+            }
+            for kms_key_id in account.listed_keys().await? {
+                labels.entry(key_id(kms_key_id)).or_default();
+            }
+        }
+
+        let mut keys = Vec::with_capacity(labels.len());
+        for (key_id, label) in labels {
+            match self.get_signer(&key_id).await {
+                Ok(signer) => keys.push(KeyInfo {
+                    address: signer.address(),
+                    key_id,
+                    label,
+                }),
+                Err(err) => warn!("skipping KMS key {}: {:#}", key_id, err),
+            }
+        }
+
+        Ok(keys)
+    }
 }
 
-#[debug_handler]
-async fn handle_address_request(
-    Path(key_id): Path<String>,
-    State(state): State<Arc<AppState>>,
-) -> Result<Json<AddressResponse>, StatusCode> {
-    match get_address(state.clone(), key_id).await {
-        Ok(address) => Ok(Json(AddressResponse {
-            address: address.to_string(),
-        })),
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+async fn create_key(
+    account: &AwsAccount,
+    description: Option<String>,
+    tags: &[String],
+    alias: Option<String>,
+) -> AnyhowResult<()> {
+    let tags = tags
+        .iter()
+        .map(|tag| {
+            let (tag_key, tag_value) = split_tag(tag);
+            Tag::builder()
+                .tag_key(tag_key)
+                .tag_value(tag_value.unwrap_or_default())
+                .build()
+                .with_context(|| format!("invalid tag {:?}", tag))
+        })
+        .collect::<AnyhowResult<Vec<_>>>()?;
+
+    let output = account
+        .client
+        .create_key()
+        .key_spec(KeySpec::EccSecgP256K1)
+        .key_usage(KeyUsageType::SignVerify)
+        .set_description(description)
+        .set_tags((!tags.is_empty()).then_some(tags))
+        .send()
+        .await
+        .context("failed to create the KMS key")?;
+    let metadata = output
+        .key_metadata()
+        .ok_or_else(|| anyhow!("no metadata returned for the new KMS key"))?;
+    let key_id = metadata.key_id();
+
+    println!("Key ID: {}", key_id);
+    println!("ARN: {}", metadata.arn().unwrap_or_default());
+
+    if let Some(alias) = alias {
+        let alias = alias_name(alias);
+        account
+            .client
+            .create_alias()
+            .alias_name(&alias)
+            .target_key_id(key_id)
+            .send()
+            .await
+            .with_context(|| format!("failed to create alias {}", alias))?;
+        println!("Alias: {}", alias);
+    }
+
+    println!("Address: {}", account.key_address(key_id).await?);
+    Ok(())
+}
+
+/// Prints the secp256k1 keys of the account, optionally those tagged with `tag` only.
+async fn list_keys(account: &AwsAccount, tag: Option<&str>) -> AnyhowResult<()> {
+    let mut aliases: HashMap<String, Vec<String>> = HashMap::new();
+    for (alias, key_id) in account.aliases(None).await? {
+        aliases.entry(key_id).or_default().push(alias);
+    }
+
+    for key_id in account.key_ids().await? {
+        if let Some(tag) = tag
+            && !account.has_tag(&key_id, tag).await
+        {
+            continue;
+        }
+
+        match account.key_metadata(&key_id).await {
+            Ok(metadata) if metadata.key_spec() == Some(&KeySpec::EccSecgP256K1) => {}
+            Ok(_) => continue,
+            Err(err) => {
+                warn!("skipping KMS key {}: {:#}", key_id, err);
+                continue;
+            }
+        }
+
+        match account.key_address(&key_id).await {
+            Ok(address) => println!(
+                "{}  {}  {}",
+                key_id,
+                address,
+                aliases.get(&key_id).map(|aliases| aliases.join(", ")).unwrap_or_default()
+            ),
+            Err(err) => warn!("skipping KMS key {}: {:#}", key_id, err),
+        }
     }
+
+    Ok(())
 }
 
-async fn get_address(state: Arc<AppState>, key_id: String) -> AnyhowResult<Address> {
-    let signer = AwsSigner::new(state.client.clone(), key_id.clone(), None).await?;
+async fn describe_key(account: &AwsAccount, key_id: &str) -> AnyhowResult<()> {
+    let metadata = account.key_metadata(key_id).await?;
+    let key_id = metadata.key_id();
+
+    let aliases: Vec<String> = account
+        .aliases(Some(key_id))
+        .await?
+        .into_iter()
+        .map(|(alias, _)| alias)
+        .collect();
+    let tags: Vec<String> = account
+        .client
+        .list_resource_tags()
+        .key_id(key_id)
+        .send()
+        .await
+        .with_context(|| format!("failed to list the tags of KMS key {}", key_id))?
+        .tags()
+        .iter()
+        .map(|tag| format!("{}={}", tag.tag_key(), tag.tag_value()))
+        .collect();
+    let created = metadata
+        .creation_date()
+        .map(|date| date.fmt(DateTimeFormat::DateTime))
+        .transpose()?;
+
+    println!("Key ID: {}", key_id);
+    println!("ARN: {}", metadata.arn().unwrap_or_default());
+    println!("State: {}", metadata.key_state().map(|state| state.as_str()).unwrap_or_default());
+    println!("Key spec: {}", metadata.key_spec().map(|spec| spec.as_str()).unwrap_or_default());
+    println!("Key usage: {}", metadata.key_usage().map(|usage| usage.as_str()).unwrap_or_default());
+    println!("Created: {}", created.unwrap_or_default());
+    println!("Description: {}", metadata.description().unwrap_or_default());
+    println!("Aliases: {}", aliases.join(", "));
+    println!("Tags: {}", tags.join(", "));
+
+    if metadata.key_spec() == Some(&KeySpec::EccSecgP256K1) {
+        println!("Address: {}", account.key_address(key_id).await?);
+    }
 
-    Ok(signer.address())
+    Ok(())
 }
 
-pub async fn handle_aws_kms(opt: AwsOpt) {
-    let config = aws_config::defaults(BehaviorVersion::latest())
-        .load()
-        .await;
+async fn set_alias(account: &AwsAccount, alias: String, key_id: &str, update: bool) -> AnyhowResult<()> {
+    let alias = alias_name(alias);
 
-    let client = aws_sdk_kms::Client::new(&config);
+    if update {
+        account
+            .client
+            .update_alias()
+            .alias_name(&alias)
+            .target_key_id(key_id)
+            .send()
+            .await
+            .with_context(|| format!("failed to update alias {}", alias))?;
+    } else {
+        account
+            .client
+            .create_alias()
+            .alias_name(&alias)
+            .target_key_id(key_id)
+            .send()
+            .await
+            .with_context(|| format!("failed to create alias {}", alias))?;
+    }
+
+    println!("Alias: {}", alias);
+    println!("Address: {}", account.key_address(&alias).await?);
+    Ok(())
+}
+
+/// Loads the AWS config of an account, assuming `role_arn` if set.
+pub async fn load_aws_config(config: &AwsKmsConfig) -> SdkConfig {
+    let loader = || {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+
+        if let Some(region) = &config.region {
+            loader = loader.region(Region::new(region.clone()));
+        }
+        if let Some(endpoint_url) = &config.endpoint_url {
+            loader = loader.endpoint_url(endpoint_url);
+        }
+        if let Some(profile) = &config.profile {
+            loader = loader.profile_name(profile);
+        }
+
+        loader
+    };
+
+    let aws_config = loader().load().await;
+    let Some(role_arn) = &config.role_arn else {
+        return aws_config;
+    };
+
+    let mut provider = AssumeRoleProvider::builder(role_arn).session_name(
+        config
+            .role_session_name
+            .as_deref()
+            .unwrap_or(DEFAULT_SESSION_NAME),
+    );
+    if let Some(external_id) = &config.external_id {
+        provider = provider.external_id(external_id);
+    }
+    let provider = provider.configure(&aws_config).build().await;
+
+    loader().credentials_provider(provider).load().await
+}
+
+async fn aws_account(config: &AwsKmsConfig) -> AwsAccount {
+    let aws_config = load_aws_config(config).await;
+
+    AwsAccount::new(
+        aws_sdk_kms::Client::new(&aws_config),
+        config.alias_prefix.clone(),
+        config.tag.clone(),
+    )
+}
+
+/// Builds the AWS KMS backend from the default AWS config chain and the overrides in `config`,
+/// with a client for each of the `accounts`.
+pub async fn aws_kms_backend(config: &AwsKmsConfig) -> AwsBackend {
+    let mut accounts = BTreeMap::new();
+    accounts.insert(String::new(), aws_account(config).await);
+
+    for (name, account) in &config.accounts {
+        info!("serving AWS KMS keys of account {} as aws-kms/{}:{{key_id}}", name, name);
+        accounts.insert(name.clone(), aws_account(account).await);
+    }
+
+    AwsBackend::new(accounts)
+}
+
+pub async fn handle_aws_kms(opt: AwsOpt) -> AnyhowResult<()> {
+    opt.conn.validate()?;
 
     match opt.cmd {
-        AwsCommand::Serve => {
-            let shared_state = Arc::new(AppState {
-                client,
-                signers: Arc::new(Mutex::new(HashMap::new())),
-            });
-
-            let app = Router::new()
-                .route("/ping", get(handle_ping))
-                .route("/key/:key_id", post(handle_request))
-                .route("/key/:key_id/address", get(handle_address_request))
-                .with_state(shared_state)
-                .layer((
-                    TraceLayer::new_for_http(),
-                    TimeoutLayer::new(Duration::from_secs(API_TIMEOUT_SECS)),
-                ));
-
-            let listener = TcpListener::bind("0.0.0.0:4000").await.unwrap();
-            info!("listening on {}", listener.local_addr().unwrap());
-            axum::serve(listener, app)
-                .with_graceful_shutdown(shutdown_signal())
-                .await
-                .unwrap();
+        AwsCommand::Serve(server_opt) => {
+            let backend = aws_kms_backend(&opt.conn).await;
+            serve_backend(Arc::new(backend), server_opt).await?;
         }
+        AwsCommand::CreateKey {
+            description,
+            tags,
+            alias,
+        } => create_key(&aws_account(&opt.conn).await, description, &tags, alias).await?,
+        AwsCommand::ListKeys { tag } => list_keys(&aws_account(&opt.conn).await, tag.as_deref()).await?,
+        AwsCommand::Describe { key_id } => describe_key(&aws_account(&opt.conn).await, &key_id).await?,
+        AwsCommand::Alias {
+            alias,
+            key_id,
+            update,
+        } => set_alias(&aws_account(&opt.conn).await, alias, &key_id, update).await?,
     }
+
+    Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as SyncMutex;
+
+    use alloy::{
+        primitives::{b256, keccak256, B256},
+        signers::k256::ecdsa::{self, signature::hazmat::PrehashSigner, SigningKey},
+    };
+    use aws_sdk_kms::config::Credentials;
+    use axum::{body::Bytes, extract::State, http::HeaderMap, http::StatusCode, routing::post, Json, Router};
+    use base64::{prelude::BASE64_STANDARD, Engine};
+    use serde_json::{json, Value};
+    use structopt::StructOpt;
+
+    use super::*;
+    use crate::signers::remote::{public_key_der, serve_stand_in};
+
+    const KEY: B256 = b256!("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318");
+    const KEY_ID: &str = "1234abcd-12ab-34cd-56ef-1234567890ab";
+
+    /// A KMS holding the secp256k1 key `KEY_ID`, aliased `alias/batcher` and tagged `signer-proxy=enabled`.
+    struct KmsStandIn {
+        key: SigningKey,
+        arn: String,
+        /// Operation and signing region of each request
+        requests: SyncMutex<Vec<(String, String)>>,
+    }
+
+    impl KmsStandIn {
+        fn new(account: &str, key: B256) -> Arc<Self> {
+            Arc::new(Self {
+                key: SigningKey::from_slice(key.as_slice()).unwrap(),
+                arn: format!("arn:aws:kms:eu-west-1:{}:key/{}", account, KEY_ID),
+                requests: SyncMutex::new(Vec::new()),
+            })
+        }
+
+        fn address(&self) -> Address {
+            Address::from_public_key(self.key.verifying_key())
+        }
+
+        fn requests(&self) -> Vec<(String, String)> {
+            self.requests.lock().unwrap().clone()
+        }
+
+        /// Whether `key_id` names the key by id, ARN or alias, as KMS accepts all three.
+        fn knows(&self, key_id: &str) -> bool {
+            key_id == KEY_ID || key_id == self.arn || key_id == "alias/batcher"
+        }
+
+        fn respond(&self, operation: &str, body: &Value) -> Option<Value> {
+            let key_id = body["KeyId"].as_str().unwrap_or_default();
+            if !matches!(operation, "ListAliases" | "ListKeys") && !self.knows(key_id) {
+                return None;
+            }
+
+            let response = match operation {
+                "GetPublicKey" => json!({
+                    "KeyId": self.arn,
+                    "KeySpec": "ECC_SECG_P256K1",
+                    "KeyUsage": "SIGN_VERIFY",
+                    "PublicKey": BASE64_STANDARD.encode(public_key_der(self.key.verifying_key())),
+                }),
+                "Sign" => {
+                    assert_eq!(body["MessageType"], "DIGEST");
+                    let hash = BASE64_STANDARD.decode(body["Message"].as_str().unwrap()).unwrap();
+                    let signature: ecdsa::Signature = self.key.sign_prehash(&hash).unwrap();
+                    json!({
+                        "KeyId": self.arn,
+                        "Signature": BASE64_STANDARD.encode(signature.to_der()),
+                        "SigningAlgorithm": "ECDSA_SHA_256",
+                    })
+                }
+                "DescribeKey" => json!({
+                    "KeyMetadata": { "KeyId": KEY_ID, "Arn": self.arn, "KeySpec": "ECC_SECG_P256K1" },
+                }),
+                "ListAliases" => json!({
+                    "Aliases": [{ "AliasName": "alias/batcher", "TargetKeyId": KEY_ID }],
+                    "Truncated": false,
+                }),
+                "ListKeys" => json!({ "Keys": [{ "KeyId": KEY_ID, "KeyArn": self.arn }], "Truncated": false }),
+                "ListResourceTags" => json!({
+                    "Tags": [{ "TagKey": "signer-proxy", "TagValue": "enabled" }],
+                    "Truncated": false,
+                }),
+                _ => panic!("unexpected KMS operation {}", operation),
+            };
+            Some(response)
+        }
 
+        fn router(self: Arc<Self>) -> Router {
+            Router::new()
+                .route(
+                    "/",
+                    post(|State(kms): State<Arc<Self>>, headers: HeaderMap, body: Bytes| async move {
+                        let operation = headers["X-Amz-Target"].to_str().unwrap();
+                        let operation = operation.strip_prefix("TrentService.").unwrap().to_string();
+                        // Credential=AKID/20240101/eu-west-1/kms/aws4_request, ...
+                        let authorization = headers["Authorization"].to_str().unwrap();
+                        let region = authorization.split('/').nth(2).unwrap().to_string();
+                        kms.requests.lock().unwrap().push((operation.clone(), region));
 
+                        let body: Value = serde_json::from_slice(&body).unwrap();
+                        match kms.respond(&operation, &body) {
+                            Some(response) => Ok(Json(response)),
+                            None => Err((
+                                StatusCode::BAD_REQUEST,
+                                Json(json!({ "__type": "NotFoundException", "message": "key not found" })),
+                            )),
+                        }
+                    }),
+                )
+                .with_state(self)
+        }
+    }
+
+    /// An account on the stand-in at `url`, with static credentials rather than those of the environment.
+    fn account(url: &str, alias_prefix: Option<&str>, tag: Option<&str>) -> AwsAccount {
+        let config = aws_sdk_kms::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("eu-west-1"))
+            .endpoint_url(url)
+            .credentials_provider(Credentials::new("test", "test", None, None, "test"))
+            .build();
+
+        AwsAccount::new(Client::from_conf(config), alias_prefix.map(str::to_string), tag.map(str::to_string))
+    }
+
+    async fn stand_in_backend(alias_prefix: Option<&str>, tag: Option<&str>) -> (AwsBackend, Arc<KmsStandIn>) {
+        let kms = KmsStandIn::new("111111111111", KEY);
+        let url = serve_stand_in(kms.clone().router()).await;
+        let accounts = BTreeMap::from([(String::new(), account(&url, alias_prefix, tag))]);
 
+        (AwsBackend::new(accounts), kms)
+    }
+
+    /// Sets the AWS environment variables, which only this test reads.
+    fn set_aws_env(endpoint_url: &str) {
+        let vars = [
+            ("AWS_ACCESS_KEY_ID", "test"),
+            ("AWS_SECRET_ACCESS_KEY", "test"),
+            ("AWS_REGION", "eu-west-1"),
+            ("AWS_ENDPOINT_URL", endpoint_url),
+            ("AWS_KMS_ALIAS_PREFIX", "alias/signer-"),
+            ("AWS_KMS_TAG", "signer-proxy=enabled"),
+        ];
+        for (name, value) in vars {
+            // SAFETY: no other test reads or writes these variables, and the test harness only reads the
+            // environment through std, whose accesses are serialized
+            unsafe { std::env::set_var(name, value) };
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_settings_from_flags_and_env() {
+        let from_env = KmsStandIn::new("111111111111", KEY);
+        let from_flag = KmsStandIn::new("111111111111", KEY);
+        let env_url = serve_stand_in(from_env.clone().router()).await;
+        let flag_url = serve_stand_in(from_flag.clone().router()).await;
+        set_aws_env(&env_url);
 
+        let config = AwsKmsConfig::from_iter_safe(["aws-kms"]).unwrap();
+        assert_eq!(config.region.as_deref(), Some("eu-west-1"));
+        assert_eq!(config.endpoint_url.as_deref(), Some(env_url.as_str()));
+        assert_eq!(config.alias_prefix.as_deref(), Some("alias/signer-"));
+        assert_eq!(config.tag.as_deref(), Some("signer-proxy=enabled"));
 
+        let hash = keccak256("aws-kms");
+        let backend = aws_kms_backend(&config).await;
+        let signature = backend.sign_hash(KEY_ID, &hash).await.unwrap();
+        assert_eq!(signature.recover_address_from_prehash(&hash).unwrap(), from_env.address());
+        assert!(from_env.requests().iter().all(|(_, region)| region == "eu-west-1"));
 
+        // Flags take precedence over the environment
+        let args = ["aws-kms", "--aws-region", "us-east-2", "--aws-endpoint-url", &flag_url, "--aws-tag", "team"];
+        let config = AwsKmsConfig::from_iter_safe(args).unwrap();
+        assert_eq!(config.tag.as_deref(), Some("team"));
+        let backend = aws_kms_backend(&config).await;
+        backend.sign_hash(KEY_ID, &hash).await.unwrap();
 
+        let requests = from_flag.requests();
+        assert_eq!(requests.first().unwrap(), &("GetPublicKey".to_string(), "us-east-2".to_string()));
+        assert!(requests.iter().any(|(operation, _)| operation == "Sign"));
+        assert!(requests.iter().all(|(_, region)| region == "us-east-2"));
+    }
+
+    #[tokio::test]
+    async fn signs_through_kms() {
+        let (backend, kms) = stand_in_backend(None, None).await;
+
+        let hash = keccak256("aws-kms");
+        let signature = backend.sign_hash("alias/batcher", &hash).await.unwrap();
+        assert_eq!(signature.recover_address_from_prehash(&hash).unwrap(), kms.address());
+
+        let signature = backend.sign_message(KEY_ID, b"hello").await.unwrap();
+        assert_eq!(signature.recover_address_from_msg(b"hello").unwrap(), kms.address());
+        assert_eq!(backend.address(&kms.arn).await.unwrap(), kms.address());
+
+        let err = backend.sign_hash("alias/other", &hash).await.err().unwrap();
+        assert!(format!("{:#}", err).contains("NotFoundException"), "{:#}", err);
+    }
+
+    #[tokio::test]
+    async fn lists_the_configured_keys() {
+        // Without alias_prefix nor tag, only the keys already used
+        let (backend, kms) = stand_in_backend(None, None).await;
+        assert!(backend.list_keys().await.unwrap().is_empty());
+        backend.sign_hash("alias/batcher", &keccak256("aws-kms")).await.unwrap();
+        let keys = backend.list_keys().await.unwrap();
+        assert_eq!((keys[0].key_id.as_str(), keys[0].label.as_deref()), (KEY_ID, None));
+        assert!(!kms.requests().iter().any(|(operation, _)| operation == "ListKeys"));
+
+        let (backend, kms) = stand_in_backend(None, Some("signer-proxy=enabled")).await;
+        let keys = backend.list_keys().await.unwrap();
+        assert_eq!((keys.len(), keys[0].key_id.as_str(), keys[0].address), (1, KEY_ID, kms.address()));
+
+        let (backend, _) = stand_in_backend(Some("batcher"), Some("other=tag")).await;
+        let keys = backend.list_keys().await.unwrap();
+        assert_eq!((keys.len(), keys[0].label.as_deref()), (1, Some("alias/batcher")));
+    }
+
+    #[test]
+    fn splits_accounts_and_aliases() {
+        assert_eq!(split_account(KEY_ID), ("", KEY_ID));
+        assert_eq!(split_account("ops:alias/batcher"), ("ops", "alias/batcher"));
+        let arn = "arn:aws:kms:eu-west-1:111111111111:key/1234abcd-12ab-34cd-56ef-1234567890ab";
+        assert_eq!(split_account(arn), ("", arn));
+        assert_eq!(split_account(&format!("ops:{}", arn)), ("ops", arn));
+
+        assert!(is_alias_or_arn("alias/batcher") && is_alias_or_arn(arn) && !is_alias_or_arn(KEY_ID));
+        assert_eq!(alias_name("batcher".to_string()), "alias/batcher");
+        assert_eq!(alias_name("alias/batcher".to_string()), "alias/batcher");
+    }
+
+    #[tokio::test]
+    async fn resolves_aliases_arns_and_key_ids_to_one_key_ref() {
+        let (backend, kms) = stand_in_backend(None, None).await;
+        let key_ref = KeyRef {
+            backend: BackendKind::AwsKms,
+            key_id: KEY_ID.to_string(),
+        };
+
+        for key_id in ["alias/batcher", kms.arn.as_str(), KEY_ID] {
+            assert_eq!(backend.resolve_key_ref(key_id).await.unwrap(), key_ref, "{}", key_id);
+        }
+        // The bare key id needs no lookup, and the others are looked up once
+        let describes = kms.requests().iter().filter(|(operation, _)| operation == "DescribeKey").count();
+        backend.resolve_key_ref("alias/batcher").await.unwrap();
+        assert_eq!(kms.requests().len(), describes);
+        assert_eq!(describes, 2);
+    }
+
+    #[tokio::test]
+    async fn selects_the_account_of_the_key_id() {
+        let default = KmsStandIn::new("111111111111", KEY);
+        let ops = KmsStandIn::new("222222222222", keccak256("ops"));
+        let accounts = BTreeMap::from([
+            (String::new(), account(&serve_stand_in(default.clone().router()).await, None, None)),
+            ("ops".to_string(), account(&serve_stand_in(ops.clone().router()).await, None, None)),
+        ]);
+        let backend = AwsBackend::new(accounts);
+
+        for key_id in ["ops:alias/batcher", &format!("ops:{}", ops.arn), &format!("ops:{}", KEY_ID)] {
+            let key_ref = backend.resolve_key_ref(key_id).await.unwrap();
+            assert_eq!(key_ref.key_id, format!("ops:{}", KEY_ID), "{}", key_id);
+        }
+        assert_eq!(backend.address("ops:alias/batcher").await.unwrap(), ops.address());
+        assert!(default.requests().is_empty());
+
+        assert_eq!(backend.address("alias/batcher").await.unwrap(), default.address());
+        assert_ne!(ops.address(), default.address());
+
+        let err = backend.key_ref("dev:alias/batcher").unwrap_err();
+        assert_eq!(err.to_string(), "unknown AWS account \"dev\"");
+    }
+}
@@ -0,0 +1,505 @@
+use std::{collections::HashMap, env, path::PathBuf, sync::Arc};
+
+use alloy::{primitives::B256, signers::k256::ecdsa};
+use anyhow::{anyhow, bail, Context, Result as AnyhowResult};
+use async_trait::async_trait;
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use structopt::StructOpt;
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+use crate::{
+    config::{read_secret_file, ServerOpt},
+    signers::{
+        cloud::{http_client, send_json, AccessToken, TokenGrant},
+        common::{serve_backend, BackendKind, EthSigner, KeyInfo, KeyRef, SignerBackend},
+        remote::{parse_sec1_public_key, DigestSigner, RemoteSigner},
+    },
+};
+
+const API_VERSION: &str = "7.4";
+const DEFAULT_AUTHORITY_HOST: &str = "https://login.microsoftonline.com";
+const RESOURCE: &str = "https://vault.azure.net";
+const IMDS_TOKEN_URL: &str = "http://169.254.169.254/metadata/identity/oauth2/token?api-version=2018-02-01";
+const CURVE: &str = "P-256K";
+const ALGORITHM: &str = "ES256K";
+const CLIENT_SECRET_ENV: &str = "AZURE_CLIENT_SECRET";
+const ACCESS_TOKEN_ENV: &str = "AZURE_ACCESS_TOKEN";
+
+#[derive(StructOpt)]
+pub struct AzureKeyVaultOpt {
+    #[structopt(flatten)]
+    pub conn: AzureKeyVaultConnOpt,
+
+    #[structopt(subcommand)]
+    pub cmd: AzureKeyVaultCommand,
+}
+
+/// Key Vault settings, shared by the `azure-key-vault` subcommands, `serve` and the config file.
+///
+/// Without a client secret nor `access_token`, tokens come from the managed identity of the VM,
+/// the user-assigned one if `client_id` is set.
+#[derive(StructOpt, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AzureKeyVaultConnOpt {
+    /// URL of the vault (e.g. https://my-vault.vault.azure.net), or of a local stand-in server
+    #[structopt(name = "azure-vault-url", long = "azure-vault-url", env = "AZURE_KEYVAULT_URL")]
+    pub vault_url: Option<String>,
+
+    /// Azure AD tenant of the application
+    #[structopt(name = "azure-tenant-id", long = "azure-tenant-id", env = "AZURE_TENANT_ID")]
+    pub tenant_id: Option<String>,
+
+    /// Client id of the application, or of the user-assigned managed identity
+    #[structopt(name = "azure-client-id", long = "azure-client-id", env = "AZURE_CLIENT_ID")]
+    pub client_id: Option<String>,
+
+    /// File holding the client secret of the application
+    #[structopt(name = "azure-client-secret-file", long = "azure-client-secret-file", parse(from_os_str))]
+    pub client_secret_file: Option<PathBuf>,
+
+    /// Client secret of the application, in the config file only; on the command line it would show up in `ps`,
+    /// use `--azure-client-secret-file` or `AZURE_CLIENT_SECRET` instead
+    #[structopt(skip)]
+    pub client_secret: Option<String>,
+
+    /// Azure AD endpoint [default: https://login.microsoftonline.com]
+    #[structopt(name = "azure-authority-host", long = "azure-authority-host", env = "AZURE_AUTHORITY_HOST")]
+    pub authority_host: Option<String>,
+
+    /// OAuth2 access token (e.g. from `az account get-access-token`), never refreshed, in the config file only;
+    /// on the command line it would show up in `ps`, use `AZURE_ACCESS_TOKEN` instead
+    #[structopt(skip)]
+    pub access_token: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub enum AzureKeyVaultCommand {
+    Serve(ServerOpt),
+}
+
+impl AzureKeyVaultConnOpt {
+    /// Checks the vault URL and credentials, without contacting Azure.
+    pub fn validate(&self) -> AnyhowResult<()> {
+        let Some(vault_url) = &self.vault_url else {
+            bail!("the Key Vault URL is required");
+        };
+        if !vault_url.starts_with("http://") && !vault_url.starts_with("https://") {
+            bail!("the Key Vault URL must start with http:// or https://");
+        }
+
+        let secret = self.client_secret_file.is_some()
+            || self.client_secret.is_some()
+            || env::var_os(CLIENT_SECRET_ENV).is_some();
+        if secret && (self.tenant_id.is_none() || self.client_id.is_none()) {
+            bail!("a client secret requires tenant_id and client_id");
+        }
+
+        Ok(())
+    }
+
+    fn grant(&self) -> AnyhowResult<TokenGrant> {
+        if let Some(token) = self.access_token.clone().or_else(|| env::var(ACCESS_TOKEN_ENV).ok()) {
+            return Ok(TokenGrant::Static(token));
+        }
+
+        let client_secret = match (&self.client_secret_file, &self.client_secret) {
+            (Some(path), _) => Some(read_secret_file(path)?),
+            (None, secret) => secret.clone().or_else(|| env::var(CLIENT_SECRET_ENV).ok()),
+        };
+
+        match (client_secret, &self.tenant_id, &self.client_id) {
+            (Some(client_secret), Some(tenant_id), Some(client_id)) => {
+                let authority = self.authority_host.as_deref().unwrap_or(DEFAULT_AUTHORITY_HOST);
+                Ok(TokenGrant::ClientSecret {
+                    token_url: format!("{}/{}/oauth2/v2.0/token", authority.trim_end_matches('/'), tenant_id),
+                    client_id: client_id.clone(),
+                    client_secret,
+                    scope: format!("{}/.default", RESOURCE),
+                })
+            }
+            (Some(_), _, _) => bail!("a client secret requires tenant_id and client_id"),
+            (None, _, client_id) => {
+                let mut url = format!("{}&resource={}", IMDS_TOKEN_URL, RESOURCE);
+                if let Some(client_id) = client_id {
+                    url = format!("{}&client_id={}", url, client_id);
+                }
+                Ok(TokenGrant::Metadata {
+                    url,
+                    header: ("Metadata", "true"),
+                })
+            }
+        }
+    }
+}
+
+/// Splits a key id into the key name and the optional version (`batcher` or `batcher@{version}`).
+pub fn parse_key_id(key_id: &str) -> AnyhowResult<(&str, Option<&str>)> {
+    let (name, version) = match key_id.split_once('@') {
+        Some((name, version)) => (name, Some(version)),
+        None => (key_id, None),
+    };
+
+    let valid = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !valid(name) || !version.is_none_or(valid) {
+        bail!("invalid Key Vault key name {:?}", key_id);
+    }
+
+    Ok((name, version))
+}
+
+#[derive(Deserialize)]
+struct KeyBundle {
+    key: JsonWebKey,
+}
+
+#[derive(Deserialize)]
+struct JsonWebKey {
+    kid: String,
+    crv: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyList {
+    #[serde(default)]
+    value: Vec<KeyItem>,
+    next_link: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct KeyItem {
+    kid: String,
+}
+
+#[derive(Deserialize)]
+struct KeyOperationResult {
+    value: String,
+}
+
+/// Key Vault REST client.
+struct KeyVaultClient {
+    http: reqwest::Client,
+    vault_url: String,
+    token: AccessToken,
+}
+
+impl KeyVaultClient {
+    async fn get<T: DeserializeOwned>(&self, url: &str) -> AnyhowResult<T> {
+        let request = self
+            .http
+            .get(url)
+            .query(&[("api-version", API_VERSION)])
+            .bearer_auth(self.token.get().await?);
+
+        send_json(request).await
+    }
+}
+
+/// A version of a Key Vault key, signing digests with the `sign` operation.
+struct KeyVaultKeyVersion {
+    client: Arc<KeyVaultClient>,
+    /// `{vault_url}/keys/{name}/{version}`
+    url: String,
+}
+
+#[async_trait]
+impl DigestSigner for KeyVaultKeyVersion {
+    async fn sign_digest(&self, hash: &B256) -> AnyhowResult<ecdsa::Signature> {
+        let request = self
+            .client
+            .http
+            .post(format!("{}/sign", self.url))
+            .query(&[("api-version", API_VERSION)])
+            .bearer_auth(self.client.token.get().await?)
+            .json(&json!({ "alg": ALGORITHM, "value": BASE64_URL_SAFE_NO_PAD.encode(hash) }));
+        let response: KeyOperationResult = send_json(request)
+            .await
+            .with_context(|| format!("failed to sign with {}", self.url))?;
+
+        // Key Vault returns r || s, as in JWS; a DER signature is accepted too
+        let signature = BASE64_URL_SAFE_NO_PAD
+            .decode(response.value.trim_end_matches('='))
+            .map_err(|_| anyhow!("invalid Key Vault signature"))?;
+        match signature.len() {
+            64 => Ok(ecdsa::Signature::from_slice(&signature)?),
+            _ => Ok(ecdsa::Signature::from_der(&signature)?),
+        }
+    }
+}
+
+/// `P-256K` keys of an Azure Key Vault or Managed HSM, by key name.
+///
+/// `batcher` signs with the version current at the time of first use, `batcher@{version}` with
+/// the given version. All versions share the access rules and policy of the key.
+pub struct AzureKeyVaultBackend {
+    client: Arc<KeyVaultClient>,
+    signers: Mutex<HashMap<String, Arc<RemoteSigner<KeyVaultKeyVersion>>>>,
+}
+
+impl AzureKeyVaultBackend {
+    async fn get_signer(&self, key_id: &str) -> AnyhowResult<Arc<RemoteSigner<KeyVaultKeyVersion>>> {
+        let mut signers = self.signers.lock().await;
+
+        if let Some(signer) = signers.get(key_id) {
+            return Ok(signer.clone());
+        }
+
+        let (name, version) = parse_key_id(key_id)?;
+        let url = format!("{}/keys/{}/{}", self.client.vault_url, name, version.unwrap_or_default());
+        let bundle: KeyBundle = self
+            .client
+            .get(&url)
+            .await
+            .with_context(|| format!("failed to get Key Vault key {}", key_id))?;
+
+        let key = bundle.key;
+        if key.crv.as_deref() != Some(CURVE) {
+            bail!("Key Vault key {} is not a {} key", key_id, CURVE);
+        }
+        let (Some(x), Some(y)) = (&key.x, &key.y) else {
+            bail!("Key Vault key {} has no public key", key_id);
+        };
+        let mut point = vec![0x04];
+        for coordinate in [x, y] {
+            point.extend(
+                BASE64_URL_SAFE_NO_PAD
+                    .decode(coordinate.trim_end_matches('='))
+                    .map_err(|_| anyhow!("invalid public key for Key Vault key {}", key_id))?,
+            );
+        }
+        let public_key = parse_sec1_public_key(&point)?;
+
+        // Built from the vault URL rather than the kid, which names the real vault behind a stand-in
+        let version = key.kid.rsplit('/').next().unwrap_or_default();
+        let key = KeyVaultKeyVersion {
+            client: self.client.clone(),
+            url: format!("{}/keys/{}/{}", self.client.vault_url, name, version),
+        };
+        let signer = Arc::new(RemoteSigner::new(key, &public_key));
+        signers.insert(key_id.to_string(), signer.clone());
+        Ok(signer)
+    }
+
+    async fn find_keys(&self) -> AnyhowResult<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut url = format!("{}/keys", self.client.vault_url);
+
+        loop {
+            let page: KeyList = self.client.get(&url).await.context("failed to list the Key Vault keys")?;
+            keys.extend(
+                page.value
+                    .iter()
+                    .filter_map(|key| key.kid.rsplit('/').next().map(str::to_string)),
+            );
+
+            match page.next_link {
+                Some(next_link) => url = next_link,
+                None => break,
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+#[async_trait]
+impl SignerBackend for AzureKeyVaultBackend {
+    fn key_ref(&self, key_id: &str) -> AnyhowResult<KeyRef> {
+        Ok(KeyRef {
+            backend: BackendKind::AzureKeyVault,
+            key_id: parse_key_id(key_id)?.0.to_string(),
+        })
+    }
+
+    async fn signer(&self, key_id: &str) -> AnyhowResult<Arc<dyn EthSigner>> {
+        Ok(self.get_signer(key_id).await?)
+    }
+
+    /// The `P-256K` keys of the vault, at their current version; other keys are skipped.
+    async fn list_keys(&self) -> AnyhowResult<Vec<KeyInfo>> {
+        let mut keys = Vec::new();
+
+        for key_id in self.find_keys().await? {
+            match self.get_signer(&key_id).await {
+                Ok(signer) => keys.push(KeyInfo {
+                    address: alloy::signers::Signer::address(signer.as_ref()),
+                    key_id,
+                    label: None,
+                }),
+                Err(err) => debug!("skipping Key Vault key {}: {:#}", key_id, err),
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+pub fn azure_key_vault_backend(opt: &AzureKeyVaultConnOpt) -> AnyhowResult<AzureKeyVaultBackend> {
+    opt.validate()?;
+    let vault_url = opt
+        .vault_url
+        .as_deref()
+        .unwrap_or_default()
+        .trim_end_matches('/')
+        .to_string();
+
+    let http = http_client()?;
+    let client = KeyVaultClient {
+        token: AccessToken::new(http.clone(), opt.grant()?),
+        http,
+        vault_url,
+    };
+    info!("using Azure Key Vault {}", client.vault_url);
+
+    Ok(AzureKeyVaultBackend {
+        client: Arc::new(client),
+        signers: Mutex::new(HashMap::new()),
+    })
+}
+
+pub async fn handle_azure_key_vault(opt: AzureKeyVaultOpt) -> AnyhowResult<()> {
+    match opt.cmd {
+        AzureKeyVaultCommand::Serve(server_opt) => {
+            let backend = azure_key_vault_backend(&opt.conn)?;
+            serve_backend(Arc::new(backend), server_opt).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        primitives::{keccak256, Address},
+        signers::k256::ecdsa::{signature::hazmat::PrehashSigner, SigningKey},
+    };
+    use axum::{
+        extract::{Path, Query},
+        http::{HeaderMap, StatusCode},
+        routing::get,
+        Json, Router,
+    };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::signers::remote::serve_stand_in;
+
+    /// Vault the key ids name, behind the stand-in.
+    const REAL_VAULT: &str = "https://real.vault.azure.net";
+
+    /// Key of `version` of `batcher`, `v1` or `v2`.
+    fn version_key(version: &str) -> SigningKey {
+        let seed = version.strip_prefix('v').unwrap().parse().unwrap();
+        SigningKey::from_slice(&[seed; 32]).unwrap()
+    }
+
+    fn version_address(version: &str) -> Address {
+        Address::from_public_key(version_key(version).verifying_key())
+    }
+
+    fn check_request(query: &HashMap<String, String>, headers: &HeaderMap) {
+        assert_eq!(query["api-version"], API_VERSION);
+        assert_eq!(headers["authorization"], "Bearer test");
+    }
+
+    /// A vault holding an RSA key and `batcher`, at versions `v1` and `v2`, the current one.
+    fn stand_in() -> Router {
+        let list = |Query(query): Query<HashMap<String, String>>, headers: HeaderMap| async move {
+            check_request(&query, &headers);
+            Json(json!({
+                "value": [{ "kid": format!("{}/keys/rsa", REAL_VAULT) }, { "kid": format!("{}/keys/batcher", REAL_VAULT) }],
+                "nextLink": null,
+            }))
+        };
+        let read = |Path(path): Path<String>,
+                    Query(query): Query<HashMap<String, String>>,
+                    headers: HeaderMap| async move {
+            check_request(&query, &headers);
+            let key = match path.split('/').collect::<Vec<_>>().as_slice() {
+                ["rsa", ""] => json!({ "kid": format!("{}/keys/rsa/r1", REAL_VAULT), "kty": "RSA" }),
+                ["batcher", version @ ("" | "v1" | "v2")] => {
+                    let version = match *version {
+                        "" => "v2",
+                        version => version,
+                    };
+                    let point = version_key(version).verifying_key().to_encoded_point(false);
+                    json!({
+                        "kid": format!("{}/keys/batcher/{}", REAL_VAULT, version),
+                        "kty": "EC",
+                        "crv": CURVE,
+                        "x": BASE64_URL_SAFE_NO_PAD.encode(point.x().unwrap()),
+                        "y": BASE64_URL_SAFE_NO_PAD.encode(point.y().unwrap()),
+                    })
+                }
+                _ => return Err(StatusCode::NOT_FOUND),
+            };
+            Ok(Json(json!({ "key": key })))
+        };
+        let sign = |Path(path): Path<String>,
+                    Query(query): Query<HashMap<String, String>>,
+                    headers: HeaderMap,
+                    Json(body): Json<Value>| async move {
+            check_request(&query, &headers);
+            let segments: Vec<&str> = path.split('/').collect();
+            let ["batcher", version @ ("v1" | "v2"), "sign"] = segments.as_slice() else {
+                return Err(StatusCode::NOT_FOUND);
+            };
+            assert_eq!(body["alg"], ALGORITHM);
+
+            let hash = BASE64_URL_SAFE_NO_PAD.decode(body["value"].as_str().unwrap()).unwrap();
+            let signature: ecdsa::Signature = version_key(version).sign_prehash(&hash).unwrap();
+            Ok(Json(json!({ "value": BASE64_URL_SAFE_NO_PAD.encode(signature.to_bytes()) })))
+        };
+
+        Router::new()
+            .route("/keys", get(list))
+            .route("/keys/*path", get(read).post(sign))
+    }
+
+    async fn backend() -> AzureKeyVaultBackend {
+        let opt = AzureKeyVaultConnOpt {
+            vault_url: Some(serve_stand_in(stand_in()).await),
+            tenant_id: None,
+            client_id: None,
+            client_secret_file: None,
+            client_secret: None,
+            authority_host: None,
+            access_token: Some("test".to_string()),
+        };
+        azure_key_vault_backend(&opt).unwrap()
+    }
+
+    #[tokio::test]
+    async fn versions_share_the_key_ref() {
+        let backend = backend().await;
+
+        for key_id in ["batcher", "batcher@v1", "batcher@v2"] {
+            assert_eq!(backend.resolve_key_ref(key_id).await.unwrap().key_id, "batcher");
+        }
+        for key_id in ["", "batcher@", "batcher_1", "a/b", "batcher@v1/sign"] {
+            assert!(backend.key_ref(key_id).is_err(), "{:?}", key_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn signs_with_the_current_or_given_version() {
+        let backend = backend().await;
+
+        let keys = backend.list_keys().await.unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!((keys[0].key_id.as_str(), keys[0].address), ("batcher", version_address("v2")));
+
+        let hash = keccak256("key vault");
+        for (key_id, version) in [("batcher", "v2"), ("batcher@v1", "v1")] {
+            let signature = backend.sign_hash(key_id, &hash).await.unwrap();
+            assert_eq!(signature.recover_address_from_prehash(&hash).unwrap(), version_address(version));
+        }
+        assert!(backend.signer("batcher@v3").await.is_err());
+    }
+}
@@ -0,0 +1,419 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use alloy::{
+    consensus::TxEnvelope,
+    primitives::{Address, Signature, B256},
+    rpc::types::TransactionRequest,
+};
+use anyhow::Result as AnyhowResult;
+use async_trait::async_trait;
+use axum::{
+    debug_handler,
+    extract::State,
+    http::HeaderMap,
+    routing::get,
+    Extension, Router,
+};
+use serde::Serialize;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::{
+    app_types::{AppJson, AppResult},
+    auth::{AccessControl, TokenScope},
+    signers::common::{BackendKind, EthSigner, KeyInfo, KeyRef, NamedKeys, ProxyState, SignerBackend},
+    tls::ClientIdentity,
+};
+
+/// Minimum time between two rebuilds of an [`AddressIndex`], so unknown addresses cannot keep the backends busy.
+const INDEX_REBUILD_SECS: u64 = 30;
+/// How long `GET /keys` and `eth_accounts` reuse the key listing of the backends.
+const LISTING_CACHE_SECS: u64 = 30;
+
+/// Entry of the `GET /keys` listing.
+#[derive(Serialize, Debug)]
+pub struct KeyEntry {
+    pub id: String,
+    /// Endpoint signing with the key
+    pub path: String,
+    pub backend: BackendKind,
+    pub label: Option<String>,
+    /// Checksummed, like `/key/:key_id/address`
+    pub address: String,
+}
+
+/// A key listed by one of the backends of a [`KeyCatalog`].
+struct ListedKey {
+    /// Index of the backend in [`KeyCatalog::sources`]
+    source: usize,
+    key_ref: KeyRef,
+    key: KeyInfo,
+}
+
+/// The keys of every backend, and when they were listed.
+type Listing = (Instant, Arc<Vec<ListedKey>>);
+
+/// The backends whose keys are listed by `GET /keys` and `eth_accounts`.
+#[derive(Default)]
+pub struct KeyCatalog {
+    sources: Vec<(String, Arc<dyn SignerBackend>)>,
+    /// Listed again once `LISTING_CACHE_SECS` old, or when the [`AddressIndex`] is rebuilt
+    listing: Mutex<Option<Listing>>,
+}
+
+impl KeyCatalog {
+    /// Lists the keys of `backend`, served under `{prefix}/key/{key_id}`.
+    pub fn add(&mut self, prefix: &str, backend: Arc<dyn SignerBackend>) {
+        self.sources.push((prefix.to_string(), backend));
+    }
+
+    /// Lists the keys of every backend, skipping the backends that fail to list theirs.
+    async fn list_sources(&self) -> Arc<Vec<ListedKey>> {
+        let mut listed = Vec::new();
+
+        for (source, (prefix, backend)) in self.sources.iter().enumerate() {
+            let keys = match backend.list_keys().await {
+                Ok(keys) => keys,
+                Err(err) => {
+                    warn!("failed to list the keys under {}/key: {:#}", prefix, err);
+                    continue;
+                }
+            };
+
+            for key in keys {
+                let Ok(key_ref) = backend.key_ref(&key.key_id) else {
+                    continue;
+                };
+                listed.push(ListedKey { source, key_ref, key });
+            }
+        }
+
+        Arc::new(listed)
+    }
+
+    /// The keys of every backend, listed again if the previous listing is `LISTING_CACHE_SECS` old.
+    async fn listed_keys(&self) -> Arc<Vec<ListedKey>> {
+        let mut listing = self.listing.lock().await;
+        if let Some((listed_at, keys)) = listing.as_ref()
+            && listed_at.elapsed() < Duration::from_secs(LISTING_CACHE_SECS)
+        {
+            return keys.clone();
+        }
+
+        let keys = self.list_sources().await;
+        *listing = Some((Instant::now(), keys.clone()));
+        keys
+    }
+
+    /// Keys the caller may use.
+    pub async fn list(
+        &self,
+        access: &AccessControl,
+        identity: Option<&ClientIdentity>,
+        token: Option<&TokenScope>,
+    ) -> Vec<KeyEntry> {
+        self.listed_keys()
+            .await
+            .iter()
+            .filter(|listed| access.authorize(identity, token, &listed.key_ref, None).is_ok())
+            .map(|ListedKey { source, key_ref, key }| KeyEntry {
+                id: key.key_id.clone(),
+                path: format!("{}/key/{}", self.sources[*source].0, key.key_id),
+                backend: key_ref.backend,
+                label: key.label.clone(),
+                address: key.address.to_string(),
+            })
+            .collect()
+    }
+
+    /// Indexes every listed key by its checksummed address, for `/address/:address` and `/`.
+    ///
+    /// The backends are listed again, so that new keys are found; the listing is then reused by [`KeyCatalog::list`].
+    /// An address listed by several backends goes to the first one, named keys coming first.
+    pub async fn address_index(&self) -> NamedKeys {
+        let mut listing = self.listing.lock().await;
+        let keys = self.list_sources().await;
+        *listing = Some((Instant::now(), keys.clone()));
+        drop(listing);
+
+        let mut index = HashMap::new();
+        for ListedKey { source, key, .. } in keys.iter() {
+            index
+                .entry(key.address.to_string())
+                .or_insert_with(|| (self.sources[*source].1.clone(), key.key_id.clone()));
+        }
+
+        info!("serving {} keys by address", index.len());
+        NamedKeys::new(index)
+    }
+
+    /// Addresses of the keys the caller may use, without duplicates.
+    pub async fn addresses(
+        &self,
+        access: &AccessControl,
+        identity: Option<&ClientIdentity>,
+        token: Option<&TokenScope>,
+    ) -> Vec<String> {
+        let mut addresses = Vec::new();
+        for entry in self.list(access, identity, token).await {
+            if !addresses.contains(&entry.address) {
+                addresses.push(entry.address);
+            }
+        }
+
+        addresses
+    }
+}
+
+#[debug_handler]
+async fn handle_list_keys(
+    State(state): State<ProxyState>,
+    identity: Option<Extension<ClientIdentity>>,
+    headers: HeaderMap,
+) -> AppResult<Vec<KeyEntry>> {
+    let token = state.access.authenticate(&headers)?;
+    let keys = state.catalog.list(&state.access, identity.as_deref(), token).await;
+
+    Ok(AppJson(keys))
+}
+
+/// Routes `GET /keys`.
+pub fn keys_router(state: ProxyState) -> Router {
+    Router::new()
+        .route("/keys", get(handle_list_keys))
+        .with_state(state)
+}
+
+/// The keys of a [`KeyCatalog`] by checksummed address, for `/address/:address` and `/`.
+///
+/// Rebuilt when asked for an address it does not know, at most every `INDEX_REBUILD_SECS`, so that keys created,
+/// tagged or first used by key id after startup are found without a restart.
+pub struct AddressIndex {
+    catalog: Arc<KeyCatalog>,
+    index: RwLock<Arc<NamedKeys>>,
+    /// When the index was last built, held while rebuilding
+    built_at: Mutex<Instant>,
+}
+
+impl AddressIndex {
+    pub async fn new(catalog: Arc<KeyCatalog>) -> Self {
+        let index = catalog.address_index().await;
+        Self {
+            catalog,
+            index: RwLock::new(Arc::new(index)),
+            built_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn current(&self) -> Arc<NamedKeys> {
+        self.index.read().unwrap_or_else(|err| err.into_inner()).clone()
+    }
+
+    /// The index holding `address`, rebuilt first if it is missing and the index is old enough.
+    async fn lookup(&self, address: &str) -> Arc<NamedKeys> {
+        let index = self.current();
+        if index.contains(address) {
+            return index;
+        }
+
+        let mut built_at = self.built_at.lock().await;
+        // Another request may have rebuilt it while this one waited
+        let index = self.current();
+        if index.contains(address) || built_at.elapsed() < Duration::from_secs(INDEX_REBUILD_SECS) {
+            return index;
+        }
+
+        let index = Arc::new(self.catalog.address_index().await);
+        *self.index.write().unwrap_or_else(|err| err.into_inner()) = index.clone();
+        *built_at = Instant::now();
+        index
+    }
+}
+
+#[async_trait]
+impl SignerBackend for AddressIndex {
+    fn key_ref(&self, address: &str) -> AnyhowResult<KeyRef> {
+        self.current().key_ref(address)
+    }
+
+    async fn resolve_key_ref(&self, address: &str) -> AnyhowResult<KeyRef> {
+        self.lookup(address).await.resolve_key_ref(address).await
+    }
+
+    async fn signer(&self, address: &str) -> AnyhowResult<Arc<dyn EthSigner>> {
+        self.lookup(address).await.signer(address).await
+    }
+
+    async fn address(&self, address: &str) -> AnyhowResult<Address> {
+        self.lookup(address).await.address(address).await
+    }
+
+    async fn list_keys(&self) -> AnyhowResult<Vec<KeyInfo>> {
+        self.current().list_keys().await
+    }
+
+    async fn sign_hash(&self, address: &str, hash: &B256) -> AnyhowResult<Signature> {
+        self.lookup(address).await.sign_hash(address, hash).await
+    }
+
+    async fn sign_message(&self, address: &str, message: &[u8]) -> AnyhowResult<Signature> {
+        self.lookup(address).await.sign_message(address, message).await
+    }
+
+    async fn sign_transaction(&self, address: &str, tx_request: TransactionRequest) -> AnyhowResult<TxEnvelope> {
+        self.lookup(address).await.sign_transaction(address, tx_request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use anyhow::bail;
+    use axum::http::{header::AUTHORIZATION, HeaderValue};
+
+    use super::*;
+    use crate::auth::hash_token;
+
+    /// Lists `keys` without being able to sign, counting how often it is listed.
+    struct ListedBackend {
+        kind: BackendKind,
+        keys: Vec<(&'static str, Address)>,
+        listings: AtomicUsize,
+    }
+
+    impl ListedBackend {
+        fn new(kind: BackendKind, keys: &[(&'static str, Address)]) -> Arc<Self> {
+            Arc::new(Self {
+                kind,
+                keys: keys.to_vec(),
+                listings: AtomicUsize::new(0),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl SignerBackend for ListedBackend {
+        fn key_ref(&self, key_id: &str) -> AnyhowResult<KeyRef> {
+            Ok(KeyRef {
+                backend: self.kind,
+                key_id: key_id.to_string(),
+            })
+        }
+
+        async fn signer(&self, key_id: &str) -> AnyhowResult<Arc<dyn EthSigner>> {
+            bail!("cannot sign with {}", key_id)
+        }
+
+        async fn list_keys(&self) -> AnyhowResult<Vec<KeyInfo>> {
+            self.listings.fetch_add(1, Ordering::SeqCst);
+            Ok(self
+                .keys
+                .iter()
+                .map(|&(key_id, address)| KeyInfo {
+                    key_id: key_id.to_string(),
+                    label: None,
+                    address,
+                })
+                .collect())
+        }
+    }
+
+    fn key(kind: BackendKind, key_id: &str) -> KeyRef {
+        KeyRef {
+            backend: kind,
+            key_id: key_id.to_string(),
+        }
+    }
+
+    fn paths(entries: &[KeyEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.path.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn lists_the_keys_the_caller_may_use() {
+        let backend = ListedBackend::new(
+            BackendKind::Keystore,
+            &[("a", Address::repeat_byte(1)), ("b", Address::repeat_byte(2)), ("c", Address::repeat_byte(3))],
+        );
+        let mut catalog = KeyCatalog::default();
+        catalog.add("/keystore", backend);
+
+        let mut access = AccessControl::default();
+        access.allow_clients(key(BackendKind::Keystore, "b"), &["batcher".to_string()]);
+        let all = catalog.list(&access, None, None).await;
+        assert_eq!(paths(&all), ["/keystore/key/a", "/keystore/key/c"]);
+        assert_eq!(all[0].address, Address::repeat_byte(1).to_string());
+
+        let batcher = ClientIdentity {
+            common_names: vec!["batcher".to_string()],
+            alt_names: Vec::new(),
+        };
+        let all = catalog.list(&access, Some(&batcher), None).await;
+        assert_eq!(paths(&all), ["/keystore/key/a", "/keystore/key/b", "/keystore/key/c"]);
+
+        let hash = hex::decode(hash_token("secret")).unwrap().try_into().unwrap();
+        let keys = HashSet::from([key(BackendKind::Keystore, "a"), key(BackendKind::Keystore, "b")]);
+        access.add_token("ops".to_string(), hash, Some(keys), None);
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer secret"));
+        let token = access.authenticate(&headers).unwrap();
+        assert_eq!(paths(&catalog.list(&access, None, token).await), ["/keystore/key/a"]);
+        assert_eq!(catalog.addresses(&access, Some(&batcher), token).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn lists_addresses_once() {
+        let address = Address::repeat_byte(1);
+        let mut catalog = KeyCatalog::default();
+        catalog.add("", ListedBackend::new(BackendKind::Keystore, &[("batcher", address)]));
+        catalog.add("/keystore", ListedBackend::new(BackendKind::Keystore, &[("a", address)]));
+        catalog.add("/vault", ListedBackend::new(BackendKind::Vault, &[("b", Address::repeat_byte(2))]));
+
+        let access = AccessControl::default();
+        let all = catalog.list(&access, None, None).await;
+        assert_eq!(paths(&all), ["/key/batcher", "/keystore/key/a", "/vault/key/b"]);
+        assert_eq!(
+            catalog.addresses(&access, None, None).await,
+            [address.to_string(), Address::repeat_byte(2).to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn indexes_addresses_by_the_first_backend() {
+        let address = Address::repeat_byte(1);
+        let mut catalog = KeyCatalog::default();
+        catalog.add("", ListedBackend::new(BackendKind::Keystore, &[("batcher", address)]));
+        catalog.add("/vault", ListedBackend::new(BackendKind::Vault, &[("a", address)]));
+        catalog.add("/vault", ListedBackend::new(BackendKind::Vault, &[("b", Address::repeat_byte(2))]));
+
+        let index = catalog.address_index().await;
+        assert_eq!(index.key_ref(&address.to_string()).unwrap(), key(BackendKind::Keystore, "batcher"));
+        assert_eq!(index.key_ref(&Address::repeat_byte(2).to_string()).unwrap(), key(BackendKind::Vault, "b"));
+        assert!(!index.contains(&Address::repeat_byte(3).to_string()));
+    }
+
+    #[tokio::test]
+    async fn reuses_the_listing() {
+        let backend = ListedBackend::new(BackendKind::Keystore, &[("a", Address::repeat_byte(1))]);
+        let mut catalog = KeyCatalog::default();
+        catalog.add("/keystore", backend.clone());
+
+        let access = AccessControl::default();
+        catalog.list(&access, None, None).await;
+        catalog.addresses(&access, None, None).await;
+        assert_eq!(backend.listings.load(Ordering::SeqCst), 1);
+
+        // Rebuilding the index lists the backends again, and refreshes the listing
+        catalog.address_index().await;
+        catalog.list(&access, None, None).await;
+        assert_eq!(backend.listings.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,163 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, bail, Result as AnyhowResult};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::RequestBuilder;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use serde_with::{serde_as, DisplayFromStr, PickFirst};
+use tokio::sync::Mutex;
+
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+/// Tokens are fetched again when they have less than this left to live
+const TOKEN_REFRESH_MARGIN_SECS: u64 = 60;
+/// Lifetime of the JWT assertions signed with a Google service account key
+const JWT_LIFETIME_SECS: u64 = 3600;
+
+pub fn http_client() -> AnyhowResult<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+        .build()?)
+}
+
+/// Sends the request and parses the JSON response, turning the `error` of Google, Azure and
+/// OAuth responses into the error message.
+pub async fn send_json<T: DeserializeOwned>(request: RequestBuilder) -> AnyhowResult<T> {
+    let response = request.send().await?;
+    let status = response.status();
+
+    if !status.is_success() {
+        let body: Value = response.json().await.unwrap_or_default();
+        let message = body["error"]["message"]
+            .as_str()
+            .or_else(|| body["error_description"].as_str())
+            .or_else(|| body["error"].as_str());
+        match message {
+            Some(message) => bail!("{}: {}", status, message),
+            None => bail!("{}", status),
+        }
+    }
+
+    Ok(response.json().await?)
+}
+
+/// How OAuth2 access tokens are obtained.
+pub enum TokenGrant {
+    /// A token obtained out of band (e.g. `gcloud auth print-access-token`), never refreshed
+    Static(String),
+    /// Google service account key, exchanged for tokens with a signed JWT
+    ServiceAccount {
+        client_email: String,
+        private_key: String,
+        token_uri: String,
+        scope: String,
+    },
+    /// Azure AD application, with the client credentials flow
+    ClientSecret {
+        token_url: String,
+        client_id: String,
+        client_secret: String,
+        scope: String,
+    },
+    /// Identity of the VM or pod, from the GCE metadata server or Azure IMDS
+    Metadata {
+        url: String,
+        header: (&'static str, &'static str),
+    },
+}
+
+/// Google service account key file, as downloaded from the console.
+#[derive(Deserialize)]
+pub struct ServiceAccountKey {
+    pub client_email: String,
+    pub private_key: String,
+    pub token_uri: String,
+}
+
+#[serde_as]
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    /// A number, or a string for Azure IMDS
+    #[serde_as(as = "PickFirst<(_, DisplayFromStr)>")]
+    expires_in: u64,
+}
+
+/// OAuth2 access token, fetched again shortly before it expires.
+pub struct AccessToken {
+    http: reqwest::Client,
+    grant: TokenGrant,
+    cached: Mutex<Option<(String, Instant)>>,
+}
+
+impl AccessToken {
+    pub fn new(http: reqwest::Client, grant: TokenGrant) -> Self {
+        Self {
+            http,
+            grant,
+            cached: Mutex::new(None),
+        }
+    }
+
+    pub async fn get(&self) -> AnyhowResult<String> {
+        let mut cached = self.cached.lock().await;
+
+        if let Some((token, refresh_at)) = cached.as_ref()
+            && Instant::now() < *refresh_at
+        {
+            return Ok(token.clone());
+        }
+
+        let response: TokenResponse = match &self.grant {
+            TokenGrant::Static(token) => return Ok(token.clone()),
+            TokenGrant::ServiceAccount {
+                client_email,
+                private_key,
+                token_uri,
+                scope,
+            } => {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                let claims = json!({
+                    "iss": client_email,
+                    "scope": scope,
+                    "aud": token_uri,
+                    "iat": now,
+                    "exp": now + JWT_LIFETIME_SECS,
+                });
+                let key = EncodingKey::from_rsa_pem(private_key.as_bytes())
+                    .map_err(|_| anyhow!("invalid service account private key"))?;
+                let assertion = jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &key)?;
+
+                let form = [
+                    ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                    ("assertion", assertion.as_str()),
+                ];
+                send_json(self.http.post(token_uri).form(&form)).await
+            }
+            TokenGrant::ClientSecret {
+                token_url,
+                client_id,
+                client_secret,
+                scope,
+            } => {
+                let form = [
+                    ("grant_type", "client_credentials"),
+                    ("client_id", client_id),
+                    ("client_secret", client_secret),
+                    ("scope", scope),
+                ];
+                send_json(self.http.post(token_url).form(&form)).await
+            }
+            TokenGrant::Metadata { url, header } => send_json(self.http.get(url).header(header.0, header.1)).await,
+        }
+        .map_err(|err| err.context("failed to get an access token"))?;
+
+        let lifetime = response.expires_in.saturating_sub(TOKEN_REFRESH_MARGIN_SECS);
+        *cached = Some((
+            response.access_token.clone(),
+            Instant::now() + Duration::from_secs(lifetime),
+        ));
+
+        Ok(response.access_token)
+    }
+}
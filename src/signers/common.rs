@@ -1,122 +1,466 @@
 use alloy::{
+    consensus::TxEnvelope,
+    dyn_abi::TypedData,
     eips::eip2718::Encodable2718,
     hex,
-    //rpc::types::TransactionRequest,
-    primitives::{U256, B256, keccak256, Address, Signature},
-    network::{TxSigner, EthereumWallet, TransactionBuilder},
-    signers::{Signer},
+    network::{EthereumWallet, TransactionBuilder, TxSigner},
+    primitives::{keccak256, Address, Bytes, Signature, B256, U256},
     rpc::types::TransactionRequest,
-
+    signers::Signer,
+};
+use async_trait::async_trait;
+use axum::{
+    debug_handler,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension, Router,
 };
-use std::{sync::Arc};
+use std::{collections::{BTreeMap, HashMap, HashSet}, future::Future, sync::Arc, time::Duration};
 
-use anyhow::{anyhow, Result as AnyhowResult};
+use anyhow::{anyhow, Context, Result as AnyhowResult};
 use serde_json::Value;
+use strum::{Display, EnumString, VariantNames};
+use tokio::{
+    net::TcpListener,
+    task::JoinSet,
+    time::{timeout_at, Instant},
+};
+use tower_http::{timeout::TimeoutLayer, trace::TraceLayer};
+use tracing::{debug, info, warn};
 
 use crate::{
-    app_types::{AppError, AppJson, AppResult},
-    jsonrpc::{JsonRpcReply, JsonRpcRequest, JsonRpcResult},
+    app_types::{AppJson, AppResult},
+    auth::{AccessControl, AuthError, TokenScope},
+    cli::ServeOpt,
+    config::{ServerConfig, ServerOpt},
+    jsonrpc::{
+        error_reply, parse_body, parse_request, split_batch, AddressResponse, JsonRpcError, JsonRpcReply,
+        JsonRpcRequest, JsonRpcResult, Id, Params, JSONRPC_VERSION, METHOD_NOT_FOUND, SIGNER_ERROR,
+    },
+    policy::{Policies, TxPolicy},
+    shutdown_signal::shutdown_signal,
+    signers::{
+        aws_kms::aws_kms_backend,
+        catalog::{keys_router, AddressIndex, KeyCatalog},
+        keystore::keystore_backend,
+        pkcs11::pkcs11_backend,
+        vault::vault_backend,
+        gcp_kms::gcp_kms_backend,
+        azure_key_vault::azure_key_vault_backend,
+        yubihsm::yubihsm_backend,
+    },
+    tls::{serve_tls, ClientIdentity, TlsState},
 };
 
-use serde::{Deserialize};
-use serde_with::{serde_as};
-use serde_with::base64::{Base64};
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
 
 #[serde_as]
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct BlockPayloadArgs {
     pub domain: [u8; 32],
-    pub chain_id: U256,         // U256 is 32 bytes, matches big.Int
+    pub chain_id: U256, // U256 is 32 bytes, matches big.Int
 
     #[serde_as(as = "Base64")]
     pub payload_hash: Vec<u8>,
-    
+
     pub sender_address: Address,
+}
+
+/// Any signer that can sign both raw hashes and transactions.
+pub trait EthSigner: Signer + TxSigner<Signature> + Send + Sync {}
+
+impl<T> EthSigner for T where T: Signer + TxSigner<Signature> + Send + Sync {}
+
+/// A source of signing keys (YubiHSM, AWS KMS, ...) that can be mounted on the proxy router.
+#[async_trait]
+pub trait SignerBackend: Send + Sync {
+    /// Resolves `key_id` to the backend key it refers to, without connecting.
+    fn key_ref(&self, key_id: &str) -> AnyhowResult<KeyRef>;
+
+    /// Resolves `key_id` like [`SignerBackend::key_ref`], looking up the other names of a key
+    /// (e.g. KMS aliases and ARNs) so that they share its access rules and policy.
+    async fn resolve_key_ref(&self, key_id: &str) -> AnyhowResult<KeyRef> {
+        self.key_ref(key_id)
     }
 
+    /// Looks up the signer for `key_id`, connecting to it on first use.
+    async fn signer(&self, key_id: &str) -> AnyhowResult<Arc<dyn EthSigner>>;
 
-pub async fn handle_eth_sign_transaction(
-    payload: JsonRpcRequest<Vec<Value>>,
-    signer: EthereumWallet,
-) -> AnyhowResult<JsonRpcReply<Value>> {
-    let params = payload.params.ok_or_else(|| anyhow!("params is empty"))?;
+    async fn address(&self, key_id: &str) -> AnyhowResult<Address> {
+        let signer = self.signer(key_id).await?;
+        Ok(Signer::address(signer.as_ref()))
+    }
+
+    /// Lists the keys of the backend usable for signing, cached or discovered.
+    async fn list_keys(&self) -> AnyhowResult<Vec<KeyInfo>>;
 
-    if params.is_empty() {
-        return Err(anyhow!("params is empty"));
+    async fn sign_hash(&self, key_id: &str, hash: &B256) -> AnyhowResult<Signature> {
+        let signer = self.signer(key_id).await?;
+        Ok(signer.sign_hash(hash).await?)
     }
 
-    let tx_object = params[0].clone();
-    let tx_request = serde_json::from_value::<TransactionRequest>(tx_object)?;
-    let tx_envelope = tx_request.build(&signer).await?;
-    println!("tx_envelope: {:?}", tx_envelope);
+    /// Signs `message` with the EIP-191 `personal_sign` prefix.
+    async fn sign_message(&self, key_id: &str, message: &[u8]) -> AnyhowResult<Signature> {
+        let signer = self.signer(key_id).await?;
+        Ok(signer.sign_message(message).await?)
+    }
+
+    async fn sign_transaction(
+        &self,
+        key_id: &str,
+        tx_request: TransactionRequest,
+    ) -> AnyhowResult<TxEnvelope> {
+        let signer = self.signer(key_id).await?;
+        Ok(tx_request.build(&EthereumWallet::from(signer)).await?)
+    }
+}
+
+#[derive(EnumString, VariantNames, Display, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[strum(serialize_all = "kebab_case")]
+#[serde(rename_all = "kebab-case")]
+pub enum BackendKind {
+    Yubihsm,
+    AwsKms,
+    Keystore,
+    Pkcs11,
+    Vault,
+    GcpKms,
+    AzureKeyVault,
+}
+
+impl BackendKind {
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            BackendKind::Yubihsm => "/yubihsm",
+            BackendKind::AwsKms => "/aws-kms",
+            BackendKind::Keystore => "/keystore",
+            BackendKind::Pkcs11 => "/pkcs11",
+            BackendKind::Vault => "/vault",
+            BackendKind::GcpKms => "/gcp-kms",
+            BackendKind::AzureKeyVault => "/azure-key-vault",
+        }
+    }
+}
+
+/// A key of a specific backend, with the key id in canonical form.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct KeyRef {
+    pub backend: BackendKind,
+    pub key_id: String,
+}
+
+impl std::fmt::Display for KeyRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.backend, self.key_id)
+    }
+}
+
+/// A key listed by [`SignerBackend::list_keys`].
+#[derive(Clone, Debug)]
+pub struct KeyInfo {
+    pub key_id: String,
+    pub label: Option<String>,
+    pub address: Address,
+}
+
+/// Keys configured by name, each pointing at a key of another backend.
+pub struct NamedKeys {
+    keys: HashMap<String, (Arc<dyn SignerBackend>, String)>,
+}
+
+impl NamedKeys {
+    pub fn new(keys: HashMap<String, (Arc<dyn SignerBackend>, String)>) -> Self {
+        Self { keys }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.keys.contains_key(name)
+    }
+
+    fn resolve(&self, name: &str) -> AnyhowResult<(&dyn SignerBackend, &str)> {
+        let (backend, key_id) = self
+            .keys
+            .get(name)
+            .ok_or_else(|| anyhow!("unknown key: {}", name))?;
+
+        Ok((backend.as_ref(), key_id.as_str()))
+    }
+}
+
+#[async_trait]
+impl SignerBackend for NamedKeys {
+    fn key_ref(&self, name: &str) -> AnyhowResult<KeyRef> {
+        let (backend, key_id) = self.resolve(name)?;
+        backend.key_ref(key_id)
+    }
+
+    async fn resolve_key_ref(&self, name: &str) -> AnyhowResult<KeyRef> {
+        let (backend, key_id) = self.resolve(name)?;
+        backend.resolve_key_ref(key_id).await
+    }
+
+    async fn signer(&self, name: &str) -> AnyhowResult<Arc<dyn EthSigner>> {
+        let (backend, key_id) = self.resolve(name)?;
+        backend.signer(key_id).await
+    }
+
+    async fn address(&self, name: &str) -> AnyhowResult<Address> {
+        let (backend, key_id) = self.resolve(name)?;
+        backend.address(key_id).await
+    }
+
+    async fn list_keys(&self) -> AnyhowResult<Vec<KeyInfo>> {
+        let mut names: Vec<&String> = self.keys.keys().collect();
+        names.sort();
+
+        let mut keys = Vec::with_capacity(names.len());
+        for name in names {
+            match self.address(name).await {
+                Ok(address) => keys.push(KeyInfo {
+                    key_id: name.clone(),
+                    label: None,
+                    address,
+                }),
+                Err(err) => warn!("skipping key {}: {:#}", name, err),
+            }
+        }
+
+        Ok(keys)
+    }
+
+    async fn sign_hash(&self, name: &str, hash: &B256) -> AnyhowResult<Signature> {
+        let (backend, key_id) = self.resolve(name)?;
+        backend.sign_hash(key_id, hash).await
+    }
+
+    async fn sign_message(&self, name: &str, message: &[u8]) -> AnyhowResult<Signature> {
+        let (backend, key_id) = self.resolve(name)?;
+        backend.sign_message(key_id, message).await
+    }
+
+    async fn sign_transaction(
+        &self,
+        name: &str,
+        tx_request: TransactionRequest,
+    ) -> AnyhowResult<TxEnvelope> {
+        let (backend, key_id) = self.resolve(name)?;
+        backend.sign_transaction(key_id, tx_request).await
+    }
+}
+
+pub async fn handle_eth_sign_transaction(
+    payload: JsonRpcRequest<Params>,
+    backend: &dyn SignerBackend,
+    key_id: &str,
+    policy: Option<&TxPolicy>,
+) -> AnyhowResult<JsonRpcReply<Value>> {
+    let params = payload.params.unwrap_or_default();
+    let mut tx_request: TransactionRequest = params.parse(0, "transaction")?;
+    // Never sign with another key than the one the client believes it is using
+    match tx_request.from {
+        Some(from) => check_address(backend, key_id, "from", from).await?,
+        None => tx_request.from = Some(backend.address(key_id).await?),
+    }
+    if let Some(policy) = policy {
+        policy.check(&tx_request).map_err(JsonRpcError::from)?;
+    }
+    let tx_envelope = backend.sign_transaction(key_id, tx_request).await?;
+    debug!("signed transaction {} with {}", tx_envelope.tx_hash(), key_id);
 
     let encoded_tx = tx_envelope.encoded_2718();
     let rlp_hex = hex::encode_prefixed(encoded_tx);
-    println!("rlp_hex: {:?}", rlp_hex);
 
     Ok(JsonRpcReply {
-        id: payload.id,
+        id: payload.id.unwrap_or_default(),
         jsonrpc: payload.jsonrpc,
         result: JsonRpcResult::Result(rlp_hex.into()),
     })
 }
 
+/// `eth_sign` (`[address, data]`) and `personal_sign` (`[data, address]`), both EIP-191 prefixed.
+pub async fn handle_eth_sign_message(
+    payload: JsonRpcRequest<Params>,
+    backend: &dyn SignerBackend,
+    key_id: &str,
+) -> AnyhowResult<JsonRpcReply<Value>> {
+    let params = payload.params.unwrap_or_default();
+    let (address, message): (Address, Bytes) = if payload.method == "personal_sign" {
+        // Wallets also accept plain text here, anything that is not 0x-prefixed hex is signed as UTF-8
+        let data: String = params.parse(0, "data")?;
+        let message = match hex::decode(&data) {
+            Ok(bytes) if data.starts_with("0x") => bytes.into(),
+            _ => Bytes::from(data.into_bytes()),
+        };
+        (params.parse(1, "address")?, message)
+    } else {
+        (params.parse(0, "address")?, params.parse(1, "data")?)
+    };
+
+    check_address(backend, key_id, "address", address).await?;
+    let signature = backend.sign_message(key_id, &message).await?;
+
+    Ok(JsonRpcReply {
+        id: payload.id.unwrap_or_default(),
+        jsonrpc: payload.jsonrpc,
+        result: JsonRpcResult::Result(hex::encode_prefixed(signature.as_bytes()).into()),
+    })
+}
+
+/// `eth_signTypedData_v4` and `_v3` (`[address, typedData]`), with `typedData` as an object or a JSON string.
+pub async fn handle_eth_sign_typed_data(
+    payload: JsonRpcRequest<Params>,
+    backend: &dyn SignerBackend,
+    key_id: &str,
+) -> AnyhowResult<JsonRpcReply<Value>> {
+    let params = payload.params.unwrap_or_default();
+    let address: Address = params.parse(0, "address")?;
+    let typed_data: TypedData = match params.parse::<Value>(1, "typedData")? {
+        Value::String(json) => serde_json::from_str(&json),
+        object => serde_json::from_value(object),
+    }
+    .map_err(|err| JsonRpcError::invalid_params(format!("typedData: {}", err)))?;
+
+    let hash = typed_data
+        .eip712_signing_hash()
+        .map_err(|err| JsonRpcError::invalid_params(format!("typedData: {}", err)))?;
+
+    check_address(backend, key_id, "address", address).await?;
+    let signature = backend.sign_hash(key_id, &hash).await?;
+
+    Ok(JsonRpcReply {
+        id: payload.id.unwrap_or_default(),
+        jsonrpc: payload.jsonrpc,
+        result: JsonRpcResult::Result(hex::encode_prefixed(signature.as_bytes()).into()),
+    })
+}
+
+/// Rejects requests whose `param` names a different address than the one of `key_id`.
+async fn check_address(
+    backend: &dyn SignerBackend,
+    key_id: &str,
+    param: &str,
+    address: Address,
+) -> AnyhowResult<()> {
+    let signer_address = backend.address(key_id).await?;
+    if address != signer_address {
+        return Err(JsonRpcError::invalid_params(format!(
+            "{} {} does not match key {} ({})",
+            param, address, key_id, signer_address
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+/// `eth_accounts`, answered with the addresses of every key the caller may use.
+pub fn handle_eth_accounts(payload: JsonRpcRequest<Params>, addresses: Vec<String>) -> JsonRpcReply<Value> {
+    JsonRpcReply {
+        id: payload.id.unwrap_or_default(),
+        jsonrpc: payload.jsonrpc,
+        result: JsonRpcResult::Result(addresses.into()),
+    }
+}
+
+/// Address of the key a request sent to `/` is signed with.
+fn request_sender(payload: &JsonRpcRequest<Params>) -> Result<Address, JsonRpcError> {
+    let no_params = Params::default();
+    let params = payload.params.as_ref().unwrap_or(&no_params);
+
+    match payload.method.as_str() {
+        "eth_signTransaction" => params
+            .parse::<TransactionRequest>(0, "transaction")?
+            .from
+            .ok_or_else(|| JsonRpcError::invalid_params("transaction: from is required to select the key")),
+        "eth_sign" | "eth_signTypedData_v4" | "eth_signTypedData_v3" => params.parse(0, "address"),
+        "personal_sign" => params.parse(1, "address"),
+        "opsigner_signBlockPayload" => Ok(params.parse::<BlockPayloadArgs>(0, "payload")?.sender_address),
+        method => Err(JsonRpcError::new(
+            METHOD_NOT_FOUND,
+            format!("method not found (only {}): {}", METHODS.join(", "), method),
+        )),
+    }
+}
+
 pub async fn handle_health_status(
-    payload: JsonRpcRequest<Vec<Value>>,
+    payload: JsonRpcRequest<Params>,
 ) -> AnyhowResult<JsonRpcReply<Value>> {
     Ok(JsonRpcReply {
-        id: payload.id,
+        id: payload.id.unwrap_or_default(),
         jsonrpc: payload.jsonrpc,
         result: JsonRpcResult::Result(env!("CARGO_PKG_VERSION").into()),
     })
 }
 
 
-pub async fn handle_eth_sign_jsonrpc<S>(
-    payload: JsonRpcRequest<Vec<Value>>,
-    signer: Arc<S>,
-) -> AppResult<JsonRpcReply<Value>> 
-where S: Signer + std::marker::Sync + std::marker::Send + TxSigner<Signature> + 'static
+/// JSON-RPC methods served on `/key/:key_id`.
+pub const METHODS: &[&str] = &[
+    "eth_signTransaction",
+    "eth_sign",
+    "personal_sign",
+    "eth_signTypedData_v4",
+    "eth_signTypedData_v3",
+    "eth_accounts",
+    "health_status",
+    "opsigner_signBlockPayload",
+];
 
-{
-    let method = payload.method.as_str();
+pub async fn handle_eth_sign_jsonrpc(
+    payload: JsonRpcRequest<Params>,
+    backend: &dyn SignerBackend,
+    key_id: &str,
+    policy: Option<&TxPolicy>,
+) -> JsonRpcReply<Value> {
+    let method = payload.method.clone();
+    let (id, jsonrpc) = (payload.id.clone(), payload.jsonrpc.clone());
+
+    if let Some(violation) = policy.and_then(|policy| policy.check_method(&method).err()) {
+        warn!("{} with key {} rejected: {}", method, key_id, violation);
+        return JsonRpcReply::error(id.unwrap_or_default(), jsonrpc, violation.into());
+    }
 
-    let result = match method {
-        "eth_signTransaction" => handle_eth_sign_transaction(payload, EthereumWallet::from(signer)).await,
+    let result = match method.as_str() {
+        "eth_signTransaction" => handle_eth_sign_transaction(payload, backend, key_id, policy).await,
+        "eth_sign" | "personal_sign" => handle_eth_sign_message(payload, backend, key_id).await,
+        "eth_signTypedData_v4" | "eth_signTypedData_v3" => {
+            handle_eth_sign_typed_data(payload, backend, key_id).await
+        }
         "health_status" => handle_health_status(payload).await,
-        "opsigner_signBlockPayload" => handle_eth_sign_block(payload, signer).await,
-        _ => Err(anyhow!(
-            "method not supported (only eth_signTransaction, health_status and opsigner_signBlockPayload): {}",
-            method
-        )),
+        "opsigner_signBlockPayload" => handle_eth_sign_block(payload, backend, key_id).await,
+        _ => Err(JsonRpcError::new(
+            METHOD_NOT_FOUND,
+            format!("method not found (only {}): {}", METHODS.join(", "), method),
+        )
+        .into()),
     };
 
-    result.map(AppJson).map_err(AppError)
+    result.unwrap_or_else(|err| {
+        let err = JsonRpcError::from(err);
+        warn!("{} with key {} failed: {}", method, key_id, err);
+        JsonRpcReply::error(id.unwrap_or_default(), jsonrpc, err)
+    })
 }
 
 
 
-pub async fn handle_eth_sign_block<S>(
-    payload: JsonRpcRequest<Vec<Value>>,
-    signer: Arc<S>,
-) -> AnyhowResult<JsonRpcReply<Value>> 
-where S: Signer
-{
-
-    println!("handle_eth_sign_block payload: {:?}", payload);
-    let params = payload.params.ok_or_else(|| anyhow!("params is empty"))?;
-    if params.is_empty() {
-        return Err(anyhow!("params is empty"));
+pub async fn handle_eth_sign_block(
+    payload: JsonRpcRequest<Params>,
+    backend: &dyn SignerBackend,
+    key_id: &str,
+) -> AnyhowResult<JsonRpcReply<Value>> {
+    let params = payload.params.unwrap_or_default();
+    let block: BlockPayloadArgs = params.parse(0, "payload")?;
+    if block.payload_hash.len() != 32 {
+        return Err(JsonRpcError::invalid_params("payloadHash must be 32 bytes").into());
     }
 
-    let block_object = params[0].clone();
-    let block: BlockPayloadArgs = serde_json::from_value(block_object)?;
-
-    println!("block: {:?}", block);
     let signing_hash = to_signing_hash(&block);
-
-    let signed_hash  = signer.sign_hash(&signing_hash).await?;
+    debug!("signing block payload {} with {}", signing_hash, key_id);
+    let signed_hash = backend.sign_hash(key_id, &signing_hash).await?;
 
     // extract the 65-byte array
     let mut sig_bytes: [u8; 65] = signed_hash.as_bytes();
@@ -124,13 +468,12 @@ where S: Signer
         return Err(anyhow!("Invalid recovery id: expected value >= 27, got {}", sig_bytes[64]));
     }
 
-    sig_bytes[64] = sig_bytes[64] - 27; // Adjust the recovery id to be 0 or 1
+    sig_bytes[64] -= 27; // Adjust the recovery id to be 0 or 1
 
     // encode as a "0x"-prefixed hex string
     let signed_hash_hex = hex::encode_prefixed(&sig_bytes[..]);
-    println!("signed_hash_hex: {:?}", signed_hash_hex);
     Ok(JsonRpcReply {
-        id: payload.id,
+        id: payload.id.unwrap_or_default(),
         jsonrpc: payload.jsonrpc,
         result: JsonRpcResult::Result(Value::String(signed_hash_hex)),
     })
@@ -143,17 +486,759 @@ pub fn to_signing_hash(args: &BlockPayloadArgs) -> B256 {
     msg_input[0..32].copy_from_slice(&args.domain);
 
     // Convert U256 → B256 (i.e. big-endian bytes) then grab the inner [u8;32]
-    let chain_id_bytes: [u8; 32] = B256::from(args.chain_id).0; 
-    msg_input[32..64].copy_from_slice(&chain_id_bytes); 
+    let chain_id_bytes: [u8; 32] = B256::from(args.chain_id).0;
+    msg_input[32..64].copy_from_slice(&chain_id_bytes);
 
     let payload_hash_bytes: [u8; 32] = args
-    .payload_hash
-    .as_slice()                    // &[u8]
-    .try_into()                   // &[u8] → [u8;32], copied
-    .unwrap();
+        .payload_hash
+        .as_slice() // &[u8]
+        .try_into() // &[u8] → [u8;32], copied
+        .unwrap();
 
-    println!("payload_hash_bytes: {:?}", payload_hash_bytes);
     msg_input[64..96].copy_from_slice(&payload_hash_bytes);
 
     keccak256(msg_input)
 }
+
+#[debug_handler]
+async fn handle_ping() -> &'static str {
+    "pong"
+}
+
+/// State shared by the routes of every backend.
+#[derive(Clone)]
+pub struct ProxyState {
+    pub access: Arc<AccessControl>,
+    pub policies: Arc<Policies>,
+    pub catalog: Arc<KeyCatalog>,
+    pub max_batch_size: usize,
+    /// Time a request body may take before the timeout layer drops it
+    pub timeout: Duration,
+}
+
+impl ProxyState {
+    pub fn new(
+        access: AccessControl,
+        policies: Policies,
+        catalog: KeyCatalog,
+        max_batch_size: usize,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            access: Arc::new(access),
+            policies: Arc::new(policies),
+            catalog: Arc::new(catalog),
+            max_batch_size,
+            timeout,
+        }
+    }
+
+    /// When the requests of a body must be answered by, leaving a tenth of the timeout to send the replies.
+    fn deadline(&self) -> Instant {
+        Instant::now() + self.timeout.mul_f64(0.9)
+    }
+}
+
+#[derive(Clone)]
+struct KeyRoutes {
+    backend: Arc<dyn SignerBackend>,
+    state: ProxyState,
+}
+
+impl KeyRoutes {
+    /// Authenticates the caller and checks that it may use `key_id`, whatever the method.
+    async fn authorize(
+        &self,
+        identity: Option<&ClientIdentity>,
+        headers: &HeaderMap,
+        key_id: &str,
+    ) -> AnyhowResult<(KeyRef, Option<&TokenScope>)> {
+        let token = self.state.access.authenticate(headers)?;
+        let key = self.backend.resolve_key_ref(key_id).await?;
+        self.state.access.authorize(identity, token, &key, None)?;
+        Ok((key, token))
+    }
+
+    /// Handles one request of the body, returning the HTTP status to use when it is not part of a batch.
+    ///
+    /// Notifications are handled like any other request, but get no reply.
+    async fn call(
+        &self,
+        request: Value,
+        identity: Option<&ClientIdentity>,
+        token: Option<&TokenScope>,
+        key: &KeyRef,
+        key_id: &str,
+    ) -> (StatusCode, Option<JsonRpcReply<Value>>) {
+        match parse_request::<Params>(request) {
+            Ok(payload) => self.dispatch(payload, identity, token, key, key_id).await,
+            Err(reply) => (StatusCode::OK, Some(reply)),
+        }
+    }
+
+    /// Handles one request sent to `/`, with the key whose address is the sender of the request.
+    async fn call_sender(
+        &self,
+        request: Value,
+        identity: Option<&ClientIdentity>,
+        token: Option<&TokenScope>,
+    ) -> (StatusCode, Option<JsonRpcReply<Value>>) {
+        let payload = match parse_request::<Params>(request) {
+            Ok(payload) => payload,
+            Err(reply) => return (StatusCode::OK, Some(reply)),
+        };
+
+        let key_id = match payload.method.as_str() {
+            "eth_accounts" | "health_status" => return self.call_keyless(payload, identity, token).await,
+            _ => request_sender(&payload).map(|address| address.to_string()),
+        };
+        let key = match key_id {
+            Ok(key_id) => match self.backend.resolve_key_ref(&key_id).await {
+                Ok(key) => Ok((key, key_id)),
+                Err(_) => Err(JsonRpcError::invalid_params(format!(
+                    "{} does not match any managed key",
+                    key_id
+                ))),
+            },
+            Err(err) => Err(err),
+        };
+
+        match key {
+            Ok((key, key_id)) => self.dispatch(payload, identity, token, &key, &key_id).await,
+            Err(err) => {
+                let reply = payload.id.map(|id| JsonRpcReply::error(id, payload.jsonrpc, err));
+                (StatusCode::OK, reply)
+            }
+        }
+    }
+
+    /// Handles the methods that need no key when sent to `/`.
+    async fn call_keyless(
+        &self,
+        payload: JsonRpcRequest<Params>,
+        identity: Option<&ClientIdentity>,
+        token: Option<&TokenScope>,
+    ) -> (StatusCode, Option<JsonRpcReply<Value>>) {
+        let (id, jsonrpc) = (payload.id.clone(), payload.jsonrpc.clone());
+
+        let result = match self.state.access.authorize_method(token, &payload.method) {
+            Ok(()) if payload.method == "eth_accounts" => {
+                let addresses = self.state.catalog.addresses(&self.state.access, identity, token).await;
+                Ok(handle_eth_accounts(payload, addresses))
+            }
+            Ok(()) => handle_health_status(payload)
+                .await
+                .map_err(|err| (StatusCode::OK, err.into())),
+            Err(err) => Err((err.status(), err.into())),
+        };
+
+        let (status, reply) = match result {
+            Ok(reply) => (StatusCode::OK, reply),
+            Err((status, err)) => (status, JsonRpcReply::error(id.clone().unwrap_or_default(), jsonrpc, err)),
+        };
+        (status, id.map(|_| reply))
+    }
+
+    /// Handles a parsed request with `key`, checking that the caller may call its method.
+    async fn dispatch(
+        &self,
+        payload: JsonRpcRequest<Params>,
+        identity: Option<&ClientIdentity>,
+        token: Option<&TokenScope>,
+        key: &KeyRef,
+        key_id: &str,
+    ) -> (StatusCode, Option<JsonRpcReply<Value>>) {
+        let is_notification = payload.id.is_none();
+
+        let (status, reply) = match self.state.access.authorize(identity, token, key, Some(&payload.method)) {
+            Ok(()) if payload.method == "eth_accounts" => {
+                let addresses = self.state.catalog.addresses(&self.state.access, identity, token).await;
+                (StatusCode::OK, handle_eth_accounts(payload, addresses))
+            }
+            Ok(()) => {
+                let policy = self.state.policies.get(key);
+                let reply = handle_eth_sign_jsonrpc(payload, self.backend.as_ref(), key_id, policy).await;
+                (StatusCode::OK, reply)
+            }
+            Err(err) => {
+                let id = payload.id.unwrap_or_default();
+                (err.status(), JsonRpcReply::error(id, payload.jsonrpc, err.into()))
+            }
+        };
+
+        (status, (!is_notification).then_some(reply))
+    }
+}
+
+/// Replies with the access error, keeping 401/403 so rejected callers also show up in HTTP logs and proxies.
+fn access_denied(err: anyhow::Error, body: &Value) -> Response {
+    let status = err.downcast_ref::<AuthError>().map_or(StatusCode::OK, AuthError::status);
+    let reply = JsonRpcReply::<Value>::error(Id::of(body), JSONRPC_VERSION.to_string(), err.into());
+    (status, AppJson(reply)).into_response()
+}
+
+/// Handles the requests of a body one after the other, the backends serialize signing anyway.
+///
+/// Requests still running or not started at `deadline` fail, so that a slow batch is answered with the replies
+/// it has rather than dropped as a whole by the timeout layer.
+async fn call_all<F, Fut>(requests: Vec<Value>, deadline: Instant, call: F) -> Vec<(StatusCode, Option<JsonRpcReply<Value>>)>
+where
+    F: Fn(Value) -> Fut,
+    Fut: Future<Output = (StatusCode, Option<JsonRpcReply<Value>>)>,
+{
+    let mut replies = Vec::with_capacity(requests.len());
+    for request in requests {
+        // Notifications have no id and get no reply, whether they timed out or not
+        let id = request.get("id").map(|_| Id::of(&request));
+        let reply = match timeout_at(deadline, call(request)).await {
+            Ok(reply) => reply,
+            Err(_) => {
+                let message = "request timed out before the rest of the batch completed".to_string();
+                (StatusCode::OK, id.map(|id| error_reply(id, SIGNER_ERROR, message)))
+            }
+        };
+        replies.push(reply);
+    }
+
+    replies
+}
+
+/// Builds the response to a single request, or to every request of a batch in order.
+fn batch_response(mut replies: Vec<(StatusCode, Option<JsonRpcReply<Value>>)>, is_batch: bool) -> Response {
+    if !is_batch {
+        return match replies.pop().unwrap_or((StatusCode::OK, None)) {
+            (status, Some(reply)) => (status, AppJson(reply)).into_response(),
+            (StatusCode::OK, None) => StatusCode::NO_CONTENT.into_response(),
+            (status, None) => status.into_response(),
+        };
+    }
+
+    let replies: Vec<_> = replies.into_iter().filter_map(|(_, reply)| reply).collect();
+
+    // A batch of notifications only gets an empty response
+    if replies.is_empty() {
+        return StatusCode::NO_CONTENT.into_response();
+    }
+    AppJson(replies).into_response()
+}
+
+async fn respond(
+    routes: KeyRoutes,
+    key_id: String,
+    identity: Option<&ClientIdentity>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Response {
+    let body = match parse_body(&body) {
+        Ok(body) => body,
+        Err(reply) => return AppJson(reply).into_response(),
+    };
+
+    let (key, token) = match routes.authorize(identity, &headers, &key_id).await {
+        Ok(authorized) => authorized,
+        Err(err) => return access_denied(err, &body),
+    };
+
+    let (requests, is_batch) = match split_batch(body, routes.state.max_batch_size) {
+        Ok(split) => split,
+        Err(reply) => return AppJson(reply).into_response(),
+    };
+
+    let replies = call_all(requests, routes.state.deadline(), |request| {
+        routes.call(request, identity, token, &key, &key_id)
+    })
+    .await;
+
+    batch_response(replies, is_batch)
+}
+
+#[debug_handler]
+async fn handle_request(
+    Path(key_id): Path<String>,
+    State(routes): State<KeyRoutes>,
+    identity: Option<Extension<ClientIdentity>>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Response {
+    respond(routes, key_id, identity.as_deref(), headers, body).await
+}
+
+#[debug_handler]
+async fn handle_address_key_request(
+    Path(address): Path<Address>,
+    State(routes): State<KeyRoutes>,
+    identity: Option<Extension<ClientIdentity>>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Response {
+    respond(routes, address.to_string(), identity.as_deref(), headers, body).await
+}
+
+/// Requests sent to `/` are signed with the key of their sender, each request of a batch on its own.
+#[debug_handler]
+async fn handle_sender_request(
+    State(routes): State<KeyRoutes>,
+    identity: Option<Extension<ClientIdentity>>,
+    headers: HeaderMap,
+    body: axum::body::Bytes,
+) -> Response {
+    let identity = identity.as_deref();
+    let body = match parse_body(&body) {
+        Ok(body) => body,
+        Err(reply) => return AppJson(reply).into_response(),
+    };
+
+    let token = match routes.state.access.authenticate(&headers) {
+        Ok(token) => token,
+        Err(err) => return access_denied(err.into(), &body),
+    };
+
+    let (requests, is_batch) = match split_batch(body, routes.state.max_batch_size) {
+        Ok(split) => split,
+        Err(reply) => return AppJson(reply).into_response(),
+    };
+
+    let replies = call_all(requests, routes.state.deadline(), |request| {
+        routes.call_sender(request, identity, token)
+    })
+    .await;
+
+    batch_response(replies, is_batch)
+}
+
+#[debug_handler]
+async fn handle_address_request(
+    Path(key_id): Path<String>,
+    State(routes): State<KeyRoutes>,
+    identity: Option<Extension<ClientIdentity>>,
+    headers: HeaderMap,
+) -> AppResult<AddressResponse> {
+    routes.authorize(identity.as_deref(), &headers, &key_id).await?;
+    let address = routes.backend.address(&key_id).await?.to_string();
+
+    Ok(AppJson(AddressResponse { address }))
+}
+
+/// Routes `/key/:key_id` and `/key/:key_id/address` to a single backend.
+pub fn backend_router(backend: Arc<dyn SignerBackend>, state: ProxyState) -> Router {
+    Router::new()
+        .route("/key/:key_id", post(handle_request))
+        .route("/key/:key_id/address", get(handle_address_request))
+        .with_state(KeyRoutes { backend, state })
+}
+
+/// Routes `/address/:address` and `/` to the keys of `index`, keyed by checksummed address.
+pub fn address_router(index: Arc<dyn SignerBackend>, state: ProxyState) -> Router {
+    Router::new()
+        .route("/", post(handle_sender_request))
+        .route("/address/:address", post(handle_address_key_request))
+        .with_state(KeyRoutes { backend: index, state })
+}
+
+/// Serves a single backend under `/key`, without access control, for the `serve` subcommand of each backend.
+pub async fn serve_backend(backend: Arc<dyn SignerBackend>, server_opt: ServerOpt) -> AnyhowResult<()> {
+    let mut server = ServerConfig::default();
+    server_opt.apply(&mut server);
+    server.validate()?;
+
+    let mut catalog = KeyCatalog::default();
+    catalog.add("", backend.clone());
+    let state = ProxyState::new(
+        AccessControl::default(),
+        Policies::new(),
+        catalog,
+        server.max_batch_size,
+        Duration::from_secs(server.timeout_secs),
+    );
+    let index = Arc::new(AddressIndex::new(state.catalog.clone()).await);
+
+    let router = backend_router(backend, state.clone())
+        .merge(address_router(index, state.clone()))
+        .merge(keys_router(state));
+    serve(router, &server).await
+}
+
+/// Adds `/ping` and the common layers to `router` and serves it on every listen address until shutdown.
+pub async fn serve(router: Router, server: &ServerConfig) -> AnyhowResult<()> {
+    let app = router
+        .route("/ping", get(handle_ping))
+        .layer((
+            TraceLayer::new_for_http(),
+            TimeoutLayer::new(Duration::from_secs(server.timeout_secs)),
+        ));
+
+    let tls = server.tls.clone().map(TlsState::new).transpose()?;
+    if let Some(tls) = &tls {
+        tokio::spawn(tls.clone().watch());
+    }
+
+    // Bind everything up front so a busy port fails before any listener starts serving
+    let mut listeners = Vec::with_capacity(server.listen.len());
+    for addr in &server.listen {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("failed to listen on {}", addr))?;
+        info!(
+            "listening on {}://{}",
+            if tls.is_some() { "https" } else { "http" },
+            listener.local_addr()?
+        );
+        listeners.push(listener);
+    }
+
+    let mut servers = JoinSet::new();
+    for listener in listeners {
+        let app = app.clone();
+        match &tls {
+            Some(tls) => {
+                servers.spawn(serve_tls(listener, app, tls.clone()));
+            }
+            None => {
+                servers.spawn(async move {
+                    axum::serve(listener, app)
+                        .with_graceful_shutdown(shutdown_signal())
+                        .await
+                });
+            }
+        }
+    }
+
+    while let Some(result) = servers.join_next().await {
+        result??;
+    }
+
+    Ok(())
+}
+
+pub async fn handle_serve(opt: ServeOpt) -> AnyhowResult<()> {
+    let config = opt.load_config()?;
+
+    let mut router = Router::new();
+    let mut backends: BTreeMap<BackendKind, Arc<dyn SignerBackend>> = BTreeMap::new();
+
+    if let Some(yubihsm) = &config.backends.yubihsm {
+        let backend = yubihsm_backend(yubihsm).await?;
+        backends.insert(BackendKind::Yubihsm, Arc::new(backend));
+    }
+    if let Some(aws_kms) = &config.backends.aws_kms {
+        let backend = aws_kms_backend(aws_kms).await;
+        backends.insert(BackendKind::AwsKms, Arc::new(backend));
+    }
+    if let Some(keystore) = &config.backends.keystore {
+        let backend = keystore_backend(keystore)?;
+        backends.insert(BackendKind::Keystore, Arc::new(backend));
+    }
+    if let Some(pkcs11) = &config.backends.pkcs11 {
+        let backend = pkcs11_backend(pkcs11)?;
+        backends.insert(BackendKind::Pkcs11, Arc::new(backend));
+    }
+    if let Some(vault) = &config.backends.vault {
+        let backend = vault_backend(vault).await?;
+        backends.insert(BackendKind::Vault, Arc::new(backend));
+    }
+    if let Some(gcp_kms) = &config.backends.gcp_kms {
+        let backend = gcp_kms_backend(gcp_kms)?;
+        backends.insert(BackendKind::GcpKms, Arc::new(backend));
+    }
+    if let Some(azure_key_vault) = &config.backends.azure_key_vault {
+        let backend = azure_key_vault_backend(azure_key_vault)?;
+        backends.insert(BackendKind::AzureKeyVault, Arc::new(backend));
+    }
+
+    let mut access = AccessControl::default();
+    let mut policies = Policies::new();
+    for key in config.keys.values() {
+        let key_ref = backends[&key.backend].resolve_key_ref(&key.key_id).await?;
+        if !key.clients.is_empty() {
+            access.allow_clients(key_ref.clone(), &key.clients);
+        }
+        if let Some(policy) = &key.policy {
+            info!("enforcing transaction policy on {}", key_ref);
+            policies.insert(key_ref, policy.clone());
+        }
+    }
+    for token in &config.tokens {
+        let keys = if token.keys.is_empty() {
+            None
+        } else {
+            let mut keys = HashSet::new();
+            for reference in &token.keys {
+                let (backend, key_id) = config.key_target(reference)?;
+                keys.insert(backends[&backend].resolve_key_ref(key_id).await?);
+            }
+            Some(keys)
+        };
+        let methods = (!token.methods.is_empty()).then(|| token.methods.iter().cloned().collect());
+
+        info!("accepting bearer token {}", token.name);
+        access.add_token(token.name.clone(), token.hash()?, keys, methods);
+    }
+
+    let named_keys: Option<Arc<dyn SignerBackend>> = (!config.keys.is_empty()).then(|| {
+        let keys = config
+            .keys
+            .iter()
+            .map(|(name, key)| {
+                info!("serving {:?} key {} as /key/{}", key.backend, key.key_id, name);
+                (name.clone(), (backends[&key.backend].clone(), key.key_id.clone()))
+            })
+            .collect();
+        Arc::new(NamedKeys::new(keys)) as Arc<dyn SignerBackend>
+    });
+
+    let mut catalog = KeyCatalog::default();
+    if let Some(named_keys) = &named_keys {
+        catalog.add("", named_keys.clone());
+    }
+    for (kind, backend) in &backends {
+        catalog.add(kind.prefix(), backend.clone());
+    }
+    let state = ProxyState::new(
+        access,
+        policies,
+        catalog,
+        config.server.max_batch_size,
+        Duration::from_secs(config.server.timeout_secs),
+    );
+    let index = Arc::new(AddressIndex::new(state.catalog.clone()).await);
+
+    for (kind, backend) in &backends {
+        info!("serving {:?} keys under {}", kind, kind.prefix());
+        router = router.nest(kind.prefix(), backend_router(backend.clone(), state.clone()));
+    }
+    if let Some(named_keys) = named_keys {
+        router = router.merge(backend_router(named_keys, state.clone()));
+    }
+    router = router
+        .merge(address_router(index, state.clone()))
+        .merge(keys_router(state));
+
+    serve(router, &config.server).await
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{primitives::b256, signers::local::PrivateKeySigner};
+    use anyhow::bail;
+    use serde_json::json;
+
+    use super::*;
+    use crate::jsonrpc::{INVALID_PARAMS, INVALID_REQUEST, PARSE_ERROR};
+
+    /// Key of the EIP-191 examples of the web3.js documentation.
+    const KEY: B256 = b256!("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318");
+    const ADDRESS: &str = "0x2c7536E3605D9C16a7a3D7b1898e529396a65c23";
+
+    struct TestBackend(Arc<PrivateKeySigner>);
+
+    impl TestBackend {
+        fn new(key: B256) -> Self {
+            Self(Arc::new(PrivateKeySigner::from_slice(key.as_slice()).unwrap()))
+        }
+    }
+
+    #[async_trait]
+    impl SignerBackend for TestBackend {
+        fn key_ref(&self, key_id: &str) -> AnyhowResult<KeyRef> {
+            Ok(KeyRef {
+                backend: BackendKind::Keystore,
+                key_id: key_id.to_string(),
+            })
+        }
+
+        async fn signer(&self, key_id: &str) -> AnyhowResult<Arc<dyn EthSigner>> {
+            match key_id {
+                "test" => Ok(self.0.clone()),
+                _ => bail!("unknown key {}", key_id),
+            }
+        }
+
+        async fn list_keys(&self) -> AnyhowResult<Vec<KeyInfo>> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn routes(key: B256) -> KeyRoutes {
+        let state = ProxyState::new(
+            AccessControl::default(),
+            Policies::new(),
+            KeyCatalog::default(),
+            3,
+            Duration::from_secs(30),
+        );
+        KeyRoutes {
+            backend: Arc::new(TestBackend::new(key)),
+            state,
+        }
+    }
+
+    /// Posts `body` to `/key/test`, returning the status and the reply, `Value::Null` when there is none.
+    async fn post(routes: KeyRoutes, body: &str) -> (StatusCode, Value) {
+        let body = axum::body::Bytes::from(body.to_string());
+        let response = respond(routes, "test".to_string(), None, HeaderMap::new(), body).await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let reply = if body.is_empty() { Value::Null } else { serde_json::from_slice(&body).unwrap() };
+        (status, reply)
+    }
+
+    fn health(id: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "method": "health_status", "id": id })
+    }
+
+    #[tokio::test]
+    async fn echoes_any_id() {
+        for id in [json!(1), json!("a"), json!(1.5), Value::Null] {
+            let (status, reply) = post(routes(KEY), &health(id.clone()).to_string()).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(reply["id"], id);
+            assert_eq!(reply["result"], env!("CARGO_PKG_VERSION"));
+        }
+
+        let (_, reply) = post(routes(KEY), &health(json!({ "id": 1 })).to_string()).await;
+        assert_eq!(reply["id"], Value::Null);
+        assert_eq!(reply["error"]["code"], INVALID_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn notification_gets_no_reply() {
+        let notification = json!({ "jsonrpc": "2.0", "method": "health_status" });
+        let (status, reply) = post(routes(KEY), &notification.to_string()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(reply, Value::Null);
+    }
+
+    #[tokio::test]
+    async fn malformed_requests() {
+        let (_, reply) = post(routes(KEY), "{").await;
+        assert_eq!(reply["error"]["code"], PARSE_ERROR);
+        assert_eq!(reply["id"], Value::Null);
+
+        let old_version = json!({ "jsonrpc": "1.0", "method": "health_status", "id": 7 });
+        let (_, reply) = post(routes(KEY), &old_version.to_string()).await;
+        assert_eq!(reply["error"]["code"], INVALID_REQUEST);
+        assert_eq!(reply["id"], 7);
+
+        let unknown = json!({ "jsonrpc": "2.0", "method": "eth_sendTransaction", "id": 8 });
+        let (_, reply) = post(routes(KEY), &unknown.to_string()).await;
+        assert_eq!(reply["error"]["code"], METHOD_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn batch_replies_in_order_without_notifications() {
+        let batch = json!([
+            health(json!(1)),
+            { "jsonrpc": "2.0", "method": "health_status" },
+            42,
+            health(json!("last")),
+        ]);
+        // One over the maximum of 3, notifications count
+        let (_, reply) = post(routes(KEY), &batch.to_string()).await;
+        assert_eq!(reply["error"]["code"], INVALID_REQUEST);
+        assert_eq!(reply["id"], Value::Null);
+
+        let batch = json!([health(json!(1)), { "jsonrpc": "2.0", "method": "health_status" }, 42]);
+        let (status, reply) = post(routes(KEY), &batch.to_string()).await;
+        assert_eq!(status, StatusCode::OK);
+        let replies = reply.as_array().unwrap();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0]["id"], 1);
+        assert_eq!(replies[0]["result"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(replies[1]["id"], Value::Null);
+        assert_eq!(replies[1]["error"]["code"], INVALID_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn batch_of_notifications_and_empty_batch() {
+        let notification = json!({ "jsonrpc": "2.0", "method": "health_status" });
+        let (status, reply) = post(routes(KEY), &json!([notification, notification]).to_string()).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(reply, Value::Null);
+
+        let (_, reply) = post(routes(KEY), "[]").await;
+        assert_eq!(reply["error"]["code"], INVALID_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn requests_left_at_the_deadline_fail() {
+        let requests = vec![health(json!(1)), json!({ "jsonrpc": "2.0", "method": "health_status" })];
+        let replies = call_all(requests, Instant::now(), |_| std::future::pending()).await;
+
+        assert_eq!(replies.len(), 2);
+        let Some(reply) = &replies[0].1 else { panic!("no reply to a request with an id") };
+        assert_eq!(reply.id, Id::Number(1.into()));
+        assert!(matches!(reply.result, JsonRpcResult::Error { code: SIGNER_ERROR, .. }));
+        assert!(replies[1].1.is_none());
+    }
+
+    #[tokio::test]
+    async fn eth_sign_and_personal_sign_prefix_the_message() {
+        // "Some data", signed by web3.eth.accounts.sign
+        let signature = "0xb91467e570a6466aa9e9876cbcd013baba02900b8979d43fe208a4a4f339f5fd6007e74cd82e037b800186422fc2da167c747ef045e5d18a5f5d4300f8e1a0291c";
+        let requests = [
+            json!({ "jsonrpc": "2.0", "method": "eth_sign", "params": [ADDRESS, "0x536f6d652064617461"], "id": 1 }),
+            json!({ "jsonrpc": "2.0", "method": "personal_sign", "params": ["0x536f6d652064617461", ADDRESS], "id": 1 }),
+            json!({ "jsonrpc": "2.0", "method": "personal_sign", "params": ["Some data", ADDRESS], "id": 1 }),
+            json!({ "jsonrpc": "2.0", "method": "personal_sign", "params": { "data": "Some data", "address": ADDRESS }, "id": 1 }),
+        ];
+
+        for request in requests {
+            let (_, reply) = post(routes(KEY), &request.to_string()).await;
+            assert_eq!(reply["result"], signature, "{}", request);
+        }
+
+        let other = json!({ "jsonrpc": "2.0", "method": "eth_sign", "params": [Address::ZERO, "0x00"], "id": 1 });
+        let (_, reply) = post(routes(KEY), &other.to_string()).await;
+        assert_eq!(reply["error"]["code"], INVALID_PARAMS);
+    }
+
+    #[tokio::test]
+    async fn eth_sign_typed_data_signs_the_eip712_hash() {
+        // The Mail example of EIP-712, signed with keccak256("cow")
+        let cow = b256!("c85ef7d79691fe79573b1a7064c19c1a9819ebdbd1faaab1a8ec92344438aaf4");
+        let typed_data = json!({
+            "types": {
+                "EIP712Domain": [
+                    { "name": "name", "type": "string" },
+                    { "name": "version", "type": "string" },
+                    { "name": "chainId", "type": "uint256" },
+                    { "name": "verifyingContract", "type": "address" }
+                ],
+                "Person": [
+                    { "name": "name", "type": "string" },
+                    { "name": "wallet", "type": "address" }
+                ],
+                "Mail": [
+                    { "name": "from", "type": "Person" },
+                    { "name": "to", "type": "Person" },
+                    { "name": "contents", "type": "string" }
+                ]
+            },
+            "primaryType": "Mail",
+            "domain": {
+                "name": "Ether Mail",
+                "version": "1",
+                "chainId": 1,
+                "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+            },
+            "message": {
+                "from": { "name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826" },
+                "to": { "name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB" },
+                "contents": "Hello, Bob!"
+            }
+        });
+        let parsed: TypedData = serde_json::from_value(typed_data.clone()).unwrap();
+        assert_eq!(
+            parsed.eip712_signing_hash().unwrap(),
+            b256!("be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2")
+        );
+
+        let signature = "0x4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b915621c";
+        let address = "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826";
+        for params in [json!([address, typed_data]), json!([address, typed_data.to_string()])] {
+            let request = json!({ "jsonrpc": "2.0", "method": "eth_signTypedData_v4", "params": params, "id": 1 });
+            let (_, reply) = post(routes(cow), &request.to_string()).await;
+            assert_eq!(reply["result"], signature);
+        }
+    }
+}
@@ -0,0 +1,509 @@
+use std::{collections::HashMap, env, fs, path::PathBuf, sync::Arc};
+
+use alloy::{primitives::B256, signers::k256::ecdsa};
+use anyhow::{anyhow, bail, Context, Result as AnyhowResult};
+use async_trait::async_trait;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use structopt::StructOpt;
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+use crate::{
+    config::ServerOpt,
+    signers::{
+        cloud::{http_client, send_json, AccessToken, ServiceAccountKey, TokenGrant},
+        common::{serve_backend, BackendKind, EthSigner, KeyInfo, KeyRef, SignerBackend},
+        remote::{parse_pem_public_key, DigestSigner, RemoteSigner},
+    },
+};
+
+const DEFAULT_ENDPOINT: &str = "https://cloudkms.googleapis.com";
+const DEFAULT_METADATA_HOST: &str = "metadata.google.internal";
+const SCOPE: &str = "https://www.googleapis.com/auth/cloudkms";
+const ALGORITHM: &str = "EC_SIGN_SECP256K1_SHA256";
+const ACCESS_TOKEN_ENV: &str = "GOOGLE_OAUTH_ACCESS_TOKEN";
+
+#[derive(StructOpt)]
+pub struct GcpKmsOpt {
+    #[structopt(flatten)]
+    pub conn: GcpKmsConnOpt,
+
+    #[structopt(subcommand)]
+    pub cmd: GcpKmsCommand,
+}
+
+/// Cloud KMS settings, shared by the `gcp-kms` subcommands, `serve` and the config file.
+///
+/// Without `credentials_file` nor `access_token`, tokens come from the metadata server of the
+/// VM or GKE pod.
+#[derive(StructOpt, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GcpKmsConnOpt {
+    /// Key ring of the keys (projects/{project}/locations/{location}/keyRings/{key_ring})
+    #[structopt(name = "gcp-key-ring", long = "gcp-key-ring", env = "GCP_KMS_KEY_RING")]
+    pub key_ring: Option<String>,
+
+    /// Cloud KMS API endpoint, e.g. a local stand-in server [default: https://cloudkms.googleapis.com]
+    #[structopt(name = "gcp-kms-endpoint", long = "gcp-kms-endpoint", env = "GCP_KMS_ENDPOINT")]
+    pub endpoint: Option<String>,
+
+    /// Service account key file
+    #[structopt(name = "gcp-credentials-file", long = "gcp-credentials-file", env = "GOOGLE_APPLICATION_CREDENTIALS", parse(from_os_str))]
+    pub credentials_file: Option<PathBuf>,
+
+    /// OAuth2 access token (e.g. from `gcloud auth print-access-token`), never refreshed, in the config file only;
+    /// on the command line it would show up in `ps`, use `GOOGLE_OAUTH_ACCESS_TOKEN` instead
+    #[structopt(skip)]
+    pub access_token: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub enum GcpKmsCommand {
+    Serve(ServerOpt),
+}
+
+impl GcpKmsConnOpt {
+    /// Checks the key ring name, without contacting Cloud KMS.
+    pub fn validate(&self) -> AnyhowResult<()> {
+        let Some(key_ring) = &self.key_ring else {
+            bail!("the Cloud KMS key ring is required");
+        };
+
+        match key_ring.split('/').collect::<Vec<_>>().as_slice() {
+            ["projects", project, "locations", location, "keyRings", name]
+                if !project.is_empty() && !location.is_empty() && !name.is_empty() => {}
+            _ => bail!("the key ring must be projects/{{project}}/locations/{{location}}/keyRings/{{key_ring}}"),
+        }
+
+        Ok(())
+    }
+
+    fn grant(&self) -> AnyhowResult<TokenGrant> {
+        if let Some(token) = self.access_token.clone().or_else(|| env::var(ACCESS_TOKEN_ENV).ok()) {
+            return Ok(TokenGrant::Static(token));
+        }
+
+        if let Some(path) = &self.credentials_file {
+            let key: ServiceAccountKey = serde_json::from_str(
+                &fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?,
+            )
+            .with_context(|| format!("invalid service account key file {}", path.display()))?;
+
+            return Ok(TokenGrant::ServiceAccount {
+                client_email: key.client_email,
+                private_key: key.private_key,
+                token_uri: key.token_uri,
+                scope: SCOPE.to_string(),
+            });
+        }
+
+        let host = env::var("GCE_METADATA_HOST").unwrap_or_else(|_| DEFAULT_METADATA_HOST.to_string());
+        Ok(TokenGrant::Metadata {
+            url: format!("http://{}/computeMetadata/v1/instance/service-accounts/default/token", host),
+            header: ("Metadata-Flavor", "Google"),
+        })
+    }
+}
+
+/// Splits a key id into the crypto key name and the optional version (`batcher` or `batcher@2`).
+pub fn parse_key_id(key_id: &str) -> AnyhowResult<(&str, Option<u64>)> {
+    let (name, version) = match key_id.split_once('@') {
+        Some((name, version)) => {
+            let version = version
+                .parse()
+                .map_err(|_| anyhow!("invalid Cloud KMS key version in {:?}", key_id))?;
+            (name, Some(version))
+        }
+        None => (key_id, None),
+    };
+
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        bail!("invalid Cloud KMS key name {:?}", key_id);
+    }
+
+    Ok((name, version))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CryptoKeyList {
+    #[serde(default)]
+    crypto_keys: Vec<CryptoKey>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CryptoKey {
+    name: String,
+    version_template: Option<VersionTemplate>,
+}
+
+#[derive(Deserialize)]
+struct VersionTemplate {
+    algorithm: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CryptoKeyVersionList {
+    #[serde(default)]
+    crypto_key_versions: Vec<CryptoKeyVersion>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CryptoKeyVersion {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct PublicKey {
+    pem: String,
+    algorithm: String,
+}
+
+#[derive(Deserialize)]
+struct AsymmetricSignature {
+    signature: String,
+}
+
+/// Cloud KMS REST client.
+struct CloudKmsClient {
+    http: reqwest::Client,
+    endpoint: String,
+    token: AccessToken,
+}
+
+impl CloudKmsClient {
+    async fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, &str)]) -> AnyhowResult<T> {
+        let request = self
+            .http
+            .get(format!("{}/v1/{}", self.endpoint, path))
+            .query(query)
+            .bearer_auth(self.token.get().await?);
+
+        send_json(request).await
+    }
+
+    /// Highest enabled version of the crypto key.
+    async fn latest_version(&self, crypto_key: &str) -> AnyhowResult<u64> {
+        let mut latest = None;
+        let mut page_token = String::new();
+
+        loop {
+            let page: CryptoKeyVersionList = self
+                .get(
+                    &format!("{}/cryptoKeyVersions", crypto_key),
+                    &[("filter", "state=ENABLED"), ("pageToken", &page_token)],
+                )
+                .await?;
+
+            for version in page.crypto_key_versions {
+                let number = version.name.rsplit('/').next().and_then(|number| number.parse().ok());
+                latest = latest.max(number);
+            }
+
+            match page.next_page_token.filter(|token| !token.is_empty()) {
+                Some(token) => page_token = token,
+                None => break,
+            }
+        }
+
+        latest.ok_or_else(|| anyhow!("{} has no enabled version", crypto_key))
+    }
+}
+
+/// A crypto key version, signing digests with `asymmetricSign`.
+struct CloudKmsKeyVersion {
+    client: Arc<CloudKmsClient>,
+    name: String,
+}
+
+#[async_trait]
+impl DigestSigner for CloudKmsKeyVersion {
+    async fn sign_digest(&self, hash: &B256) -> AnyhowResult<ecdsa::Signature> {
+        // Cloud KMS signs the digest as given: the Keccak-256 hash goes in the sha256 field
+        let request = self
+            .client
+            .http
+            .post(format!("{}/v1/{}:asymmetricSign", self.client.endpoint, self.name))
+            .bearer_auth(self.client.token.get().await?)
+            .json(&json!({ "digest": { "sha256": BASE64_STANDARD.encode(hash) } }));
+        let response: AsymmetricSignature = send_json(request)
+            .await
+            .with_context(|| format!("failed to sign with {}", self.name))?;
+
+        let der = BASE64_STANDARD
+            .decode(&response.signature)
+            .map_err(|_| anyhow!("invalid Cloud KMS signature"))?;
+        Ok(ecdsa::Signature::from_der(&der)?)
+    }
+}
+
+/// `EC_SIGN_SECP256K1_SHA256` keys of a Cloud KMS key ring, by crypto key name.
+///
+/// `batcher` signs with the highest enabled version at the time of first use, `batcher@2`
+/// with version 2. All versions share the access rules and policy of the crypto key.
+pub struct GcpKmsBackend {
+    client: Arc<CloudKmsClient>,
+    key_ring: String,
+    signers: Mutex<HashMap<String, Arc<RemoteSigner<CloudKmsKeyVersion>>>>,
+}
+
+impl GcpKmsBackend {
+    async fn get_signer(&self, key_id: &str) -> AnyhowResult<Arc<RemoteSigner<CloudKmsKeyVersion>>> {
+        let mut signers = self.signers.lock().await;
+
+        if let Some(signer) = signers.get(key_id) {
+            return Ok(signer.clone());
+        }
+
+        let (name, version) = parse_key_id(key_id)?;
+        let crypto_key = format!("{}/cryptoKeys/{}", self.key_ring, name);
+        let version = match version {
+            Some(version) => version,
+            None => self.client.latest_version(&crypto_key).await?,
+        };
+        let name = format!("{}/cryptoKeyVersions/{}", crypto_key, version);
+
+        let public_key: PublicKey = self
+            .client
+            .get(&format!("{}/publicKey", name), &[])
+            .await
+            .with_context(|| format!("failed to get the public key of {}", name))?;
+        if public_key.algorithm != ALGORITHM {
+            bail!("{} is a {} key, not {}", name, public_key.algorithm, ALGORITHM);
+        }
+        let public_key = parse_pem_public_key(&public_key.pem)?;
+
+        let key = CloudKmsKeyVersion {
+            client: self.client.clone(),
+            name,
+        };
+        let signer = Arc::new(RemoteSigner::new(key, &public_key));
+        signers.insert(key_id.to_string(), signer.clone());
+        Ok(signer)
+    }
+
+    async fn find_keys(&self) -> AnyhowResult<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut page_token = String::new();
+
+        loop {
+            let page: CryptoKeyList = self
+                .client
+                .get(&format!("{}/cryptoKeys", self.key_ring), &[("pageToken", &page_token)])
+                .await
+                .context("failed to list the Cloud KMS keys")?;
+
+            for key in page.crypto_keys {
+                if key.version_template.is_some_and(|template| template.algorithm == ALGORITHM)
+                    && let Some(name) = key.name.rsplit('/').next()
+                {
+                    keys.push(name.to_string());
+                }
+            }
+
+            match page.next_page_token.filter(|token| !token.is_empty()) {
+                Some(token) => page_token = token,
+                None => break,
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+#[async_trait]
+impl SignerBackend for GcpKmsBackend {
+    fn key_ref(&self, key_id: &str) -> AnyhowResult<KeyRef> {
+        Ok(KeyRef {
+            backend: BackendKind::GcpKms,
+            key_id: parse_key_id(key_id)?.0.to_string(),
+        })
+    }
+
+    async fn signer(&self, key_id: &str) -> AnyhowResult<Arc<dyn EthSigner>> {
+        Ok(self.get_signer(key_id).await?)
+    }
+
+    /// The secp256k1 keys of the key ring, at their highest enabled version.
+    async fn list_keys(&self) -> AnyhowResult<Vec<KeyInfo>> {
+        let mut keys = Vec::new();
+
+        for key_id in self.find_keys().await? {
+            match self.get_signer(&key_id).await {
+                Ok(signer) => keys.push(KeyInfo {
+                    address: alloy::signers::Signer::address(signer.as_ref()),
+                    key_id,
+                    label: None,
+                }),
+                Err(err) => debug!("skipping Cloud KMS key {}: {:#}", key_id, err),
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+pub fn gcp_kms_backend(opt: &GcpKmsConnOpt) -> AnyhowResult<GcpKmsBackend> {
+    opt.validate()?;
+    let key_ring = opt.key_ring.clone().unwrap_or_default();
+    let endpoint = opt
+        .endpoint
+        .as_deref()
+        .unwrap_or(DEFAULT_ENDPOINT)
+        .trim_end_matches('/')
+        .to_string();
+
+    let http = http_client()?;
+    let client = CloudKmsClient {
+        token: AccessToken::new(http.clone(), opt.grant()?),
+        http,
+        endpoint,
+    };
+    info!("using Cloud KMS key ring {} at {}", key_ring, client.endpoint);
+
+    Ok(GcpKmsBackend {
+        client: Arc::new(client),
+        key_ring,
+        signers: Mutex::new(HashMap::new()),
+    })
+}
+
+pub async fn handle_gcp_kms(opt: GcpKmsOpt) -> AnyhowResult<()> {
+    match opt.cmd {
+        GcpKmsCommand::Serve(server_opt) => {
+            let backend = gcp_kms_backend(&opt.conn)?;
+            serve_backend(Arc::new(backend), server_opt).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        primitives::{keccak256, Address},
+        signers::k256::ecdsa::{signature::hazmat::PrehashSigner, SigningKey},
+    };
+    use axum::{
+        extract::{Path, Query},
+        http::{HeaderMap, StatusCode},
+        routing::get,
+        Json, Router,
+    };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::signers::remote::{public_key_pem, serve_stand_in};
+
+    const KEY_RING: &str = "projects/test/locations/global/keyRings/test";
+
+    /// Key of version `version` of `batcher`.
+    fn version_key(version: &str) -> SigningKey {
+        SigningKey::from_slice(&[version.parse().unwrap(); 32]).unwrap()
+    }
+
+    fn version_address(version: &str) -> Address {
+        Address::from_public_key(version_key(version).verifying_key())
+    }
+
+    /// A key ring holding a symmetric key and `batcher`, listed on two pages, with `batcher` enabled at
+    /// versions 1 and 3.
+    fn stand_in() -> Router {
+        let read = |Path(path): Path<String>,
+                    Query(query): Query<HashMap<String, String>>,
+                    headers: HeaderMap| async move {
+            assert_eq!(headers["authorization"], "Bearer test");
+            let key_ring_path = path.strip_prefix(KEY_RING).ok_or(StatusCode::NOT_FOUND)?;
+
+            let body = match key_ring_path.split('/').collect::<Vec<_>>().as_slice() {
+                ["", "cryptoKeys"] => match query.get("pageToken").map(String::as_str) {
+                    Some("") => json!({
+                        "cryptoKeys": [{ "name": format!("{}/cryptoKeys/aes", KEY_RING) }],
+                        "nextPageToken": "2",
+                    }),
+                    _ => json!({
+                        "cryptoKeys": [{
+                            "name": format!("{}/cryptoKeys/batcher", KEY_RING),
+                            "versionTemplate": { "algorithm": ALGORITHM },
+                        }],
+                    }),
+                },
+                ["", "cryptoKeys", "batcher", "cryptoKeyVersions"] => {
+                    assert_eq!(query["filter"], "state=ENABLED");
+                    json!({
+                        "cryptoKeyVersions": [
+                            { "name": format!("{}/cryptoKeys/batcher/cryptoKeyVersions/1", KEY_RING) },
+                            { "name": format!("{}/cryptoKeys/batcher/cryptoKeyVersions/3", KEY_RING) },
+                        ],
+                    })
+                }
+                ["", "cryptoKeys", "batcher", "cryptoKeyVersions", version @ ("1" | "3"), "publicKey"] => json!({
+                    "pem": public_key_pem(version_key(version).verifying_key()),
+                    "algorithm": ALGORITHM,
+                }),
+                _ => return Err(StatusCode::NOT_FOUND),
+            };
+            Ok(Json(body))
+        };
+        let sign = |Path(path): Path<String>, headers: HeaderMap, Json(body): Json<Value>| async move {
+            assert_eq!(headers["authorization"], "Bearer test");
+            let Some(version) = path
+                .strip_prefix(&format!("{}/cryptoKeys/batcher/cryptoKeyVersions/", KEY_RING))
+                .and_then(|path| path.strip_suffix(":asymmetricSign"))
+            else {
+                return Err(StatusCode::NOT_FOUND);
+            };
+
+            let hash = BASE64_STANDARD.decode(body["digest"]["sha256"].as_str().unwrap()).unwrap();
+            let signature: ecdsa::Signature = version_key(version).sign_prehash(&hash).unwrap();
+            Ok(Json(json!({ "signature": BASE64_STANDARD.encode(signature.to_der()) })))
+        };
+
+        Router::new().route("/v1/*path", get(read).post(sign))
+    }
+
+    async fn backend() -> GcpKmsBackend {
+        let opt = GcpKmsConnOpt {
+            key_ring: Some(KEY_RING.to_string()),
+            endpoint: Some(serve_stand_in(stand_in()).await),
+            credentials_file: None,
+            access_token: Some("test".to_string()),
+        };
+        gcp_kms_backend(&opt).unwrap()
+    }
+
+    #[tokio::test]
+    async fn versions_share_the_key_ref() {
+        let backend = backend().await;
+
+        for key_id in ["batcher", "batcher@1", "batcher@3"] {
+            assert_eq!(backend.resolve_key_ref(key_id).await.unwrap().key_id, "batcher");
+        }
+        for key_id in ["", "batcher@", "batcher@latest", "a/b", "batcher@1/publicKey"] {
+            assert!(backend.key_ref(key_id).is_err(), "{:?}", key_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn signs_with_the_latest_or_given_version() {
+        let backend = backend().await;
+
+        let keys = backend.list_keys().await.unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!((keys[0].key_id.as_str(), keys[0].address), ("batcher", version_address("3")));
+
+        let hash = keccak256("cloud kms");
+        for (key_id, version) in [("batcher", "3"), ("batcher@1", "1")] {
+            let signature = backend.sign_hash(key_id, &hash).await.unwrap();
+            assert_eq!(signature.recover_address_from_prehash(&hash).unwrap(), version_address(version));
+        }
+        assert!(backend.signer("batcher@2").await.is_err());
+    }
+}
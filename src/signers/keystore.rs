@@ -0,0 +1,250 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use alloy::signers::local::PrivateKeySigner;
+use anyhow::{anyhow, bail, Context, Result as AnyhowResult};
+use async_trait::async_trait;
+use serde::Deserialize;
+use structopt::StructOpt;
+use tracing::info;
+
+use crate::{
+    config::{read_secret_file, ServerOpt},
+    signers::common::{serve_backend, BackendKind, EthSigner, KeyInfo, KeyRef, SignerBackend},
+};
+
+pub const PASSWORD_ENV: &str = "KEYSTORE_PASSWORD";
+
+#[derive(StructOpt)]
+pub struct KeystoreOpt {
+    #[structopt(flatten)]
+    pub conn: KeystoreConnOpt,
+
+    #[structopt(subcommand)]
+    pub cmd: KeystoreCommand,
+}
+
+/// Keystore settings, shared by the `keystore` subcommands, `serve` and the config file.
+#[derive(StructOpt, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeystoreConnOpt {
+    /// Directory of Web3 Secret Storage (geth) keystore files, served by file name without `.json`
+    #[structopt(name = "keystore-dir", long = "keystore-dir", env = "KEYSTORE_DIR", parse(from_os_str))]
+    pub dir: Option<PathBuf>,
+
+    /// File holding the password of the keystore files
+    #[structopt(name = "keystore-password-file", long = "keystore-password-file", env = "KEYSTORE_PASSWORD_FILE", parse(from_os_str))]
+    pub password_file: Option<PathBuf>,
+
+    /// Password of the keystore files, in the config file only; on the command line it would show up in `ps`,
+    /// use `--keystore-password-file` or `KEYSTORE_PASSWORD` instead
+    #[structopt(skip)]
+    pub password: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub enum KeystoreCommand {
+    Serve(ServerOpt),
+}
+
+impl KeystoreConnOpt {
+    /// Checks that a directory and a password source are given, without decrypting anything.
+    pub fn validate(&self) -> AnyhowResult<()> {
+        if self.dir.is_none() {
+            bail!("the keystore directory is required");
+        }
+        if self.password_file.is_none() && self.password.is_none() && env::var_os(PASSWORD_ENV).is_none() {
+            bail!("the keystore password is required, from password_file or {}", PASSWORD_ENV);
+        }
+
+        Ok(())
+    }
+
+    /// Reads the password from `password_file`, `password` or `KEYSTORE_PASSWORD`, in that order.
+    fn password(&self) -> AnyhowResult<String> {
+        if let Some(path) = &self.password_file {
+            return read_secret_file(path);
+        }
+
+        match &self.password {
+            Some(password) => Ok(password.clone()),
+            None => env::var(PASSWORD_ENV).map_err(|_| {
+                anyhow!("the keystore password is required, from password_file or {}", PASSWORD_ENV)
+            }),
+        }
+    }
+}
+
+/// Keys decrypted from keystore files at startup, kept in memory.
+pub struct KeystoreBackend {
+    signers: BTreeMap<String, Arc<PrivateKeySigner>>,
+}
+
+#[async_trait]
+impl SignerBackend for KeystoreBackend {
+    fn key_ref(&self, key_id: &str) -> AnyhowResult<KeyRef> {
+        Ok(KeyRef {
+            backend: BackendKind::Keystore,
+            key_id: key_id.to_string(),
+        })
+    }
+
+    async fn signer(&self, key_id: &str) -> AnyhowResult<Arc<dyn EthSigner>> {
+        let signer = self
+            .signers
+            .get(key_id)
+            .ok_or_else(|| anyhow!("unknown keystore key: {}", key_id))?;
+
+        Ok(signer.clone())
+    }
+
+    async fn list_keys(&self) -> AnyhowResult<Vec<KeyInfo>> {
+        Ok(self
+            .signers
+            .iter()
+            .map(|(key_id, signer)| KeyInfo {
+                key_id: key_id.clone(),
+                label: None,
+                address: signer.address(),
+            })
+            .collect())
+    }
+}
+
+/// Key id of a keystore file: its name without the `.json` extension. Hidden files and directories have none.
+fn keystore_key_id(path: &Path) -> Option<String> {
+    if !path.is_file() {
+        return None;
+    }
+
+    let name = path.file_name()?.to_str()?;
+    if name.starts_with('.') {
+        return None;
+    }
+
+    Some(name.strip_suffix(".json").unwrap_or(name).to_string())
+}
+
+/// Decrypts every keystore file of the directory with the configured password.
+///
+/// Fails on the first file that cannot be decrypted, rather than serving a partial set of keys.
+pub fn keystore_backend(opt: &KeystoreConnOpt) -> AnyhowResult<KeystoreBackend> {
+    let dir = opt
+        .dir
+        .as_deref()
+        .ok_or_else(|| anyhow!("the keystore directory is required"))?;
+    let password = opt.password()?;
+
+    let mut signers = BTreeMap::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to read keystore directory {}", dir.display()))? {
+        let path = entry?.path();
+        let Some(key_id) = keystore_key_id(&path) else {
+            continue;
+        };
+        if signers.contains_key(&key_id) {
+            bail!("{} holds both {} and {}.json, which would be the same key", dir.display(), key_id, key_id);
+        }
+
+        let signer = PrivateKeySigner::decrypt_keystore(&path, &password)
+            .with_context(|| format!("failed to decrypt keystore {}", path.display()))?;
+        info!("loaded keystore key {} ({})", key_id, signer.address());
+        signers.insert(key_id, Arc::new(signer));
+    }
+
+    if signers.is_empty() {
+        bail!("no keystore files in {}", dir.display());
+    }
+
+    Ok(KeystoreBackend { signers })
+}
+
+pub async fn handle_keystore(opt: KeystoreOpt) -> AnyhowResult<()> {
+    match opt.cmd {
+        KeystoreCommand::Serve(server_opt) => {
+            let backend = keystore_backend(&opt.conn)?;
+            serve_backend(Arc::new(backend), server_opt).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        primitives::{b256, keccak256, Address, B256},
+        signers::k256::elliptic_curve::rand_core::OsRng,
+    };
+    use tempfile::TempDir;
+
+    use super::*;
+
+    const BATCHER_KEY: B256 = b256!("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318");
+    const PROPOSER_KEY: B256 = b256!("25b1759e8eabc06b7d097550dffd7d8c92407fb818c5e9e33b81ef92d4afa2b7");
+
+    /// Writes `key` encrypted with `password` to `dir/name`, returning its address.
+    fn write_keystore(dir: &TempDir, name: &str, key: B256, password: &str) -> Address {
+        let (signer, _) =
+            PrivateKeySigner::encrypt_keystore(dir.path(), &mut OsRng, key, password, Some(name)).unwrap();
+        signer.address()
+    }
+
+    fn opt(dir: &TempDir, password: &str) -> KeystoreConnOpt {
+        KeystoreConnOpt {
+            dir: Some(dir.path().to_path_buf()),
+            password_file: None,
+            password: Some(password.to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn loads_lists_and_signs() {
+        let dir = TempDir::new().unwrap();
+        let batcher = write_keystore(&dir, "batcher.json", BATCHER_KEY, "secret");
+        let proposer = write_keystore(&dir, "proposer", PROPOSER_KEY, "secret");
+        // Editor and sync leftovers, and directories, are not keystores
+        fs::write(dir.path().join(".batcher.json.swp"), "not a keystore").unwrap();
+        fs::create_dir(dir.path().join("old.json")).unwrap();
+
+        let backend = keystore_backend(&opt(&dir, "secret")).unwrap();
+        let keys = backend.list_keys().await.unwrap();
+        let keys: Vec<_> = keys.iter().map(|key| (key.key_id.as_str(), key.address)).collect();
+        assert_eq!(keys, [("batcher", batcher), ("proposer", proposer)]);
+
+        let hash = keccak256("keystore");
+        let signature = backend.sign_hash("proposer", &hash).await.unwrap();
+        assert_eq!(signature.recover_address_from_prehash(&hash).unwrap(), proposer);
+        assert!(backend.sign_hash("proposer.json", &hash).await.is_err());
+    }
+
+    #[test]
+    fn rejects_a_wrong_password() {
+        let dir = TempDir::new().unwrap();
+        write_keystore(&dir, "batcher.json", BATCHER_KEY, "secret");
+
+        let err = keystore_backend(&opt(&dir, "wrong")).err().unwrap();
+        assert!(format!("{:#}", err).contains("failed to decrypt keystore"), "{:#}", err);
+    }
+
+    #[test]
+    fn rejects_files_with_the_same_key_id() {
+        let dir = TempDir::new().unwrap();
+        write_keystore(&dir, "batcher.json", BATCHER_KEY, "secret");
+        write_keystore(&dir, "batcher", PROPOSER_KEY, "secret");
+
+        let err = keystore_backend(&opt(&dir, "secret")).err().unwrap();
+        assert!(err.to_string().contains("both batcher and batcher.json"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_a_directory_without_keystores() {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join(".hidden"), "not a keystore").unwrap();
+
+        assert!(keystore_backend(&opt(&dir, "secret")).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::signers::yubihsm::AppState;
+use crate::signers::yubihsm::{YubiBackend, YubiKey};
 use alloy::primitives::hex;
 use alloy::{signers::local::yubihsm::Domain, signers::local::YubiSigner};
 use anyhow::Result as AnyhowResult;
@@ -19,10 +19,10 @@ pub const MOCK_KEYS: &[(u16, [u8; 32], &str)] = &[
 ];
 
 pub async fn add_mock_signers(
-    state: Arc<AppState>,
+    backend: &YubiBackend,
     keys: Vec<(u16, [u8; 32], String)>,
 ) -> AnyhowResult<()> {
-    let mut signers = state.signers.lock().await;
+    let mut signers = backend.signers.lock().await;
 
     let keys_to_use = if keys.is_empty() {
         MOCK_KEYS
@@ -35,16 +35,16 @@ pub async fn add_mock_signers(
 
     for (key_id, private_key, _address) in keys_to_use {
         let yubi_signer = 
-        Arc::new(
+        Arc::new(YubiKey::signer(
             YubiSigner::from_key(
-                state.connector.clone(),
-                state.credentials.clone(),
+                backend.connector.clone(),
+                backend.credentials.clone(),
                 key_id,
                 "".into(),
                 Domain::all(),
                 private_key,
             )?
-        );
+        )?);
 
         signers.insert(key_id, yubi_signer.clone());
     }
@@ -1,5 +1,13 @@
 pub mod aws_kms;
+pub mod azure_key_vault;
+pub mod catalog;
+pub mod cloud;
 pub mod common;
-#[cfg(debug_assertions)]
+pub mod gcp_kms;
+pub mod keystore;
+#[cfg(feature = "mock")]
 pub mod mock;
+pub mod pkcs11;
+pub mod remote;
+pub mod vault;
 pub mod yubihsm;
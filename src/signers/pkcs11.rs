@@ -0,0 +1,405 @@
+use std::{
+    collections::HashMap,
+    env,
+    path::PathBuf,
+    sync::{Arc, Mutex as SyncMutex, MutexGuard},
+};
+
+use alloy::{
+    hex,
+    primitives::B256,
+    signers::k256::ecdsa::{self, VerifyingKey},
+};
+use anyhow::{anyhow, bail, Context, Result as AnyhowResult};
+use async_trait::async_trait;
+use cryptoki::{
+    context::{CInitializeArgs, Pkcs11},
+    mechanism::Mechanism,
+    object::{Attribute, AttributeType, KeyType, ObjectClass, ObjectHandle},
+    session::{Session, UserType},
+    types::AuthPin,
+};
+use serde::Deserialize;
+use structopt::StructOpt;
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+use crate::{
+    config::{read_secret_file, ServerOpt},
+    signers::{
+        common::{serve_backend, BackendKind, EthSigner, KeyInfo, KeyRef, SignerBackend},
+        remote::{parse_sec1_public_key, DigestSigner, RemoteSigner},
+    },
+};
+
+const PIN_ENV: &str = "PKCS11_PIN";
+
+/// DER encoded OID of the secp256k1 curve, as found in `CKA_EC_PARAMS`
+const SECP256K1_EC_PARAMS: &[u8] = &[0x06, 0x05, 0x2b, 0x81, 0x04, 0x00, 0x0a];
+
+#[derive(StructOpt)]
+pub struct Pkcs11Opt {
+    #[structopt(flatten)]
+    pub conn: Pkcs11ConnOpt,
+
+    #[structopt(subcommand)]
+    pub cmd: Pkcs11Command,
+}
+
+/// PKCS#11 settings, shared by the `pkcs11` subcommands, `serve` and the config file.
+#[derive(StructOpt, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Pkcs11ConnOpt {
+    /// PKCS#11 module of the HSM (e.g. /usr/lib/softhsm/libsofthsm2.so)
+    #[structopt(name = "pkcs11-module", long = "pkcs11-module", env = "PKCS11_MODULE", parse(from_os_str))]
+    pub module: Option<PathBuf>,
+
+    /// Label of the token holding the keys; the first token found if omitted
+    #[structopt(name = "pkcs11-token", long = "pkcs11-token", env = "PKCS11_TOKEN")]
+    pub token: Option<String>,
+
+    /// File holding the user PIN of the token
+    #[structopt(name = "pkcs11-pin-file", long = "pkcs11-pin-file", env = "PKCS11_PIN_FILE", parse(from_os_str))]
+    pub pin_file: Option<PathBuf>,
+
+    /// User PIN of the token, in the config file only; on the command line it would show up in `ps`,
+    /// use `--pkcs11-pin-file` or `PKCS11_PIN` instead
+    #[structopt(skip)]
+    pub pin: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub enum Pkcs11Command {
+    Serve(ServerOpt),
+}
+
+impl Pkcs11ConnOpt {
+    /// Checks that a module and a PIN source are given, without loading the module.
+    pub fn validate(&self) -> AnyhowResult<()> {
+        if self.module.is_none() {
+            bail!("the PKCS#11 module is required");
+        }
+        if self.pin_file.is_none() && self.pin.is_none() && env::var_os(PIN_ENV).is_none() {
+            bail!("the PKCS#11 user PIN is required, from pin_file or {}", PIN_ENV);
+        }
+
+        Ok(())
+    }
+
+    /// Reads the PIN from `pin_file`, `pin` or `PKCS11_PIN`, in that order.
+    fn pin(&self) -> AnyhowResult<String> {
+        if let Some(path) = &self.pin_file {
+            return read_secret_file(path);
+        }
+
+        match &self.pin {
+            Some(pin) => Ok(pin.clone()),
+            None => env::var(PIN_ENV)
+                .map_err(|_| anyhow!("the PKCS#11 user PIN is required, from pin_file or {}", PIN_ENV)),
+        }
+    }
+}
+
+/// A private key of the token, signing with `CKM_ECDSA` on the shared session.
+struct Pkcs11Key {
+    session: Arc<SyncMutex<Session>>,
+    handle: ObjectHandle,
+}
+
+#[async_trait]
+impl DigestSigner for Pkcs11Key {
+    async fn sign_digest(&self, hash: &B256) -> AnyhowResult<ecdsa::Signature> {
+        let (session, handle, hash) = (self.session.clone(), self.handle, *hash);
+        let signature = tokio::task::spawn_blocking(move || -> AnyhowResult<_> {
+            Ok(lock(&session)?.sign(&Mechanism::Ecdsa, handle, hash.as_slice())?)
+        })
+        .await??;
+        // CKM_ECDSA returns r and s as two 32-byte big-endian integers
+        Ok(ecdsa::Signature::from_slice(&signature)?)
+    }
+}
+
+type Pkcs11Signer = RemoteSigner<Pkcs11Key>;
+
+fn lock(session: &SyncMutex<Session>) -> AnyhowResult<MutexGuard<'_, Session>> {
+    session.lock().map_err(|_| anyhow!("the PKCS#11 session is poisoned"))
+}
+
+/// secp256k1 keys of a PKCS#11 token, looked up by `CKA_LABEL`, or by `CKA_ID` given as `0x` hex.
+///
+/// A key is known by its label when it has one and by its id otherwise, whichever it was asked for, so that
+/// both share its access rules and policy.
+pub struct Pkcs11Backend {
+    session: Arc<SyncMutex<Session>>,
+    /// By the label or id they were asked for, with their canonical key id
+    signers: Mutex<HashMap<String, (String, Arc<Pkcs11Signer>)>>,
+}
+
+/// A key pair found on the token.
+struct TokenKey {
+    key_id: String,
+    handle: ObjectHandle,
+    public_key: VerifyingKey,
+}
+
+/// Checks that `key_id` is a label or a `0x` hex id, without looking it up.
+pub fn check_key_id(key_id: &str) -> AnyhowResult<()> {
+    if key_id.is_empty() {
+        bail!("the PKCS#11 key id is empty");
+    }
+
+    key_template(ObjectClass::PRIVATE_KEY, key_id).map(|_| ())
+}
+
+/// Template matching the objects of `class` for `key_id`.
+fn key_template(class: ObjectClass, key_id: &str) -> AnyhowResult<Vec<Attribute>> {
+    let key = match key_id.strip_prefix("0x") {
+        Some(id) => Attribute::Id(hex::decode(id).map_err(|_| anyhow!("invalid PKCS#11 key id: {}", key_id))?),
+        None => Attribute::Label(key_id.as_bytes().to_vec()),
+    };
+
+    Ok(vec![Attribute::Class(class), Attribute::KeyType(KeyType::EC), key])
+}
+
+fn find_one(session: &Session, template: &[Attribute], key_id: &str, kind: &str) -> AnyhowResult<ObjectHandle> {
+    match session.find_objects(template)?.as_slice() {
+        [handle] => Ok(*handle),
+        [] => bail!("no PKCS#11 {} found for {}", kind, key_id),
+        _ => bail!("several PKCS#11 {}s match {}", kind, key_id),
+    }
+}
+
+/// Key id of a key with `label` and `id`: its label, or its id as `0x` hex when it has none.
+fn canonical_key_id(label: &[u8], id: &[u8]) -> Option<String> {
+    match (label.is_empty(), id.is_empty()) {
+        (false, _) => Some(String::from_utf8_lossy(label).into_owned()),
+        (true, false) => Some(hex::encode_prefixed(id)),
+        (true, true) => None,
+    }
+}
+
+fn read_label_and_id(session: &Session, handle: ObjectHandle) -> AnyhowResult<(Vec<u8>, Vec<u8>)> {
+    let (mut label, mut id) = (Vec::new(), Vec::new());
+    for attribute in session.get_attributes(handle, &[AttributeType::Label, AttributeType::Id])? {
+        match attribute {
+            Attribute::Label(value) => label = value,
+            Attribute::Id(value) => id = value,
+            _ => {}
+        }
+    }
+
+    Ok((label, id))
+}
+
+/// Finds the private and public key objects for `key_id`.
+fn find_key(session: &Session, key_id: &str) -> AnyhowResult<TokenKey> {
+    let private = key_template(ObjectClass::PRIVATE_KEY, key_id)?;
+    let public = key_template(ObjectClass::PUBLIC_KEY, key_id)?;
+    let handle = find_one(session, &private, key_id, "private key")?;
+    let public = find_one(session, &public, key_id, "public key")?;
+
+    let (label, id) = read_label_and_id(session, handle)?;
+    Ok(TokenKey {
+        // Found by label or id, so it has at least one of them
+        key_id: canonical_key_id(&label, &id).unwrap_or_else(|| key_id.to_string()),
+        handle,
+        public_key: read_public_key(session, public)?,
+    })
+}
+
+fn read_public_key(session: &Session, handle: ObjectHandle) -> AnyhowResult<VerifyingKey> {
+    let (mut params, mut point) = (None, None);
+    for attribute in session.get_attributes(handle, &[AttributeType::EcParams, AttributeType::EcPoint])? {
+        match attribute {
+            Attribute::EcParams(value) => params = Some(value),
+            Attribute::EcPoint(value) => point = Some(value),
+            _ => {}
+        }
+    }
+
+    if params.as_deref() != Some(SECP256K1_EC_PARAMS) {
+        bail!("not a secp256k1 key");
+    }
+    let point = point.ok_or_else(|| anyhow!("the public key has no EC point"))?;
+
+    // CKA_EC_POINT should be a DER OCTET STRING, but some modules return the bare point
+    match point.as_slice() {
+        [0x04, len, inner @ ..] if *len as usize == inner.len() => {
+            parse_sec1_public_key(inner).or_else(|_| parse_sec1_public_key(&point))
+        }
+        _ => parse_sec1_public_key(&point),
+    }
+}
+
+impl Pkcs11Backend {
+    /// The canonical key id of `key_id` and its signer.
+    async fn get_signer(&self, key_id: &str) -> AnyhowResult<(String, Arc<Pkcs11Signer>)> {
+        let mut signers = self.signers.lock().await;
+
+        if let Some(found) = signers.get(key_id) {
+            return Ok(found.clone());
+        }
+
+        check_key_id(key_id)?;
+        let (session, owned_key_id) = (self.session.clone(), key_id.to_string());
+        let found = tokio::task::spawn_blocking(move || find_key(&*lock(&session)?, &owned_key_id)).await??;
+
+        let key = Pkcs11Key {
+            session: self.session.clone(),
+            handle: found.handle,
+        };
+        let signer = Arc::new(RemoteSigner::new(key, &found.public_key));
+        signers.insert(key_id.to_string(), (found.key_id.clone(), signer.clone()));
+        Ok((found.key_id, signer))
+    }
+}
+
+#[async_trait]
+impl SignerBackend for Pkcs11Backend {
+    fn key_ref(&self, key_id: &str) -> AnyhowResult<KeyRef> {
+        check_key_id(key_id)?;
+        Ok(KeyRef {
+            backend: BackendKind::Pkcs11,
+            key_id: key_id.to_string(),
+        })
+    }
+
+    async fn resolve_key_ref(&self, key_id: &str) -> AnyhowResult<KeyRef> {
+        Ok(KeyRef {
+            backend: BackendKind::Pkcs11,
+            key_id: self.get_signer(key_id).await?.0,
+        })
+    }
+
+    async fn signer(&self, key_id: &str) -> AnyhowResult<Arc<dyn EthSigner>> {
+        Ok(self.get_signer(key_id).await?.1)
+    }
+
+    /// The secp256k1 private keys of the token, by label, or by id when they have none.
+    async fn list_keys(&self) -> AnyhowResult<Vec<KeyInfo>> {
+        let session = self.session.clone();
+        let key_ids = tokio::task::spawn_blocking(move || -> AnyhowResult<_> {
+            let session = lock(&session)?;
+            let template = [Attribute::Class(ObjectClass::PRIVATE_KEY), Attribute::KeyType(KeyType::EC)];
+
+            let mut key_ids = Vec::new();
+            for handle in session.find_objects(&template)? {
+                let (label, id) = read_label_and_id(&session, handle)?;
+                match canonical_key_id(&label, &id) {
+                    Some(key_id) => key_ids.push((key_id.clone(), (!label.is_empty()).then_some(key_id))),
+                    None => debug!("skipping PKCS#11 key without label nor id"),
+                }
+            }
+            Ok(key_ids)
+        })
+        .await??;
+
+        let mut keys = Vec::with_capacity(key_ids.len());
+        for (key_id, label) in key_ids {
+            // Other curves are expected on a shared token, so they are only logged in debug
+            match self.get_signer(&key_id).await {
+                Ok((_, signer)) => keys.push(KeyInfo {
+                    address: alloy::signers::Signer::address(signer.as_ref()),
+                    key_id,
+                    label,
+                }),
+                Err(err) => debug!("skipping PKCS#11 key {}: {:#}", key_id, err),
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+/// Loads the module, opens a session on the token and logs in as user.
+pub fn pkcs11_backend(opt: &Pkcs11ConnOpt) -> AnyhowResult<Pkcs11Backend> {
+    let module = opt
+        .module
+        .as_ref()
+        .ok_or_else(|| anyhow!("the PKCS#11 module is required"))?;
+    let pkcs11 = Pkcs11::new(module)
+        .with_context(|| format!("failed to load PKCS#11 module {}", module.display()))?;
+    pkcs11.initialize(CInitializeArgs::OsThreads)?;
+
+    let mut slot = None;
+    for candidate in pkcs11.get_slots_with_token()? {
+        let label = pkcs11.get_token_info(candidate)?.label().trim_end().to_string();
+        if opt.token.as_ref().is_none_or(|token| *token == label) {
+            slot = Some((candidate, label));
+            break;
+        }
+    }
+    let (slot, label) = slot.ok_or_else(|| match &opt.token {
+        Some(token) => anyhow!("no PKCS#11 token labeled {:?}", token),
+        None => anyhow!("no PKCS#11 token found"),
+    })?;
+
+    let session = pkcs11.open_ro_session(slot)?;
+    session
+        .login(UserType::User, Some(&AuthPin::new(opt.pin()?)))
+        .with_context(|| format!("failed to log in to PKCS#11 token {:?}", label))?;
+    info!("logged in to PKCS#11 token {:?} (slot {})", label, slot.id());
+
+    Ok(Pkcs11Backend {
+        session: Arc::new(SyncMutex::new(session)),
+        signers: Mutex::new(HashMap::new()),
+    })
+}
+
+pub async fn handle_pkcs11(opt: Pkcs11Opt) -> AnyhowResult<()> {
+    match opt.cmd {
+        Pkcs11Command::Serve(server_opt) => {
+            let backend = pkcs11_backend(&opt.conn)?;
+            serve_backend(Arc::new(backend), server_opt).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::keccak256;
+
+    use super::*;
+
+    #[test]
+    fn canonical_key_ids() {
+        assert_eq!(canonical_key_id(b"batcher", &[1]).as_deref(), Some("batcher"));
+        assert_eq!(canonical_key_id(b"batcher", &[]).as_deref(), Some("batcher"));
+        assert_eq!(canonical_key_id(b"", &[0, 1]).as_deref(), Some("0x0001"));
+        assert_eq!(canonical_key_id(b"", &[]), None);
+    }
+
+    #[test]
+    fn key_templates() {
+        let by_id = key_template(ObjectClass::PRIVATE_KEY, "0x01").unwrap();
+        assert!(matches!(&by_id[2], Attribute::Id(id) if *id == [1]));
+        let by_label = key_template(ObjectClass::PRIVATE_KEY, "batcher").unwrap();
+        assert!(matches!(&by_label[2], Attribute::Label(label) if label == b"batcher"));
+        assert!(key_template(ObjectClass::PRIVATE_KEY, "0xzz").is_err());
+    }
+
+    /// Runs against the SoftHSM2 token of the README:
+    /// `PKCS11_MODULE=/usr/lib/softhsm/libsofthsm2.so PKCS11_TOKEN=signer PKCS11_PIN=5678 cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore = "needs a SoftHSM2 token with the batcher key"]
+    async fn softhsm_batcher_key() {
+        let opt = Pkcs11ConnOpt {
+            module: env::var_os("PKCS11_MODULE").map(PathBuf::from),
+            token: env::var("PKCS11_TOKEN").ok(),
+            pin_file: None,
+            pin: None,
+        };
+        let backend = pkcs11_backend(&opt).unwrap();
+
+        assert_eq!(backend.resolve_key_ref("batcher").await.unwrap().key_id, "batcher");
+        assert_eq!(backend.resolve_key_ref("0x01").await.unwrap().key_id, "batcher");
+
+        let keys = backend.list_keys().await.unwrap();
+        let key = keys.iter().find(|key| key.key_id == "batcher").unwrap();
+        let hash = keccak256("softhsm");
+        let signature = backend.sign_hash("0x01", &hash).await.unwrap();
+        assert_eq!(signature.recover_address_from_prehash(&hash).unwrap(), key.address);
+    }
+}
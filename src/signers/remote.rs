@@ -0,0 +1,227 @@
+use alloy::{
+    consensus::SignableTransaction,
+    network::TxSigner,
+    primitives::{Address, ChainId, Signature, B256},
+    signers::{
+        k256::{
+            ecdsa::{self, VerifyingKey},
+            pkcs8::DecodePublicKey,
+        },
+        Signer,
+    },
+};
+use anyhow::{anyhow, bail, Result as AnyhowResult};
+use async_trait::async_trait;
+use base64::{prelude::BASE64_STANDARD, Engine};
+
+/// A key held by an HSM or a remote service, that signs digests without knowing about Ethereum.
+#[async_trait]
+pub trait DigestSigner: Send + Sync {
+    /// Signs the 32-byte `hash` as is, without hashing it again.
+    async fn sign_digest(&self, hash: &B256) -> AnyhowResult<ecdsa::Signature>;
+}
+
+/// Turns the signatures of a [`DigestSigner`] into Ethereum signatures.
+///
+/// The services only return (r, s): s is normalized to the lower half of the curve order, as
+/// Ethereum requires, and the recovery id is found by recovering the address with both parities.
+pub struct RemoteSigner<T> {
+    key: T,
+    address: Address,
+    chain_id: Option<ChainId>,
+}
+
+impl<T: DigestSigner> RemoteSigner<T> {
+    pub fn new(key: T, public_key: &VerifyingKey) -> Self {
+        Self {
+            key,
+            address: Address::from_public_key(public_key),
+            chain_id: None,
+        }
+    }
+
+    async fn sign_eth(&self, hash: &B256) -> AnyhowResult<Signature> {
+        let signature = self.key.sign_digest(hash).await?;
+        let signature = signature.normalize_s().unwrap_or(signature);
+
+        for parity in [false, true] {
+            let candidate = Signature::from_signature_and_parity(signature, parity);
+            if candidate.recover_address_from_prehash(hash).ok() == Some(self.address) {
+                return Ok(candidate);
+            }
+        }
+
+        bail!("signature does not recover to {}", self.address)
+    }
+}
+
+#[async_trait]
+impl<T: DigestSigner> Signer for RemoteSigner<T> {
+    async fn sign_hash(&self, hash: &B256) -> alloy::signers::Result<Signature> {
+        self.sign_eth(hash).await.map_err(alloy::signers::Error::other)
+    }
+
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    fn chain_id(&self) -> Option<ChainId> {
+        self.chain_id
+    }
+
+    fn set_chain_id(&mut self, chain_id: Option<ChainId>) {
+        self.chain_id = chain_id;
+    }
+}
+
+#[async_trait]
+impl<T: DigestSigner> TxSigner<Signature> for RemoteSigner<T> {
+    fn address(&self) -> Address {
+        self.address
+    }
+
+    async fn sign_transaction(
+        &self,
+        tx: &mut dyn SignableTransaction<Signature>,
+    ) -> alloy::signers::Result<Signature> {
+        if let Some(chain_id) = self.chain_id
+            && !tx.set_chain_id_checked(chain_id)
+        {
+            return Err(alloy::signers::Error::TransactionChainIdMismatch {
+                signer: chain_id,
+                tx: tx.chain_id().unwrap_or_default(),
+            });
+        }
+
+        self.sign_hash(&tx.signature_hash()).await
+    }
+}
+
+/// Parses a secp256k1 public key given as an uncompressed or compressed SEC1 point.
+pub fn parse_sec1_public_key(point: &[u8]) -> AnyhowResult<VerifyingKey> {
+    VerifyingKey::from_sec1_bytes(point).map_err(|_| anyhow!("not a secp256k1 public key"))
+}
+
+/// Parses a PEM encoded `PUBLIC KEY` (SubjectPublicKeyInfo) holding a secp256k1 key.
+pub fn parse_pem_public_key(pem: &str) -> AnyhowResult<VerifyingKey> {
+    let body: String = pem
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let der = BASE64_STANDARD
+        .decode(body)
+        .map_err(|_| anyhow!("invalid PEM public key"))?;
+
+    VerifyingKey::from_public_key_der(&der).map_err(|_| anyhow!("not a secp256k1 public key"))
+}
+
+/// Serves `router` on a local port, standing in for a signing service, and returns its base URL.
+#[cfg(test)]
+pub async fn serve_stand_in(router: axum::Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+    format!("http://{}", address)
+}
+
+/// `key` as a DER encoded SubjectPublicKeyInfo, like KMS returns it.
+#[cfg(test)]
+pub fn public_key_der(key: &VerifyingKey) -> Vec<u8> {
+    // SubjectPublicKeyInfo header of an uncompressed secp256k1 point
+    let mut der = alloy::hex::decode("3056301006072a8648ce3d020106052b8104000a034200").unwrap();
+    der.extend_from_slice(key.to_encoded_point(false).as_bytes());
+    der
+}
+
+/// `key` as a PEM encoded `PUBLIC KEY`, like the services return it.
+#[cfg(test)]
+pub fn public_key_pem(key: &VerifyingKey) -> String {
+    let der = BASE64_STANDARD.encode(public_key_der(key));
+    format!("-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----\n", der)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        primitives::{b256, keccak256},
+        signers::{
+            k256::ecdsa::{signature::hazmat::PrehashSigner, SigningKey},
+            local::PrivateKeySigner,
+        },
+    };
+
+    use super::*;
+
+    const KEY: B256 = b256!("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318");
+
+    /// Signs locally, returning the high-S form of the signatures if `high_s`, like some HSMs do.
+    struct LocalKey {
+        key: SigningKey,
+        high_s: bool,
+    }
+
+    #[async_trait]
+    impl DigestSigner for LocalKey {
+        async fn sign_digest(&self, hash: &B256) -> AnyhowResult<ecdsa::Signature> {
+            let signature: ecdsa::Signature = self.key.sign_prehash(hash.as_slice())?;
+            match self.high_s {
+                true => Ok(ecdsa::Signature::from_scalars(signature.r(), -signature.s())?),
+                false => Ok(signature),
+            }
+        }
+    }
+
+    fn remote_signer(key: B256, high_s: bool) -> RemoteSigner<LocalKey> {
+        let key = SigningKey::from_slice(key.as_slice()).unwrap();
+        let public_key = *key.verifying_key();
+        RemoteSigner::new(LocalKey { key, high_s }, &public_key)
+    }
+
+    /// The signatures of the first `count` test hashes.
+    async fn sign_all(signer: &impl Signer, count: u8) -> Vec<Signature> {
+        let mut signatures = Vec::new();
+        for i in 0..count {
+            signatures.push(signer.sign_hash(&keccak256([i])).await.unwrap());
+        }
+        signatures
+    }
+
+    #[tokio::test]
+    async fn matches_local_signatures() {
+        let local = PrivateKeySigner::from_slice(KEY.as_slice()).unwrap();
+        let expected = sign_all(&local, 16).await;
+
+        for high_s in [false, true] {
+            let remote = remote_signer(KEY, high_s);
+            assert_eq!(Signer::address(&remote), local.address());
+            assert_eq!(sign_all(&remote, 16).await, expected, "high_s: {}", high_s);
+        }
+
+        // Both recovery ids were found
+        let parities: Vec<u8> = expected.iter().map(|signature| signature.as_bytes()[64]).collect();
+        assert!(parities.contains(&27) && parities.contains(&28));
+    }
+
+    #[tokio::test]
+    async fn normalizes_s() {
+        let remote = remote_signer(KEY, true);
+
+        for (i, signature) in sign_all(&remote, 8).await.into_iter().enumerate() {
+            assert!(signature.to_k256().unwrap().normalize_s().is_none());
+            let hash = keccak256([i as u8]);
+            assert_eq!(signature.recover_address_from_prehash(&hash).unwrap(), Signer::address(&remote));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_signatures_of_another_key() {
+        let other = SigningKey::from_slice(&[1; 32]).unwrap();
+        let public_key = *SigningKey::from_slice(KEY.as_slice()).unwrap().verifying_key();
+        let remote = RemoteSigner::new(LocalKey { key: other, high_s: false }, &public_key);
+
+        let err = remote.sign_eth(&keccak256([0])).await.unwrap_err();
+        assert!(err.to_string().contains("does not recover"), "{}", err);
+    }
+}
@@ -0,0 +1,700 @@
+use std::{
+    collections::HashMap,
+    env, fmt,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use alloy::{
+    primitives::B256,
+    signers::k256::ecdsa::{self, VerifyingKey},
+};
+use anyhow::{anyhow, bail, Context, Result as AnyhowResult};
+use async_trait::async_trait;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use reqwest::{Method, RequestBuilder, StatusCode, Url};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use structopt::StructOpt;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::{
+    config::{read_secret_file, ServerOpt},
+    signers::{
+        common::{serve_backend, BackendKind, EthSigner, KeyInfo, KeyRef, SignerBackend},
+        remote::{parse_pem_public_key, DigestSigner, RemoteSigner},
+    },
+};
+
+const TOKEN_ENV: &str = "VAULT_TOKEN";
+const SECRET_ID_ENV: &str = "VAULT_SECRET_ID";
+const DEFAULT_MOUNT: &str = "transit";
+const DEFAULT_APPROLE_MOUNT: &str = "approle";
+const KEY_TYPE: &str = "ecdsa-p256k1";
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+/// Delay before retrying a failed renewal or login
+const RENEW_RETRY_SECS: u64 = 10;
+
+#[derive(StructOpt)]
+pub struct VaultOpt {
+    #[structopt(flatten)]
+    pub conn: VaultConnOpt,
+
+    #[structopt(subcommand)]
+    pub cmd: VaultCommand,
+}
+
+/// Vault settings, shared by the `vault` subcommands, `serve` and the config file.
+#[derive(StructOpt, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VaultConnOpt {
+    /// Address of the Vault or OpenBao server (e.g. http://127.0.0.1:8200)
+    #[structopt(name = "vault-addr", long = "vault-addr", env = "VAULT_ADDR")]
+    pub address: Option<String>,
+
+    /// Mount path of the transit secrets engine [default: transit]
+    #[structopt(name = "vault-mount", long = "vault-mount", env = "VAULT_TRANSIT_MOUNT")]
+    pub mount: Option<String>,
+
+    /// Namespace of the transit engine (Vault Enterprise, OpenBao)
+    #[structopt(name = "vault-namespace", long = "vault-namespace", env = "VAULT_NAMESPACE")]
+    pub namespace: Option<String>,
+
+    /// File holding the Vault token
+    #[structopt(name = "vault-token-file", long = "vault-token-file", parse(from_os_str))]
+    pub token_file: Option<PathBuf>,
+
+    /// Vault token, used unless an AppRole is configured, in the config file only; on the command line it would
+    /// show up in `ps`, use `--vault-token-file` or `VAULT_TOKEN` instead
+    #[structopt(skip)]
+    pub token: Option<String>,
+
+    /// AppRole role id; logs in with the AppRole instead of a token
+    #[structopt(name = "vault-role-id", long = "vault-role-id", env = "VAULT_ROLE_ID")]
+    pub role_id: Option<String>,
+
+    /// File holding the AppRole secret id
+    #[structopt(name = "vault-secret-id-file", long = "vault-secret-id-file", parse(from_os_str))]
+    pub secret_id_file: Option<PathBuf>,
+
+    /// AppRole secret id, in the config file only; on the command line it would show up in `ps`,
+    /// use `--vault-secret-id-file` or `VAULT_SECRET_ID` instead
+    #[structopt(skip)]
+    pub secret_id: Option<String>,
+
+    /// Mount path of the AppRole auth method [default: approle]
+    #[structopt(name = "vault-approle-mount", long = "vault-approle-mount")]
+    pub approle_mount: Option<String>,
+}
+
+#[derive(StructOpt)]
+pub enum VaultCommand {
+    Serve(ServerOpt),
+}
+
+impl VaultConnOpt {
+    /// Checks that an address and credentials are given, without contacting Vault.
+    pub fn validate(&self) -> AnyhowResult<()> {
+        let Some(address) = &self.address else {
+            bail!("the Vault address is required");
+        };
+        if !address.starts_with("http://") && !address.starts_with("https://") {
+            bail!("the Vault address must start with http:// or https://");
+        }
+
+        if self.role_id.is_some() {
+            if self.secret_id_file.is_none() && self.secret_id.is_none() && env::var_os(SECRET_ID_ENV).is_none() {
+                bail!("the AppRole secret id is required, from secret_id_file or {}", SECRET_ID_ENV);
+            }
+        } else if self.token_file.is_none() && self.token.is_none() && env::var_os(TOKEN_ENV).is_none() {
+            bail!("a Vault token or an AppRole role_id is required");
+        }
+
+        Ok(())
+    }
+
+    fn secret(file: &Option<PathBuf>, value: &Option<String>, env_var: &str) -> AnyhowResult<String> {
+        if let Some(path) = file {
+            return read_secret_file(path);
+        }
+
+        match value {
+            Some(value) => Ok(value.clone()),
+            None => env::var(env_var).map_err(|_| anyhow!("{} is not set", env_var)),
+        }
+    }
+
+    fn credentials(&self) -> AnyhowResult<Credentials> {
+        match &self.role_id {
+            Some(role_id) => Ok(Credentials::AppRole {
+                mount: self
+                    .approle_mount
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_APPROLE_MOUNT.to_string()),
+                role_id: role_id.clone(),
+                secret_id: Self::secret(&self.secret_id_file, &self.secret_id, SECRET_ID_ENV)?,
+            }),
+            None => Ok(Credentials::Token(Self::secret(&self.token_file, &self.token, TOKEN_ENV)?)),
+        }
+    }
+}
+
+enum Credentials {
+    Token(String),
+    AppRole {
+        mount: String,
+        role_id: String,
+        secret_id: String,
+    },
+}
+
+#[derive(Deserialize)]
+struct VaultResponse<T> {
+    data: Option<T>,
+    auth: Option<VaultAuth>,
+}
+
+#[derive(Deserialize)]
+struct VaultAuth {
+    client_token: String,
+    lease_duration: u64,
+    renewable: bool,
+}
+
+#[derive(Deserialize)]
+struct VaultErrors {
+    #[serde(default)]
+    errors: Vec<String>,
+}
+
+/// Error status returned by Vault, with its `errors`.
+#[derive(Debug)]
+struct VaultError {
+    status: StatusCode,
+    errors: Vec<String>,
+}
+
+impl fmt::Display for VaultError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.errors.is_empty() {
+            true => write!(f, "Vault returned {}", self.status),
+            false => write!(f, "Vault returned {}: {}", self.status, self.errors.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for VaultError {}
+
+#[derive(Deserialize)]
+struct TokenLookup {
+    ttl: u64,
+    #[serde(default)]
+    renewable: bool,
+}
+
+#[derive(Deserialize)]
+struct KeyList {
+    keys: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct TransitKey {
+    #[serde(rename = "type")]
+    key_type: String,
+    latest_version: u64,
+    /// Versions of the key; public keys for asymmetric keys, creation times otherwise
+    keys: HashMap<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct TransitSignature {
+    signature: String,
+}
+
+/// Time to live of the current token, in seconds; 0 for tokens that never expire.
+struct Lease {
+    ttl: u64,
+    renewable: bool,
+}
+
+/// Vault HTTP API client, logged in with a token or an AppRole.
+struct VaultClient {
+    http: reqwest::Client,
+    address: Url,
+    namespace: Option<String>,
+    credentials: Credentials,
+    token: RwLock<String>,
+}
+
+impl VaultClient {
+    /// URL of the API `path`, followed by `key_name` percent-encoded as a single segment.
+    fn url(&self, path: &str, key_name: Option<&str>) -> AnyhowResult<Url> {
+        let mut url = self.address.clone();
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|_| anyhow!("invalid Vault address {}", self.address))?;
+            segments.pop_if_empty().push("v1").extend(path.split('/'));
+            if let Some(key_name) = key_name {
+                segments.push(key_name);
+            }
+        }
+
+        Ok(url)
+    }
+
+    fn request(&self, method: Method, path: &str) -> AnyhowResult<RequestBuilder> {
+        self.request_url(method, self.url(path, None)?)
+    }
+
+    /// Request on the API `path` of the transit key `key_name`, e.g. `transit/sign`.
+    fn key_request(&self, method: Method, path: &str, key_name: &str) -> AnyhowResult<RequestBuilder> {
+        self.request_url(method, self.url(path, Some(key_name))?)
+    }
+
+    fn request_url(&self, method: Method, url: Url) -> AnyhowResult<RequestBuilder> {
+        let token = self.token.read().map_err(|_| anyhow!("the Vault token lock is poisoned"))?;
+        let mut request = self.http.request(method, url).header("X-Vault-Request", "true");
+
+        if !token.is_empty() {
+            request = request.header("X-Vault-Token", token.as_str());
+        }
+        if let Some(namespace) = &self.namespace {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+
+        Ok(request)
+    }
+
+    /// Sends the request, turning Vault's `errors` into the error message.
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> AnyhowResult<VaultResponse<T>> {
+        let response = request.send().await?;
+        let status = response.status();
+
+        if !status.is_success() {
+            let errors = response.json::<VaultErrors>().await.map(|body| body.errors).unwrap_or_default();
+            return Err(VaultError { status, errors }.into());
+        }
+
+        Ok(response.json().await?)
+    }
+
+    /// Logs in with the AppRole, or checks the configured token.
+    async fn login(&self) -> AnyhowResult<Lease> {
+        let (path, role_id, secret_id) = match &self.credentials {
+            Credentials::Token(token) => {
+                *self.token.write().map_err(|_| anyhow!("the Vault token lock is poisoned"))? = token.clone();
+                let lookup: VaultResponse<TokenLookup> = self
+                    .send(self.request(Method::GET, "auth/token/lookup-self")?)
+                    .await
+                    .context("failed to look up the Vault token")?;
+                let lookup = lookup.data.ok_or_else(|| anyhow!("Vault returned no token data"))?;
+
+                return Ok(Lease {
+                    ttl: lookup.ttl,
+                    renewable: lookup.renewable,
+                });
+            }
+            Credentials::AppRole {
+                mount,
+                role_id,
+                secret_id,
+            } => (format!("auth/{}/login", mount), role_id, secret_id),
+        };
+
+        let request = self
+            .request(Method::POST, &path)?
+            .json(&json!({ "role_id": role_id, "secret_id": secret_id }));
+        let response: VaultResponse<Value> = self.send(request).await.context("failed to log in with the AppRole")?;
+        self.set_auth(response.auth)
+    }
+
+    async fn renew(&self) -> AnyhowResult<Lease> {
+        let request = self.request(Method::POST, "auth/token/renew-self")?.json(&json!({}));
+        let response: VaultResponse<Value> = self.send(request).await.context("failed to renew the Vault token")?;
+        self.set_auth(response.auth)
+    }
+
+    fn set_auth(&self, auth: Option<VaultAuth>) -> AnyhowResult<Lease> {
+        let auth = auth.ok_or_else(|| anyhow!("Vault returned no token"))?;
+        *self.token.write().map_err(|_| anyhow!("the Vault token lock is poisoned"))? = auth.client_token;
+
+        Ok(Lease {
+            ttl: auth.lease_duration,
+            renewable: auth.renewable,
+        })
+    }
+
+    /// Renews the token at two thirds of its lease, and logs in again with the AppRole once it
+    /// cannot be renewed anymore (e.g. past its max TTL).
+    async fn keep_token_alive(self: Arc<Self>, mut lease: Lease) {
+        let approle = matches!(self.credentials, Credentials::AppRole { .. });
+
+        loop {
+            if lease.ttl == 0 {
+                debug!("the Vault token does not expire");
+                return;
+            }
+            if !lease.renewable && !approle {
+                warn!("the Vault token is not renewable and expires in {}s", lease.ttl);
+                return;
+            }
+
+            tokio::time::sleep(Duration::from_secs((lease.ttl * 2 / 3).max(1))).await;
+
+            let renewable = lease.renewable;
+            let result = match renewable {
+                true => match self.renew().await {
+                    // Renewals are capped by the max TTL of the token: log in again once it runs out
+                    Ok(renewed) if approle && renewed.ttl <= RENEW_RETRY_SECS => self.login().await,
+                    Err(err) if approle => {
+                        debug!("{:#}", err);
+                        self.login().await
+                    }
+                    result => result,
+                },
+                false => self.login().await,
+            };
+
+            lease = result.unwrap_or_else(|err| {
+                warn!("{:#}, retrying in {}s", err, RENEW_RETRY_SECS);
+                Lease {
+                    ttl: RENEW_RETRY_SECS * 3 / 2,
+                    renewable,
+                }
+            });
+        }
+    }
+
+    async fn read_key(&self, mount: &str, name: &str) -> AnyhowResult<TransitKey> {
+        let response: VaultResponse<TransitKey> = self
+            .send(self.key_request(Method::GET, &format!("{}/keys", mount), name)?)
+            .await
+            .with_context(|| format!("failed to read transit key {}", name))?;
+
+        response.data.ok_or_else(|| anyhow!("Vault returned no data for transit key {}", name))
+    }
+
+    /// Names of the transit keys, none when the engine has no keys yet.
+    async fn list_keys(&self, mount: &str) -> AnyhowResult<Vec<String>> {
+        let request = self.request(Method::GET, &format!("{}/keys", mount))?.query(&[("list", "true")]);
+        let response: VaultResponse<KeyList> = match self.send(request).await {
+            Ok(response) => response,
+            Err(err) if err.downcast_ref::<VaultError>().is_some_and(|err| err.status == StatusCode::NOT_FOUND) => {
+                return Ok(Vec::new());
+            }
+            Err(err) => return Err(err.context("failed to list the transit keys")),
+        };
+
+        Ok(response.data.map(|list| list.keys).unwrap_or_default())
+    }
+}
+
+/// A transit key, pinned to the version its address was derived from.
+///
+/// Rotating the key in Vault does not change the address served: restart the proxy to pick up
+/// the new version.
+struct TransitKeyVersion {
+    client: Arc<VaultClient>,
+    mount: String,
+    name: String,
+    version: u64,
+}
+
+#[async_trait]
+impl DigestSigner for TransitKeyVersion {
+    async fn sign_digest(&self, hash: &B256) -> AnyhowResult<ecdsa::Signature> {
+        let body = json!({
+            "input": BASE64_STANDARD.encode(hash),
+            "prehashed": true,
+            "key_version": self.version,
+            "marshaling_algorithm": "asn1",
+        });
+        let request = self
+            .client
+            .key_request(Method::POST, &format!("{}/sign", self.mount), &self.name)?
+            .json(&body);
+        let response: VaultResponse<TransitSignature> = self
+            .client
+            .send(request)
+            .await
+            .with_context(|| format!("failed to sign with transit key {}", self.name))?;
+
+        // vault:v{version}:{base64 DER signature}
+        let signature = response.data.ok_or_else(|| anyhow!("Vault returned no signature"))?.signature;
+        let der = signature
+            .rsplit(':')
+            .next()
+            .and_then(|der| BASE64_STANDARD.decode(der).ok())
+            .ok_or_else(|| anyhow!("invalid Vault signature {:?}", signature))?;
+
+        Ok(ecdsa::Signature::from_der(&der)?)
+    }
+}
+
+/// `ecdsa-p256k1` keys of a Vault or OpenBao transit engine, by key name.
+pub struct VaultBackend {
+    client: Arc<VaultClient>,
+    mount: String,
+    signers: Mutex<HashMap<String, Arc<RemoteSigner<TransitKeyVersion>>>>,
+}
+
+/// Checks that `key_id` is a transit key name, made of ASCII letters, digits, `_` and `-`.
+pub fn check_key_name(key_id: &str) -> AnyhowResult<()> {
+    if key_id.is_empty() || !key_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        bail!("invalid transit key name: {:?}", key_id);
+    }
+
+    Ok(())
+}
+
+impl VaultBackend {
+    async fn get_signer(&self, key_id: &str) -> AnyhowResult<Arc<RemoteSigner<TransitKeyVersion>>> {
+        check_key_name(key_id)?;
+        let mut signers = self.signers.lock().await;
+
+        if let Some(signer) = signers.get(key_id) {
+            return Ok(signer.clone());
+        }
+
+        let key = self.client.read_key(&self.mount, key_id).await?;
+        if key.key_type != KEY_TYPE {
+            bail!("transit key {} is of type {}, not {}", key_id, key.key_type, KEY_TYPE);
+        }
+        let public_key = public_key(&key, key_id)?;
+
+        let key = TransitKeyVersion {
+            client: self.client.clone(),
+            mount: self.mount.clone(),
+            name: key_id.to_string(),
+            version: key.latest_version,
+        };
+        let signer = Arc::new(RemoteSigner::new(key, &public_key));
+        signers.insert(key_id.to_string(), signer.clone());
+        Ok(signer)
+    }
+}
+
+fn public_key(key: &TransitKey, key_id: &str) -> AnyhowResult<VerifyingKey> {
+    let pem = key
+        .keys
+        .get(&key.latest_version.to_string())
+        .and_then(|version| version.get("public_key"))
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow!("transit key {} has no public key", key_id))?;
+
+    parse_pem_public_key(pem).with_context(|| format!("invalid public key for transit key {}", key_id))
+}
+
+#[async_trait]
+impl SignerBackend for VaultBackend {
+    fn key_ref(&self, key_id: &str) -> AnyhowResult<KeyRef> {
+        check_key_name(key_id)?;
+        Ok(KeyRef {
+            backend: BackendKind::Vault,
+            key_id: key_id.to_string(),
+        })
+    }
+
+    async fn signer(&self, key_id: &str) -> AnyhowResult<Arc<dyn EthSigner>> {
+        Ok(self.get_signer(key_id).await?)
+    }
+
+    /// The `ecdsa-p256k1` keys of the transit engine; keys of other types are skipped.
+    async fn list_keys(&self) -> AnyhowResult<Vec<KeyInfo>> {
+        let mut keys = Vec::new();
+
+        for key_id in self.client.list_keys(&self.mount).await? {
+            match self.get_signer(&key_id).await {
+                Ok(signer) => keys.push(KeyInfo {
+                    address: alloy::signers::Signer::address(signer.as_ref()),
+                    key_id,
+                    label: None,
+                }),
+                Err(err) => debug!("skipping transit key {}: {:#}", key_id, err),
+            }
+        }
+
+        Ok(keys)
+    }
+}
+
+/// Logs in to Vault and starts renewing the token in the background.
+pub async fn vault_backend(opt: &VaultConnOpt) -> AnyhowResult<VaultBackend> {
+    let address = opt
+        .address
+        .as_deref()
+        .ok_or_else(|| anyhow!("the Vault address is required"))?;
+    let address = Url::parse(address).with_context(|| format!("invalid Vault address {}", address))?;
+
+    let client = Arc::new(VaultClient {
+        http: reqwest::Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()?,
+        address,
+        namespace: opt.namespace.clone(),
+        credentials: opt.credentials()?,
+        token: RwLock::new(String::new()),
+    });
+
+    let lease = client.login().await?;
+    info!("logged in to Vault at {}, token TTL {}s", client.address, lease.ttl);
+    tokio::spawn(client.clone().keep_token_alive(lease));
+
+    Ok(VaultBackend {
+        client,
+        mount: opt.mount.clone().unwrap_or_else(|| DEFAULT_MOUNT.to_string()),
+        signers: Mutex::new(HashMap::new()),
+    })
+}
+
+pub async fn handle_vault(opt: VaultOpt) -> AnyhowResult<()> {
+    match opt.cmd {
+        VaultCommand::Serve(server_opt) => {
+            let backend = vault_backend(&opt.conn).await?;
+            serve_backend(Arc::new(backend), server_opt).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        primitives::{keccak256, Address},
+        signers::k256::ecdsa::{signature::hazmat::PrehashSigner, SigningKey},
+    };
+    use axum::{
+        extract::Path,
+        http::HeaderMap,
+        routing::{get, post},
+        Json, Router,
+    };
+
+    use super::*;
+    use crate::signers::remote::{public_key_pem, serve_stand_in};
+
+    fn client(address: &str) -> VaultClient {
+        VaultClient {
+            http: reqwest::Client::new(),
+            address: Url::parse(address).unwrap(),
+            namespace: None,
+            credentials: Credentials::Token("root".to_string()),
+            token: RwLock::new(String::new()),
+        }
+    }
+
+    /// A transit engine holding `batcher` at version 2, an AES key, and a key with a name the proxy refuses.
+    fn stand_in(key: SigningKey) -> Router {
+        let pem = public_key_pem(key.verifying_key());
+
+        Router::new()
+            .route(
+                "/v1/auth/token/lookup-self",
+                get(|headers: HeaderMap| async move {
+                    assert_eq!(headers["X-Vault-Token"], "root");
+                    Json(json!({ "data": { "ttl": 0, "renewable": false } }))
+                }),
+            )
+            .route(
+                "/v1/transit/keys",
+                get(|| async { Json(json!({ "data": { "keys": ["batcher", "aes", "other.key"] } })) }),
+            )
+            .route(
+                "/v1/transit/keys/:name",
+                get(move |Path(name): Path<String>| async move {
+                    let key = match name.as_str() {
+                        "batcher" => json!({
+                            "type": KEY_TYPE,
+                            "latest_version": 2,
+                            "keys": { "1": { "public_key": "rotated" }, "2": { "public_key": pem } },
+                        }),
+                        "aes" => json!({ "type": "aes256-gcm96", "latest_version": 1, "keys": { "1": 1700000000 } }),
+                        _ => return Err((StatusCode::NOT_FOUND, Json(json!({ "errors": [] })))),
+                    };
+                    Ok(Json(json!({ "data": key })))
+                }),
+            )
+            .route(
+                "/v1/transit/sign/:name",
+                post(move |Path(name): Path<String>, Json(body): Json<Value>| async move {
+                    assert_eq!(name, "batcher");
+                    assert_eq!(body["key_version"], 2);
+                    assert_eq!(body["prehashed"], true);
+
+                    let hash = BASE64_STANDARD.decode(body["input"].as_str().unwrap()).unwrap();
+                    let signature: ecdsa::Signature = key.sign_prehash(&hash).unwrap();
+                    let signature = format!("vault:v2:{}", BASE64_STANDARD.encode(signature.to_der()));
+                    Json(json!({ "data": { "signature": signature } }))
+                }),
+            )
+    }
+
+    async fn backend(key: &SigningKey) -> VaultBackend {
+        let opt = VaultConnOpt {
+            address: Some(serve_stand_in(stand_in(key.clone())).await),
+            mount: None,
+            namespace: None,
+            token_file: None,
+            token: Some("root".to_string()),
+            role_id: None,
+            secret_id_file: None,
+            secret_id: None,
+            approle_mount: None,
+        };
+        vault_backend(&opt).await.unwrap()
+    }
+
+    #[test]
+    fn key_names() {
+        let backend = VaultBackend {
+            client: Arc::new(client("http://127.0.0.1:8200")),
+            mount: DEFAULT_MOUNT.to_string(),
+            signers: Mutex::new(HashMap::new()),
+        };
+
+        for name in ["batcher", "my-key_1"] {
+            assert_eq!(backend.key_ref(name).unwrap().key_id, name);
+        }
+        for name in ["", "a/b", "../keys", "a b", "a.b", "batcher?list=true"] {
+            assert!(backend.key_ref(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn key_urls() {
+        let url = client("http://127.0.0.1:8200").url("transit/sign", Some("a b/c")).unwrap();
+        assert_eq!(url.as_str(), "http://127.0.0.1:8200/v1/transit/sign/a%20b%2Fc");
+
+        let url = client("https://vault.example.com/prefix/").url("auth/token/lookup-self", None).unwrap();
+        assert_eq!(url.as_str(), "https://vault.example.com/prefix/v1/auth/token/lookup-self");
+    }
+
+    #[tokio::test]
+    async fn signs_with_the_latest_version() {
+        let key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let address = Address::from_public_key(key.verifying_key());
+        let backend = backend(&key).await;
+
+        let keys = backend.list_keys().await.unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!((keys[0].key_id.as_str(), keys[0].address), ("batcher", address));
+
+        let hash = keccak256("vault");
+        let signature = backend.sign_hash("batcher", &hash).await.unwrap();
+        assert_eq!(signature.recover_address_from_prehash(&hash).unwrap(), address);
+    }
+
+    #[tokio::test]
+    async fn refuses_other_keys() {
+        let backend = backend(&SigningKey::from_slice(&[7; 32]).unwrap()).await;
+
+        let err = backend.signer("aes").await.err().unwrap();
+        assert!(err.to_string().contains("aes256-gcm96"), "{:#}", err);
+        let err = backend.signer("missing").await.err().unwrap();
+        assert!(format!("{:#}", err).contains("404"), "{:#}", err);
+    }
+}
@@ -1,63 +1,90 @@
-use anyhow::{Result as AnyhowResult};
-use crate::shutdown_signal::shutdown_signal;
-#[cfg(debug_assertions)]
+use anyhow::{anyhow, bail, Context, Result as AnyhowResult};
+use async_trait::async_trait;
+#[cfg(feature = "mock")]
 use crate::signers::mock::{add_mock_signers, MOCK_KEYS};
 use alloy::{
+    hex,
+    primitives::B256,
     signers::{
+        k256::ecdsa::{self, signature::hazmat::PrehashSigner, VerifyingKey},
         local::{
         yubihsm::{
-            asymmetric::Algorithm::EcK256, device::SerialNumber, Capability, Client, Connector,
-            Credentials, Domain, HttpConfig, UsbConfig,
+            algorithm::Algorithm, asymmetric::Algorithm::EcK256, device::SerialNumber,
+            object::{Filter, Info, Label, Type}, Capability, Client, Connector, Credentials, Domain,
+            HttpConfig, UsbConfig, wrap,
         },
-        YubiSigner,
+        PrivateKeySigner, YubiSigner,
         },
     }, 
 };
-use axum::routing::get;
-use axum::{
-    debug_handler,
-    extract::{Path, State},
-    routing::post,
-    Router,
+use base64::{prelude::BASE64_STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use aws_sdk_kms::primitives::Blob;
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
 };
-use serde_json::Value;
-use std::time::Duration;
-use std::{collections::HashMap, str::FromStr, sync::Arc};
 use structopt::StructOpt;
 use strum::{EnumString, VariantNames};
-use tokio::net::TcpListener;
 use tokio::sync::Mutex;
-use tower_http::timeout::TimeoutLayer;
-use tower_http::trace::TraceLayer;
-use tracing::info;
+use tracing::warn;
+use zeroize::Zeroizing;
 use crate::{
-    app_types::{AppJson, AppResult},
-    jsonrpc::{JsonRpcReply, JsonRpcRequest, AddressResponse},
-    signers::common::{handle_eth_sign_jsonrpc},
-
+    config::{read_secret_file, ServerOpt},
+    signers::{
+        common::{serve_backend, BackendKind, EthSigner, KeyInfo, KeyRef, SignerBackend},
+        remote::{DigestSigner, RemoteSigner},
+        aws_kms::{load_aws_config, AwsKmsConfig},
+        keystore::PASSWORD_ENV as KEYSTORE_PASSWORD_ENV,
+    },
 };
 use alloy::primitives::{Address};
 
 
 const DEFAULT_USB_TIMEOUT_MS: u64 = 30_000;
 const DEFAULT_HTTP_TIMEOUT_MS: u64 = 5000;
-const API_TIMEOUT_SECS: u64 = 30;
+/// Object labels are at most 40 bytes on the device
+const MAX_LABEL_LEN: usize = 40;
+/// How long `GET /keys` reuses the key listing of the device.
+const LIST_CACHE_SECS: u64 = 60;
+/// Default auth key of the mock HSM
+const MOCK_AUTH_KEY_ID: u16 = 1;
+const MOCK_PASSWORD: &str = "password";
+const PASSWORD_ENV: &str = "YUBIHSM_PASSWORD";
 
-#[derive(EnumString, VariantNames, Debug)]
+#[derive(EnumString, VariantNames, Deserialize, Debug, Default)]
 #[strum(serialize_all = "kebab_case")]
+#[serde(rename_all = "kebab-case")]
 pub enum YubiMode {
+    #[default]
     Usb,
     Http,
-    #[cfg(debug_assertions)]
+    /// In-memory HSM with the mock keys, for tests (needs the `mock` feature)
     Mock,
 }
 
+/// Modes offered on the command line; `mock` only in builds with the `mock` feature.
+const MODES: &[&str] = if cfg!(feature = "mock") {
+    YubiMode::VARIANTS
+} else {
+    &["usb", "http"]
+};
+
 
 
+/// Options of the `yubihsm` subcommand, which clap checks against the mode.
+///
+/// `serve` and the config file use [`YubiConnOpt`] instead, checked by [`YubiConnOpt::validate`]
+/// since the YubiHSM options are only required there when the yubihsm backend is enabled.
 #[derive(StructOpt)]
 pub struct YubiOpt {
-    /// Connection mode (usb or http)
-    #[structopt(short, long, possible_values = YubiMode::VARIANTS, case_insensitive = true, default_value = "usb", env = "YUBIHSM_MODE")]
+    /// Connection mode (usb or http, or mock in test builds)
+    #[structopt(short, long, possible_values = MODES, case_insensitive = true, default_value = "usb", env = "YUBIHSM_MODE")]
     pub mode: YubiMode,
 
     /// YubiHSM device serial ID (for USB mode)
@@ -81,118 +108,657 @@ pub struct YubiOpt {
     #[structopt(long = "port", env = "YUBIHSM_HTTP_PORT", required_if("mode", "http"))]
     pub http_port: Option<u16>,
 
-    /// YubiHSM auth key ID
-    #[structopt(short, long = "auth-key", env = "YUBIHSM_AUTH_KEY_ID")]
-    pub auth_key_id: u16,
+    /// YubiHSM auth key ID (for USB and HTTP modes)
+    #[structopt(
+        short,
+        long = "auth-key",
+        env = "YUBIHSM_AUTH_KEY_ID",
+        required_ifs(&[("mode", "usb"), ("mode", "http")])
+    )]
+    pub auth_key_id: Option<u16>,
+
+    /// File holding the YubiHSM auth key password [default: $YUBIHSM_PASSWORD, or a prompt]
+    #[structopt(long = "password-file", env = "YUBIHSM_PASSWORD_FILE", parse(from_os_str))]
+    pub password_file: Option<PathBuf>,
+
+    /// File holding the YubiHSM auth key password encrypted with AWS KMS (`aws kms encrypt`)
+    #[structopt(long = "sealed-password-file", env = "YUBIHSM_SEALED_PASSWORD_FILE", parse(from_os_str))]
+    pub sealed_password_file: Option<PathBuf>,
 
-    /// YubiHSM auth key password
-    #[structopt(short, long = "pass", env = "YUBIHSM_PASSWORD", hide_env_values = true)]
-    pub password: String,
+    /// AWS region of the KMS key that sealed the password
+    #[structopt(long, env = "YUBIHSM_SEALED_PASSWORD_REGION", requires = "sealed-password-file")]
+    pub sealed_password_region: Option<String>,
+
+    /// KMS endpoint to unseal the password with, e.g. LocalStack's http://localhost:4566
+    #[structopt(long, env = "YUBIHSM_SEALED_PASSWORD_ENDPOINT_URL", requires = "sealed-password-file")]
+    pub sealed_password_endpoint_url: Option<String>,
+
+    /// AWS profile to unseal the password with
+    #[structopt(long, env = "YUBIHSM_SEALED_PASSWORD_PROFILE", requires = "sealed-password-file")]
+    pub sealed_password_profile: Option<String>,
+
+    /// IAM role to assume to unseal the password
+    #[structopt(long, env = "YUBIHSM_SEALED_PASSWORD_ROLE_ARN", requires = "sealed-password-file")]
+    pub sealed_password_role_arn: Option<String>,
 
     #[structopt(subcommand)]
     pub cmd: YubiCommand,
 }
 
+/// YubiHSM connection settings, shared by `serve` and the config file.
+#[derive(StructOpt, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct YubiConnOpt {
+    /// Connection mode (usb or http, or mock in test builds)
+    #[structopt(short, long, possible_values = MODES, case_insensitive = true, default_value = "usb", env = "YUBIHSM_MODE")]
+    #[serde(default)]
+    pub mode: YubiMode,
+
+    /// YubiHSM device serial ID (for USB mode)
+    #[structopt(short, long = "device-serial", env = "YUBIHSM_DEVICE_SERIAL_ID")]
+    pub device_serial_id: Option<String>,
+
+    /// YubiHSM HTTP address (for HTTP mode)
+    #[structopt(long = "addr", env = "YUBIHSM_HTTP_ADDRESS")]
+    pub http_address: Option<String>,
+
+    /// YubiHSM HTTP port (for HTTP mode)
+    #[structopt(long = "port", env = "YUBIHSM_HTTP_PORT")]
+    pub http_port: Option<u16>,
+
+    /// YubiHSM auth key ID
+    #[structopt(short, long = "auth-key", env = "YUBIHSM_AUTH_KEY_ID")]
+    pub auth_key_id: Option<u16>,
+
+    /// Auth key password, in the config file only; on the command line it would show up in `ps`,
+    /// use `--password-file` or `YUBIHSM_PASSWORD` instead
+    #[structopt(skip)]
+    pub password: Option<String>,
+
+    /// File holding the YubiHSM auth key password
+    #[structopt(long = "password-file", env = "YUBIHSM_PASSWORD_FILE", parse(from_os_str))]
+    pub password_file: Option<PathBuf>,
+
+    /// File holding the YubiHSM auth key password encrypted with AWS KMS (`aws kms encrypt`)
+    #[structopt(long = "sealed-password-file", env = "YUBIHSM_SEALED_PASSWORD_FILE", parse(from_os_str))]
+    pub sealed_password_file: Option<PathBuf>,
+
+    /// AWS region of the KMS key that sealed the password
+    #[structopt(long, env = "YUBIHSM_SEALED_PASSWORD_REGION", requires = "sealed-password-file")]
+    pub sealed_password_region: Option<String>,
+
+    /// KMS endpoint to unseal the password with, e.g. LocalStack's http://localhost:4566
+    #[structopt(long, env = "YUBIHSM_SEALED_PASSWORD_ENDPOINT_URL", requires = "sealed-password-file")]
+    pub sealed_password_endpoint_url: Option<String>,
+
+    /// AWS profile to unseal the password with
+    #[structopt(long, env = "YUBIHSM_SEALED_PASSWORD_PROFILE", requires = "sealed-password-file")]
+    pub sealed_password_profile: Option<String>,
+
+    /// IAM role to assume to unseal the password
+    #[structopt(long, env = "YUBIHSM_SEALED_PASSWORD_ROLE_ARN", requires = "sealed-password-file")]
+    pub sealed_password_role_arn: Option<String>,
+}
+
 #[derive(StructOpt)]
 pub enum YubiCommand {
-    Serve,
+    Serve(ServerOpt),
     GenerateKey {
+        /// Key label, suffixed with `-1`, `-2`, ... with `--count`
+        #[structopt(short, long, default_value)]
+        label: String,
+        /// The key will be exportable or not
+        #[structopt(short, long)]
+        exportable: bool,
+        /// Object ID of the key, 0 to let the device pick one (the first of consecutive IDs with `--count`)
+        #[structopt(long, default_value = "0")]
+        id: u16,
+        /// Domains of the key, comma separated (e.g. 1,2) [default: all]
+        #[structopt(long, use_delimiter = true)]
+        domains: Vec<u8>,
+        /// Capabilities besides sign-ecdsa, comma separated, as yubihsm-shell names them (e.g. sign-attestation-certificate)
+        #[structopt(short, long = "capabilities", use_delimiter = true)]
+        capabilities: Vec<String>,
+        /// Number of keys to generate
+        #[structopt(long, default_value = "1")]
+        count: u16,
+        /// Prints the generated keys as a JSON array of `{"id", "label", "address"}`
+        #[structopt(long)]
+        json: bool,
+    },
+    /// Lists the secp256k1 keys with their label, domains, capabilities and address
+    ListKeys,
+    /// Prints the label, domains, capabilities, origin and address of a key
+    KeyInfo {
+        /// Object ID of the key
+        id: u16,
+    },
+    /// Deletes a key from the device, after confirmation
+    DeleteKey {
+        /// Object ID of the key
+        id: u16,
+        /// Deletes the key without asking for confirmation
+        #[structopt(short, long)]
+        yes: bool,
+    },
+    /// Imports an existing private key, from a keystore file or a file holding it in hex
+    ImportKey {
+        /// Keystore JSON file, or file holding the private key in hex
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// File holding the password of the keystore file [default: $KEYSTORE_PASSWORD]
+        #[structopt(long, env = "KEYSTORE_PASSWORD_FILE", parse(from_os_str))]
+        keystore_password_file: Option<PathBuf>,
+        /// Object ID of the key, 0 to let the device pick one
+        #[structopt(long, default_value = "0")]
+        id: u16,
         /// Key label
         #[structopt(short, long, default_value)]
         label: String,
+        /// Domains of the key, comma separated (e.g. 1,2) [default: those of the auth key]
+        #[structopt(long, use_delimiter = true)]
+        domains: Vec<u8>,
         /// The key will be exportable or not
         #[structopt(short, long)]
         exportable: bool,
+        /// Imports the key without asking for confirmation
+        #[structopt(short, long)]
+        yes: bool,
     },
+    /// Writes a key encrypted under a wrap key, to restore it with `import-wrapped`
+    ExportWrapped {
+        /// Object ID of the key, generated or imported with `--exportable`
+        id: u16,
+        /// Object ID of the wrap key
+        #[structopt(short, long)]
+        wrap_key: u16,
+        /// File to write the wrapped key to, in base64 like yubihsm-shell
+        #[structopt(short, long, parse(from_os_str))]
+        out: PathBuf,
+    },
+    /// Restores a key written by `export-wrapped` or `yubihsm-shell get-wrapped`
+    ImportWrapped {
+        /// File of the wrapped key
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+        /// Object ID of the wrap key, holding the same key as on the exporting device
+        #[structopt(short, long)]
+        wrap_key: u16,
+    },
+}
+
+/// A key of the device, signing off the async workers since every signature is a round trip to the device.
+pub struct YubiKey(Arc<YubiSigner>);
+
+#[async_trait]
+impl DigestSigner for YubiKey {
+    async fn sign_digest(&self, hash: &B256) -> AnyhowResult<ecdsa::Signature> {
+        let (signer, hash) = (self.0.clone(), *hash);
+        Ok(tokio::task::spawn_blocking(move || signer.credential().sign_prehash(hash.as_slice())).await??)
+    }
 }
 
-#[derive(Clone)]
-pub struct AppState {
+pub type YubiKeySigner = RemoteSigner<YubiKey>;
+
+impl YubiKey {
+    pub fn signer(signer: YubiSigner) -> AnyhowResult<YubiKeySigner> {
+        let public_key = VerifyingKey::from_encoded_point(signer.credential().public_key())?;
+        Ok(RemoteSigner::new(Self(Arc::new(signer)), &public_key))
+    }
+}
+
+pub struct YubiBackend {
     pub connector: Connector,
     pub credentials: Credentials,
-    pub signers: Arc<Mutex<HashMap<u16, Arc<YubiSigner>>>>,
+    pub signers: Mutex<HashMap<u16, Arc<YubiKeySigner>>>,
+    /// The EcK256 keys on the device, and when they were listed
+    keys: Mutex<Option<(Instant, Vec<KeyInfo>)>>,
 }
 
+impl YubiBackend {
+    pub fn new(connector: Connector, credentials: Credentials) -> Self {
+        Self {
+            connector,
+            credentials,
+            signers: Mutex::new(HashMap::new()),
+            keys: Mutex::new(None),
+        }
+    }
 
+    async fn get_signer(&self, key_id: u16) -> AnyhowResult<Arc<YubiKeySigner>> {
+        let mut signers = self.signers.lock().await;
 
-#[debug_handler]
-async fn handle_ping() -> &'static str {
-    "pong"
+        if let Some(signer) = signers.get(&key_id) {
+            return Ok(signer.clone());
+        }
+
+        // Opening a session talks to the device, off the async workers
+        let (connector, credentials) = (self.connector.clone(), self.credentials.clone());
+        let yubi_signer = Arc::new(YubiKey::signer(
+            tokio::task::spawn_blocking(move || YubiSigner::connect(connector, credentials, key_id)).await??,
+        )?);
+        signers.insert(key_id, yubi_signer.clone());
+        Ok(yubi_signer)
+    }
+
+    /// The EcK256 keys on the device, listed again once the previous listing is `LIST_CACHE_SECS` old.
+    ///
+    /// The listing uses a single session, closed when done; signers are only connected for keys that sign.
+    async fn device_keys(&self) -> AnyhowResult<Vec<KeyInfo>> {
+        let mut keys = self.keys.lock().await;
+        if let Some((listed_at, keys)) = keys.as_ref()
+            && listed_at.elapsed() < Duration::from_secs(LIST_CACHE_SECS)
+        {
+            return Ok(keys.clone());
+        }
+
+        let (connector, credentials) = (self.connector.clone(), self.credentials.clone());
+        let listed = tokio::task::spawn_blocking(move || -> AnyhowResult<_> {
+            let client = Client::open(connector, credentials, true)?;
+            let objects = client.list_objects(&[
+                Filter::Type(Type::AsymmetricKey),
+                Filter::Algorithm(Algorithm::Asymmetric(EcK256)),
+            ])?;
+
+            let mut keys = Vec::with_capacity(objects.len());
+            for object in objects {
+                let listed = || -> AnyhowResult<_> {
+                    let info = client.get_object_info(object.object_id, object.object_type)?;
+                    Ok((object_label(&info), key_address(&client, object.object_id)?))
+                };
+                match listed() {
+                    Ok((label, address)) => keys.push(KeyInfo {
+                        key_id: object.object_id.to_string(),
+                        label: (!label.is_empty()).then_some(label),
+                        address,
+                    }),
+                    Err(err) => warn!("skipping YubiHSM key {}: {:#}", object.object_id, err),
+                }
+            }
+            Ok(keys)
+        })
+        .await??;
+
+        *keys = Some((Instant::now(), listed.clone()));
+        Ok(listed)
+    }
 }
 
-#[debug_handler]
-async fn handle_request(
-    Path(key_id): Path<u16>,
-    State(state): State<Arc<AppState>>,
-    AppJson(payload): AppJson<JsonRpcRequest<Vec<Value>>>,
-) -> AppResult<JsonRpcReply<Value>> {
-    println!("{:?}", payload);
-    let yubi_signer = get_signer(state.clone(), key_id).await?;
-    handle_eth_sign_jsonrpc(payload, yubi_signer).await
+pub fn parse_key_id(key_id: &str) -> AnyhowResult<u16> {
+    u16::from_str(key_id).map_err(|_| anyhow!("invalid YubiHSM key id: {}", key_id))
 }
 
-async fn get_signer(state: Arc<AppState>, key_id: u16) -> AnyhowResult<Arc<YubiSigner>> {
-    let mut signers = state.signers.lock().await;
+#[async_trait]
+impl SignerBackend for YubiBackend {
+    fn key_ref(&self, key_id: &str) -> AnyhowResult<KeyRef> {
+        Ok(KeyRef {
+            backend: BackendKind::Yubihsm,
+            key_id: parse_key_id(key_id)?.to_string(),
+        })
+    }
 
-    if let Some(signer) = signers.get(&key_id) {
-        return Ok(signer.clone());
+    async fn signer(&self, key_id: &str) -> AnyhowResult<Arc<dyn EthSigner>> {
+        Ok(self.get_signer(parse_key_id(key_id)?).await?)
     }
 
-    let yubi_signer = Arc::new(
-        YubiSigner::connect(
-            state.connector.clone(),
-            state.credentials.clone(),
-            key_id,
-        )?
-    );    
-    signers.insert(key_id, yubi_signer.clone());
-    Ok(yubi_signer)
+    /// The EcK256 keys on the device, from one session that is not kept for signing.
+    async fn list_keys(&self) -> AnyhowResult<Vec<KeyInfo>> {
+        self.device_keys().await
+    }
 }
 
-#[debug_handler]
-async fn handle_address_request(
-    Path(key_id): Path<u16>,
-    State(state): State<Arc<AppState>>,
-) -> AppResult<AddressResponse> {
-    let yubi_signer = get_signer(state.clone(), key_id).await?;
-    let address = yubi_signer.address().to_string();
-
-    Ok(AppJson(AddressResponse { address }))
+/// A key created by `generate-key`, as printed with `--json`.
+#[derive(Serialize)]
+struct GeneratedKey {
+    id: u16,
+    label: String,
+    address: String,
 }
 
 fn generate_new_key(
-    connector: Connector,
-    credentials: Credentials,
+    client: &Client,
+    id: u16,
+    label: &str,
+    domains: Domain,
+    capabilities: Capability,
+) -> AnyhowResult<(u16, Address)> {
+    let id = client.generate_asymmetric_key(id, new_label(label)?, domains, capabilities, EcK256)?;
+
+    Ok((id, key_address(client, id)?))
+}
+
+/// Labels of the `count` keys generated from object ID `id`, checking the batch before any key is generated.
+fn batch_labels(label: &str, id: u16, count: u16) -> AnyhowResult<Vec<String>> {
+    if count == 0 {
+        bail!("--count must be at least 1");
+    }
+    if id != 0 && id.checked_add(count - 1).is_none() {
+        bail!("{} keys do not fit from ID {}", count, id);
+    }
+
+    let labels: Vec<String> = (1..=count)
+        .map(|n| match count == 1 || label.is_empty() {
+            true => label.to_string(),
+            false => format!("{}-{}", label, n),
+        })
+        .collect();
+    for label in &labels {
+        new_label(label)?;
+    }
+
+    Ok(labels)
+}
+
+/// Generates a key per label, at consecutive IDs from `id` unless 0, stopping at the first failure.
+///
+/// The keys generated before a failure are returned along with it, as they are on the device.
+fn generate_keys(
+    client: &Client,
+    id: u16,
+    labels: Vec<String>,
+    domains: Domain,
+    capabilities: Capability,
+) -> (Vec<GeneratedKey>, AnyhowResult<()>) {
+    let count = labels.len();
+    let mut keys = Vec::with_capacity(count);
+
+    for (n, label) in (0..).zip(labels) {
+        let id = match id {
+            0 => 0,
+            id => id + n,
+        };
+
+        match generate_new_key(client, id, &label, domains, capabilities) {
+            Ok((id, address)) => keys.push(GeneratedKey {
+                id,
+                label,
+                address: address.to_string(),
+            }),
+            Err(err) => {
+                let err = err.context(format!("generated {} of {} keys", n, count));
+                return (keys, Err(err));
+            }
+        }
+    }
+
+    (keys, Ok(()))
+}
+
+/// Checks the length of a label, which the device library would panic on.
+fn new_label(label: &str) -> AnyhowResult<Label> {
+    if label.len() > MAX_LABEL_LEN {
+        bail!("label {:?} is longer than {} bytes", label, MAX_LABEL_LEN);
+    }
+
+    Ok(label.into())
+}
+
+/// Domains 1 to 16, all of them if none is given.
+fn parse_domains(domains: &[u8]) -> AnyhowResult<Domain> {
+    if domains.is_empty() {
+        return Ok(Domain::all());
+    }
+
+    let mut parsed = Domain::empty();
+    for &domain in domains {
+        if !(1..=16).contains(&domain) {
+            bail!("invalid domain {}, expected 1 to 16", domain);
+        }
+        parsed |= Domain::at(domain.into())?;
+    }
+
+    Ok(parsed)
+}
+
+/// Capability named as by yubihsm-shell, e.g. `exportable-under-wrap`.
+fn parse_capability(name: &str) -> AnyhowResult<Capability> {
+    Capability::from_name(&name.to_uppercase().replace('-', "_"))
+        .ok_or_else(|| anyhow!("unknown capability {:?}", name))
+}
+
+/// Label of an object, without the NUL padding of the device.
+fn object_label(info: &Info) -> String {
+    info.label.to_string().trim_end_matches('\0').to_string()
+}
+
+/// Domain numbers, e.g. `1,2`.
+fn format_domains(domains: Domain) -> String {
+    (0..16)
+        .filter(|bit| domains.bits() & (1 << bit) != 0)
+        .map(|bit| (bit + 1).to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Capability names as yubihsm-shell spells them, e.g. `sign-ecdsa,exportable-under-wrap`.
+fn format_capabilities(capabilities: Capability) -> String {
+    capabilities
+        .iter_names()
+        .map(|(name, _)| name.to_lowercase().replace('_', "-"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Info of the secp256k1 key `id`, failing for any other object.
+fn key_info(client: &Client, id: u16) -> AnyhowResult<Info> {
+    let info = client
+        .get_object_info(id, Type::AsymmetricKey)
+        .with_context(|| format!("no asymmetric key with ID {}", id))?;
+    if info.algorithm != Algorithm::Asymmetric(EcK256) {
+        bail!("key {} is not a secp256k1 key", id);
+    }
+
+    Ok(info)
+}
+
+fn key_address(client: &Client, id: u16) -> AnyhowResult<Address> {
+    let public_key = client.get_public_key(id)?;
+    if public_key.as_ref().len() != 64 {
+        bail!("unexpected public key length for key {}", id);
+    }
+
+    Ok(Address::from_raw_public_key(public_key.as_ref()))
+}
+
+/// Asks the operator on stdin, anything but `y` or `yes` declining.
+fn confirm(prompt: &str) -> AnyhowResult<bool> {
+    print!("{} [y/N] ", prompt);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn list_keys(client: &Client) -> AnyhowResult<()> {
+    let objects = client.list_objects(&[
+        Filter::Type(Type::AsymmetricKey),
+        Filter::Algorithm(Algorithm::Asymmetric(EcK256)),
+    ])?;
+
+    for object in objects {
+        let info = client.get_object_info(object.object_id, object.object_type)?;
+        let label = object_label(&info);
+        println!(
+            "{}  {}  {}  domains {}  {}",
+            info.object_id,
+            key_address(client, info.object_id)?,
+            if label.is_empty() { "-" } else { &label },
+            format_domains(info.domains),
+            format_capabilities(info.capabilities),
+        );
+    }
+
+    Ok(())
+}
+
+fn print_key_info(client: &Client, id: u16) -> AnyhowResult<()> {
+    let info = key_info(client, id)?;
+
+    println!("Key ID: {}", info.object_id);
+    println!("Label: {}", object_label(&info));
+    println!("Domains: {}", format_domains(info.domains));
+    println!("Capabilities: {}", format_capabilities(info.capabilities));
+    println!("Origin: {:?}", info.origin);
+    println!("Address: {}", key_address(client, id)?);
+    Ok(())
+}
+
+fn delete_key(client: &Client, id: u16, yes: bool) -> AnyhowResult<()> {
+    let info = key_info(client, id)?;
+    let address = key_address(client, id)?;
+
+    let prompt = format!(
+        "Delete key {} ({:?}, {})? This cannot be undone unless the key was backed up under wrap.",
+        id,
+        object_label(&info),
+        address
+    );
+    if !yes && !confirm(&prompt)? {
+        bail!("key {} was not deleted", id);
+    }
+
+    client.delete_object(id, Type::AsymmetricKey)?;
+    println!("Deleted key {} ({})", id, address);
+    Ok(())
+}
+
+/// Reads the private key of a keystore file, or of a file holding it in hex.
+///
+/// The file contents, password and key bytes are wiped from memory once dropped.
+fn read_private_key(path: &Path, keystore_password_file: Option<&Path>) -> AnyhowResult<Zeroizing<Vec<u8>>> {
+    let contents = Zeroizing::new(
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?,
+    );
+
+    if contents.trim_start().starts_with('{') {
+        let password = Zeroizing::new(match keystore_password_file {
+            Some(file) => read_secret_file(file)?,
+            None => env::var(KEYSTORE_PASSWORD_ENV).map_err(|_| {
+                anyhow!(
+                    "the keystore password is required, from --keystore-password-file or {}",
+                    KEYSTORE_PASSWORD_ENV
+                )
+            })?,
+        });
+        let signer = PrivateKeySigner::decrypt_keystore(path, password.as_bytes())
+            .with_context(|| format!("failed to decrypt keystore {}", path.display()))?;
+        return Ok(Zeroizing::new(signer.to_bytes().to_vec()));
+    }
+
+    let key = Zeroizing::new(
+        hex::decode(contents.trim())
+            .map_err(|_| anyhow!("{} is neither a keystore file nor a hex private key", path.display()))?,
+    );
+    if key.len() != 32 {
+        bail!("the private key of {} must be 32 bytes long", path.display());
+    }
+
+    Ok(key)
+}
+
+/// Domains of the auth key of the session, those an imported key is usable from by default.
+fn auth_key_domains(client: &Client, auth_key_id: u16) -> AnyhowResult<Domain> {
+    let info = client
+        .get_object_info(auth_key_id, Type::AuthenticationKey)
+        .with_context(|| format!("failed to read the domains of auth key {}", auth_key_id))?;
+
+    Ok(info.domains)
+}
+
+fn import_key(
+    client: &Client,
+    key: Zeroizing<Vec<u8>>,
+    id: u16,
     label: String,
+    domains: Domain,
     exportable: bool,
-) -> AnyhowResult<(u16, Address)> {
-    let client = Client::open(connector.clone(), credentials.clone(), true)?;
+    yes: bool,
+) -> AnyhowResult<()> {
+    let address = PrivateKeySigner::from_slice(&key)?.address();
+
+    let prompt = format!(
+        "Import the key of {} as {} with label {:?} in domains {}{}?",
+        address,
+        match id {
+            0 => "a new object".to_string(),
+            id => format!("object {}", id),
+        },
+        label,
+        format_domains(domains),
+        if exportable { ", exportable under wrap" } else { "" },
+    );
+    if !yes && !confirm(&prompt)? {
+        bail!("the key was not imported");
+    }
+
     let capabilities = if exportable {
         Capability::SIGN_ECDSA | Capability::EXPORTABLE_UNDER_WRAP
     } else {
         Capability::SIGN_ECDSA
     };
-    let id = client.generate_asymmetric_key(
-        0,
-        label.as_str().into(),
-        Domain::all(),
+    // The device library takes a Vec of its own and copies it into its command buffers, none of which it
+    // zeroizes: those copies stay in freed memory until overwritten. Only `key` is wiped, when dropped.
+    let id = client.put_asymmetric_key(
+        id,
+        new_label(&label)?,
+        domains,
         capabilities,
         EcK256,
+        key.to_vec(),
     )?;
-    let signer = YubiSigner::connect(connector, credentials, id)?;
 
-    Ok((id, signer.address()))
+    println!("Key ID: {}", id);
+    println!("Address: {}", key_address(client, id)?);
+    Ok(())
+}
+
+fn export_wrapped(client: &Client, id: u16, wrap_key: u16, out: &Path) -> AnyhowResult<()> {
+    let info = key_info(client, id)?;
+    if !info.capabilities.contains(Capability::EXPORTABLE_UNDER_WRAP) {
+        bail!("key {} was not created exportable under wrap", id);
+    }
+
+    let message = client
+        .export_wrapped(wrap_key, Type::AsymmetricKey, id)
+        .with_context(|| format!("failed to wrap key {} under wrap key {}", id, wrap_key))?;
+
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(out)
+        .with_context(|| format!("failed to create {}", out.display()))?;
+    writeln!(file, "{}", BASE64_STANDARD.encode(message.into_vec()))?;
+
+    println!("Wrapped key {} ({}) to {}", id, key_address(client, id)?, out.display());
+    Ok(())
+}
+
+fn import_wrapped(client: &Client, file: &Path, wrap_key: u16) -> AnyhowResult<()> {
+    let contents = fs::read_to_string(file).with_context(|| format!("failed to read {}", file.display()))?;
+    let bytes = BASE64_STANDARD
+        .decode(contents.trim())
+        .map_err(|_| anyhow!("{} is not a base64 wrapped key", file.display()))?;
+
+    let handle = client
+        .import_wrapped(wrap_key, wrap::Message::from_vec(bytes)?)
+        .with_context(|| format!("failed to unwrap {} with wrap key {}", file.display(), wrap_key))?;
+    let info = key_info(client, handle.object_id)?;
+
+    println!("Key ID: {}", info.object_id);
+    println!("Label: {}", object_label(&info));
+    println!("Address: {}", key_address(client, info.object_id)?);
+    Ok(())
 }
 
-fn create_connector(opt: &YubiOpt) -> Connector {
-    match opt.mode {
+fn create_connector(opt: &YubiConnOpt) -> AnyhowResult<Connector> {
+    let connector = match opt.mode {
         YubiMode::Usb => {
             let serial = SerialNumber::from_str(
                 opt.device_serial_id
                     .as_ref()
-                    .expect("USB mode requires a device serial ID"),
-            )
-            .unwrap();
+                    .ok_or_else(|| anyhow!("USB mode requires a device serial ID"))?,
+            )?;
             Connector::usb(&UsbConfig {
                 serial: Some(serial),
                 timeout_ms: DEFAULT_USB_TIMEOUT_MS,
@@ -202,73 +768,431 @@ fn create_connector(opt: &YubiOpt) -> Connector {
             let addr = opt
                 .http_address
                 .as_ref()
-                .expect("HTTP mode requires an address")
+                .ok_or_else(|| anyhow!("HTTP mode requires an address"))?
                 .clone();
-            let port = *opt.http_port.as_ref().expect("HTTP mode requires a port");
+            let port = opt.http_port.ok_or_else(|| anyhow!("HTTP mode requires a port"))?;
             Connector::http(&HttpConfig {
                 addr,
                 port,
                 timeout_ms: DEFAULT_HTTP_TIMEOUT_MS,
             })
         }
-        #[cfg(debug_assertions)]
+        #[cfg(feature = "mock")]
         YubiMode::Mock => Connector::mockhsm(),
+        #[cfg(not(feature = "mock"))]
+        YubiMode::Mock => bail!("mock mode is only available in builds with the mock feature"),
+    };
+
+    Ok(connector)
+}
+
+/// Decrypts a password file sealed with `aws kms encrypt`, in base64 or binary, with the AWS
+/// settings of `aws` falling back to the default AWS config chain.
+async fn unseal_password(path: &Path, aws: &AwsKmsConfig) -> AnyhowResult<Zeroizing<String>> {
+    let sealed = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let ciphertext = std::str::from_utf8(&sealed)
+        .ok()
+        .and_then(|text| BASE64_STANDARD.decode(text.trim()).ok())
+        .unwrap_or(sealed);
+
+    let client = aws_sdk_kms::Client::new(&load_aws_config(aws).await);
+    let output = client
+        .decrypt()
+        .ciphertext_blob(Blob::new(ciphertext))
+        .send()
+        .await
+        .with_context(|| format!("failed to decrypt {} with AWS KMS", path.display()))?;
+    let plaintext = output
+        .plaintext()
+        .ok_or_else(|| anyhow!("no plaintext returned for {}", path.display()))?;
+
+    let password = Zeroizing::new(
+        String::from_utf8(plaintext.as_ref().to_vec())
+            .map_err(|_| anyhow!("the password sealed in {} is not UTF-8", path.display()))?,
+    );
+    Ok(Zeroizing::new(password.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// Reads the auth key password from `password_file`, `sealed_password_file`, `password` or
+/// `YUBIHSM_PASSWORD`, or prompts for it on the terminal if `interactive`.
+async fn read_password(opt: &YubiConnOpt, interactive: bool) -> AnyhowResult<Zeroizing<String>> {
+    if let Some(path) = &opt.password_file {
+        return Ok(Zeroizing::new(read_secret_file(path)?));
+    }
+    if let Some(path) = &opt.sealed_password_file {
+        return unseal_password(path, &opt.sealed_password_aws()).await;
     }
+    if let Some(password) = opt.password.clone().or_else(|| env::var(PASSWORD_ENV).ok()) {
+        return Ok(Zeroizing::new(password));
+    }
+    if interactive && io::stdin().is_terminal() {
+        return Ok(Zeroizing::new(rpassword::prompt_password("YubiHSM auth key password: ")?));
+    }
+
+    bail!(
+        "YubiHSM auth key password is required, from --password-file, --sealed-password-file or {}",
+        PASSWORD_ENV
+    )
 }
 
-pub async fn handle_yubihsm(opt: YubiOpt) {
-    let connector = create_connector(&opt);
-    #[cfg(not(debug_assertions))]
-    let credentials = Credentials::from_password(opt.auth_key_id, opt.password.as_bytes());
-    #[cfg(debug_assertions)]
-    let credentials = Credentials::from_password(1, "password".as_bytes());
+/// Credentials of the auth key, the default one of the mock HSM in mock mode unless given.
+async fn create_credentials(opt: &YubiConnOpt, interactive: bool) -> AnyhowResult<Credentials> {
+    if matches!(opt.mode, YubiMode::Mock) && opt.auth_key_id.is_none() && opt.password_sources() == 0 {
+        return Ok(Credentials::from_password(MOCK_AUTH_KEY_ID, MOCK_PASSWORD.as_bytes()));
+    }
+
+    opt.check_password_sources()?;
+    let auth_key_id = opt
+        .auth_key_id
+        .ok_or_else(|| anyhow!("YubiHSM auth key ID is required"))?;
+    let password = read_password(opt, interactive).await?;
 
-    match opt.cmd {
-        YubiCommand::Serve => {
-            let shared_state = Arc::new(AppState {
-                connector,
-                credentials,
-                signers: Arc::new(Mutex::new(HashMap::new())),
-            });
-
-            #[cfg(debug_assertions)]
-            add_mock_signers(
-                shared_state.clone(),
-                MOCK_KEYS
-                    .iter()
-                    .map(|&(key_id, private_key, address)| {
-                        (key_id, private_key, address.to_string())
-                    })
-                    .collect(),
-            )
-            .await
-            .unwrap();
+    Ok(Credentials::from_password(auth_key_id, password.as_bytes()))
+}
 
-            let app = Router::new()
-                .route("/ping", get(handle_ping))
-                .route("/key/:key_id", post(handle_request))
-                .route("/key/:key_id/address", get(handle_address_request))
-                .with_state(shared_state)
-                .layer((
-                    TraceLayer::new_for_http(),
-                    TimeoutLayer::new(Duration::from_secs(API_TIMEOUT_SECS)),
-                ));
-
-            let listener = TcpListener::bind("0.0.0.0:4000").await.unwrap();
-            info!("listening on {}", listener.local_addr().unwrap());
-            axum::serve(listener, app)
-                .with_graceful_shutdown(shutdown_signal())
-                .await
-                .unwrap();
+/// Opens a session for the admin subcommands, which may prompt for the password.
+async fn open_client(opt: &YubiConnOpt) -> AnyhowResult<Client> {
+    Ok(Client::open(create_connector(opt)?, create_credentials(opt, true).await?, true)?)
+}
+
+impl YubiConnOpt {
+    fn password_sources(&self) -> usize {
+        [
+            self.password.is_some() || env::var_os(PASSWORD_ENV).is_some(),
+            self.password_file.is_some(),
+            self.sealed_password_file.is_some(),
+        ]
+        .into_iter()
+        .filter(|&given| given)
+        .count()
+    }
+
+    fn check_password_sources(&self) -> AnyhowResult<()> {
+        if self.password_sources() > 1 {
+            bail!(
+                "only one of the YubiHSM password_file, sealed_password_file and password ({}) may be given",
+                PASSWORD_ENV
+            );
         }
-        YubiCommand::GenerateKey { label, exportable } => {
-            let (id, address) =
-                generate_new_key(connector, credentials, label, exportable).unwrap();
+        Ok(())
+    }
 
-            println!("Key ID: {}", id);
-            println!("Address: {}", address);
+    /// AWS settings to unseal `sealed_password_file` with.
+    fn sealed_password_aws(&self) -> AwsKmsConfig {
+        AwsKmsConfig {
+            region: self.sealed_password_region.clone(),
+            endpoint_url: self.sealed_password_endpoint_url.clone(),
+            profile: self.sealed_password_profile.clone(),
+            role_arn: self.sealed_password_role_arn.clone(),
+            ..Default::default()
         }
     }
+
+    /// Checks that the options required by the selected mode are present, without connecting.
+    ///
+    /// `serve` cannot prompt, so a password source is required outside mock mode.
+    pub fn validate(&self) -> AnyhowResult<()> {
+        if matches!(self.mode, YubiMode::Mock) && !cfg!(feature = "mock") {
+            bail!("mock mode is only available in builds with the mock feature");
+        }
+        create_connector(self)?;
+        self.check_password_sources()?;
+
+        if !matches!(self.mode, YubiMode::Mock) {
+            if self.auth_key_id.is_none() {
+                bail!("YubiHSM auth key ID is required");
+            }
+            if self.password_sources() == 0 {
+                bail!(
+                    "YubiHSM auth key password is required, from password_file, sealed_password_file or {}",
+                    PASSWORD_ENV
+                );
+            }
+        }
+        let unseal_options = [
+            &self.sealed_password_region,
+            &self.sealed_password_endpoint_url,
+            &self.sealed_password_profile,
+            &self.sealed_password_role_arn,
+        ];
+        if self.sealed_password_file.is_none() && unseal_options.iter().any(|option| option.is_some()) {
+            bail!("the sealed_password_* AWS settings require sealed_password_file");
+        }
+        Ok(())
+    }
+}
+
+/// Builds the YubiHSM backend used by `serve`, preloaded with the mock keys in mock mode.
+pub async fn yubihsm_backend(opt: &YubiConnOpt) -> AnyhowResult<YubiBackend> {
+    let backend = YubiBackend::new(create_connector(opt)?, create_credentials(opt, false).await?);
+
+    #[cfg(feature = "mock")]
+    if matches!(opt.mode, YubiMode::Mock) {
+        warn!("serving the mock YubiHSM keys, whose private keys are public; never use mock mode in production");
+        add_mock_signers(
+            &backend,
+            MOCK_KEYS
+                .iter()
+                .map(|&(key_id, private_key, address)| {
+                    (key_id, private_key, address.to_string())
+                })
+                .collect(),
+        )
+        .await?;
+    }
+
+    Ok(backend)
 }
 
+pub async fn handle_yubihsm(opt: YubiOpt) -> AnyhowResult<()> {
+    let conn = YubiConnOpt {
+        mode: opt.mode,
+        device_serial_id: opt.device_serial_id,
+        http_address: opt.http_address,
+        http_port: opt.http_port,
+        auth_key_id: opt.auth_key_id,
+        password: None,
+        password_file: opt.password_file,
+        sealed_password_file: opt.sealed_password_file,
+        sealed_password_region: opt.sealed_password_region,
+        sealed_password_endpoint_url: opt.sealed_password_endpoint_url,
+        sealed_password_profile: opt.sealed_password_profile,
+        sealed_password_role_arn: opt.sealed_password_role_arn,
+    };
+
+    match opt.cmd {
+        YubiCommand::Serve(server_opt) => {
+            let backend = yubihsm_backend(&conn).await?;
+            serve_backend(Arc::new(backend), server_opt).await?;
+        }
+        YubiCommand::GenerateKey {
+            label,
+            exportable,
+            id,
+            domains,
+            capabilities,
+            count,
+            json,
+        } => {
+            let labels = batch_labels(&label, id, count)?;
+            let domains = parse_domains(&domains)?;
+            let mut key_capabilities = Capability::SIGN_ECDSA;
+            if exportable {
+                key_capabilities |= Capability::EXPORTABLE_UNDER_WRAP;
+            }
+            for name in &capabilities {
+                key_capabilities |= parse_capability(name)?;
+            }
+
+            let client = open_client(&conn).await?;
+            // Keys generated before a failure are still printed, they are on the device
+            let (keys, result) = generate_keys(&client, id, labels, domains, key_capabilities);
+            if json {
+                println!("{}", serde_json::to_string_pretty(&keys)?);
+            } else {
+                for key in &keys {
+                    println!("Key ID: {}", key.id);
+                    println!("Address: {}", key.address);
+                }
+            }
+            result?;
+        }
+        YubiCommand::ListKeys => {
+            let client = open_client(&conn).await?;
+            list_keys(&client)?;
+        }
+        YubiCommand::KeyInfo { id } => {
+            let client = open_client(&conn).await?;
+            print_key_info(&client, id)?;
+        }
+        YubiCommand::DeleteKey { id, yes } => {
+            let client = open_client(&conn).await?;
+            delete_key(&client, id, yes)?;
+        }
+        YubiCommand::ImportKey {
+            file,
+            keystore_password_file,
+            id,
+            label,
+            domains,
+            exportable,
+            yes,
+        } => {
+            let domains = (!domains.is_empty()).then(|| parse_domains(&domains)).transpose()?;
+            let key = read_private_key(&file, keystore_password_file.as_deref())?;
+            let client = open_client(&conn).await?;
+            // The session is open, so the auth key ID is only missing for the default one of the mock HSM
+            let domains = match domains {
+                Some(domains) => domains,
+                None => auth_key_domains(&client, conn.auth_key_id.unwrap_or(MOCK_AUTH_KEY_ID))?,
+            };
+            import_key(&client, key, id, label, domains, exportable, yes)?;
+        }
+        YubiCommand::ExportWrapped { id, wrap_key, out } => {
+            let client = open_client(&conn).await?;
+            export_wrapped(&client, id, wrap_key, &out)?;
+        }
+        YubiCommand::ImportWrapped { file, wrap_key } => {
+            let client = open_client(&conn).await?;
+            import_wrapped(&client, &file, wrap_key)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "mock")]
+    use alloy::primitives::{b256, keccak256};
+
+    use super::*;
+
+    #[cfg(feature = "mock")]
+    const KEY: B256 = b256!("4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318");
+
+    #[test]
+    fn parses_domains() {
+        assert_eq!(parse_domains(&[]).unwrap(), Domain::all());
+        assert_eq!(parse_domains(&[1, 2, 16]).unwrap(), Domain::DOM1 | Domain::DOM2 | Domain::DOM16);
+        assert_eq!(format_domains(parse_domains(&[2, 1, 2]).unwrap()), "1,2");
+        assert_eq!(format_domains(Domain::all()), "1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16");
+
+        for domain in [0, 17] {
+            let err = parse_domains(&[1, domain]).unwrap_err();
+            assert_eq!(err.to_string(), format!("invalid domain {}, expected 1 to 16", domain));
+        }
+    }
 
+    #[test]
+    fn parses_capabilities() {
+        assert_eq!(parse_capability("exportable-under-wrap").unwrap(), Capability::EXPORTABLE_UNDER_WRAP);
+        assert_eq!(parse_capability("SIGN_ATTESTATION_CERTIFICATE").unwrap(), Capability::SIGN_ATTESTATION_CERTIFICATE);
+        let err = parse_capability("sign-everything").unwrap_err();
+        assert_eq!(err.to_string(), "unknown capability \"sign-everything\"");
+
+        let capabilities = Capability::SIGN_ECDSA | Capability::EXPORTABLE_UNDER_WRAP;
+        assert_eq!(format_capabilities(capabilities), "sign-ecdsa,exportable-under-wrap");
+        assert_eq!(format_capabilities(Capability::empty()), "");
+    }
+
+    #[test]
+    fn limits_label_length() {
+        let label = "a".repeat(MAX_LABEL_LEN);
+        assert_eq!(new_label(&label).unwrap().to_string().trim_end_matches('\0'), label);
+        assert!(new_label("").is_ok());
+
+        let err = new_label(&"a".repeat(MAX_LABEL_LEN + 1)).unwrap_err();
+        assert!(err.to_string().ends_with("is longer than 40 bytes"), "{}", err);
+        // Multi-byte characters count in bytes
+        assert!(new_label(&"é".repeat(21)).is_err());
+    }
+
+    /// A backend on an empty mock HSM, and an admin session on the same HSM.
+    #[cfg(feature = "mock")]
+    fn mock_backend() -> (YubiBackend, Client) {
+        let connector = Connector::mockhsm();
+        let credentials = Credentials::from_password(MOCK_AUTH_KEY_ID, MOCK_PASSWORD.as_bytes());
+        let client = Client::open(connector.clone(), credentials.clone(), true).unwrap();
+
+        (YubiBackend::new(connector, credentials), client)
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn lists_keys_without_connecting_signers() {
+        let (backend, client) = mock_backend();
+        let label = new_label("batcher").unwrap();
+        client
+            .put_asymmetric_key(3, label, Domain::all(), Capability::SIGN_ECDSA, EcK256, KEY.to_vec())
+            .unwrap();
+        let address = PrivateKeySigner::from_slice(KEY.as_slice()).unwrap().address();
+
+        let keys = backend.list_keys().await.unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!((keys[0].key_id.as_str(), keys[0].label.as_deref()), ("3", Some("batcher")));
+        assert_eq!(keys[0].address, address);
+        assert!(backend.signers.lock().await.is_empty());
+
+        let hash = keccak256("yubihsm");
+        let signature = backend.sign_hash("3", &hash).await.unwrap();
+        assert_eq!(signature.recover_address_from_prehash(&hash).unwrap(), address);
+        assert_eq!(backend.signers.lock().await.len(), 1);
+    }
+
+    #[cfg(feature = "mock")]
+    #[tokio::test]
+    async fn generates_lists_and_deletes_keys() {
+        let (backend, client) = mock_backend();
+        let capabilities = Capability::SIGN_ECDSA | Capability::EXPORTABLE_UNDER_WRAP;
+        let (id, address) = generate_new_key(&client, 5, "batcher", parse_domains(&[1, 2]).unwrap(), capabilities)
+            .unwrap();
+        assert_eq!((id, address), (5, key_address(&client, 5).unwrap()));
+
+        let info = key_info(&client, 5).unwrap();
+        assert_eq!(object_label(&info), "batcher");
+        assert_eq!(format_domains(info.domains), "1,2");
+        assert_eq!(info.capabilities, capabilities);
+        list_keys(&client).unwrap();
+        let keys = backend.list_keys().await.unwrap();
+        assert_eq!((keys.len(), keys[0].key_id.as_str(), keys[0].address), (1, "5", address));
+
+        let err = generate_new_key(&client, 6, &"a".repeat(41), Domain::all(), Capability::SIGN_ECDSA).unwrap_err();
+        assert!(err.to_string().contains("longer than 40 bytes"), "{}", err);
+        assert!(key_info(&client, 6).is_err());
+
+        delete_key(&client, 5, true).unwrap();
+        assert!(key_info(&client, 5).is_err());
+        assert!(delete_key(&client, 5, true).is_err());
+        list_keys(&client).unwrap();
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn imports_keys_in_the_given_domains() {
+        let (_, client) = mock_backend();
+        let address = PrivateKeySigner::from_slice(KEY.as_slice()).unwrap().address();
+
+        let domains = parse_domains(&[2]).unwrap();
+        import_key(&client, Zeroizing::new(KEY.to_vec()), 7, "batcher".to_string(), domains, true, true).unwrap();
+        let info = key_info(&client, 7).unwrap();
+        assert_eq!((object_label(&info), format_domains(info.domains)), ("batcher".to_string(), "2".to_string()));
+        assert_eq!(info.capabilities, Capability::SIGN_ECDSA | Capability::EXPORTABLE_UNDER_WRAP);
+        assert_eq!(key_address(&client, 7).unwrap(), address);
+
+        // By default, the domains of the auth key, all of them for that of the mock HSM
+        let domains = auth_key_domains(&client, MOCK_AUTH_KEY_ID).unwrap();
+        assert_eq!(domains, Domain::all());
+        import_key(&client, Zeroizing::new(KEY.to_vec()), 8, String::new(), domains, false, true).unwrap();
+        assert_eq!(key_info(&client, 8).unwrap().capabilities, Capability::SIGN_ECDSA);
+        assert!(auth_key_domains(&client, 9).is_err());
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn generates_batches_of_keys() {
+        let (_, client) = mock_backend();
+        let no_keys = |client: &Client| client.list_objects(&[Filter::Type(Type::AsymmetricKey)]).unwrap().is_empty();
+
+        // Rejected batches generate nothing
+        assert_eq!(batch_labels("batcher", 0, 0).unwrap_err().to_string(), "--count must be at least 1");
+        let err = batch_labels("batcher", u16::MAX - 1, 3).unwrap_err();
+        assert_eq!(err.to_string(), "3 keys do not fit from ID 65534");
+        assert!(batch_labels(&"a".repeat(MAX_LABEL_LEN - 1), 1, 2).is_err());
+        assert!(no_keys(&client));
+
+        let labels = batch_labels("batcher", u16::MAX - 1, 2).unwrap();
+        assert_eq!(labels, ["batcher-1", "batcher-2"]);
+        let (keys, result) = generate_keys(&client, u16::MAX - 1, labels, Domain::DOM2, Capability::SIGN_ECDSA);
+        result.unwrap();
+        assert_eq!(keys.iter().map(|key| key.id).collect::<Vec<_>>(), [u16::MAX - 1, u16::MAX]);
+        for key in &keys {
+            assert_eq!(object_label(&key_info(&client, key.id).unwrap()), key.label);
+            assert_eq!(key.address, key_address(&client, key.id).unwrap().to_string());
+        }
+        assert_ne!(keys[0].address, keys[1].address);
+
+        assert_eq!(batch_labels("sequencer", 100, 1).unwrap(), ["sequencer"]);
+        assert_eq!(batch_labels("", 0, 2).unwrap(), ["", ""]);
+    }
+}
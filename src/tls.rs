@@ -0,0 +1,476 @@
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context, Result as AnyhowResult};
+use axum::{extract::Request, Router};
+use hyper::{body::Incoming, server::conn::http1, service::service_fn};
+use hyper_util::rt::TokioIo;
+use rustls::{
+    pki_types::{CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use serde::Deserialize;
+use tokio::{
+    net::TcpListener,
+    sync::watch,
+    task::JoinSet,
+    time::timeout,
+};
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+use tracing::{debug, info, warn};
+use x509_parser::{extensions::GeneralName, parse_x509_certificate};
+
+use crate::shutdown_signal::shutdown_signal;
+
+const RELOAD_INTERVAL_SECS: u64 = 10;
+/// Connections that have not completed the TLS handshake by then are dropped.
+const HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM certificate chain presented by the proxy
+    pub cert: PathBuf,
+    /// PEM private key of `cert`
+    pub key: PathBuf,
+    /// PEM CA bundle; when set, clients must present a certificate signed by one of these CAs
+    pub client_ca: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    pub fn files(&self) -> Vec<&Path> {
+        [Some(&self.cert), Some(&self.key), self.client_ca.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(PathBuf::as_path)
+            .collect()
+    }
+}
+
+/// Identity of a client that authenticated with a certificate.
+#[derive(Clone, Debug)]
+pub struct ClientIdentity {
+    /// Common names of the certificate subject
+    pub common_names: Vec<String>,
+    /// DNS, URI, email and IP subject alternative names
+    pub alt_names: Vec<String>,
+}
+
+impl ClientIdentity {
+    fn from_certificate(cert: &CertificateDer<'_>) -> AnyhowResult<Self> {
+        let (_, cert) = parse_x509_certificate(cert.as_ref())
+            .map_err(|err| anyhow!("invalid client certificate: {}", err))?;
+
+        let common_names = cert
+            .subject()
+            .iter_common_name()
+            .filter_map(|cn| cn.as_str().ok())
+            .map(str::to_string)
+            .collect();
+
+        let mut alt_names = Vec::new();
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(name)
+                    | GeneralName::URI(name)
+                    | GeneralName::RFC822Name(name) => alt_names.push(name.to_string()),
+                    GeneralName::IPAddress(bytes) => {
+                        if let Ok(octets) = <[u8; 4]>::try_from(*bytes) {
+                            alt_names.push(std::net::Ipv4Addr::from(octets).to_string());
+                        } else if let Ok(octets) = <[u8; 16]>::try_from(*bytes) {
+                            alt_names.push(std::net::Ipv6Addr::from(octets).to_string());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Self {
+            common_names,
+            alt_names,
+        })
+    }
+
+    /// Whether `name` is one of the subject common names or alternative names.
+    pub fn matches(&self, name: &str) -> bool {
+        self.common_names.iter().chain(&self.alt_names).any(|n| n == name)
+    }
+}
+
+/// The current rustls configuration, rebuilt whenever one of the certificate files changes.
+pub struct TlsState {
+    config: TlsConfig,
+    server_config: RwLock<Arc<ServerConfig>>,
+}
+
+impl TlsState {
+    pub fn new(config: TlsConfig) -> AnyhowResult<Arc<Self>> {
+        let server_config = load_server_config(&config)?;
+
+        Ok(Arc::new(Self {
+            config,
+            server_config: RwLock::new(Arc::new(server_config)),
+        }))
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.server_config.read().unwrap().clone())
+    }
+
+    /// Polls the certificate files and reloads them when they change.
+    pub async fn watch(self: Arc<Self>) {
+        let mut last_modified = modified_times(&self.config);
+        let mut interval = tokio::time::interval(Duration::from_secs(RELOAD_INTERVAL_SECS));
+
+        loop {
+            interval.tick().await;
+
+            let modified = modified_times(&self.config);
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            match self.reload() {
+                Ok(()) => info!("reloaded TLS certificates"),
+                Err(err) => warn!("failed to reload TLS certificates, keeping the previous ones: {:#}", err),
+            }
+        }
+    }
+
+    /// Rereads the certificate files; on error the previous configuration stays in use.
+    fn reload(&self) -> AnyhowResult<()> {
+        let server_config = load_server_config(&self.config)?;
+        *self.server_config.write().unwrap() = Arc::new(server_config);
+        Ok(())
+    }
+}
+
+fn modified_times(config: &TlsConfig) -> Vec<Option<SystemTime>> {
+    config
+        .files()
+        .into_iter()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+fn load_certs(path: &Path) -> AnyhowResult<Vec<CertificateDer<'static>>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("failed to read certificates from {}", path.display()))?;
+
+    if certs.is_empty() {
+        return Err(anyhow!("no certificates found in {}", path.display()));
+    }
+
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> AnyhowResult<PrivateKeyDer<'static>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .with_context(|| format!("failed to read private key from {}", path.display()))?
+        .ok_or_else(|| anyhow!("no private key found in {}", path.display()))
+}
+
+/// Builds the rustls server configuration, reading every file referenced by `config`.
+pub fn load_server_config(config: &TlsConfig) -> AnyhowResult<ServerConfig> {
+    let certs = load_certs(&config.cert)?;
+    let key = load_key(&config.key)?;
+
+    let builder = ServerConfig::builder();
+    let builder = match &config.client_ca {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca)? {
+                roots
+                    .add(cert)
+                    .with_context(|| format!("invalid CA certificate in {}", client_ca.display()))?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots)).build()?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder
+        .with_single_cert(certs, key)
+        .context("invalid TLS certificate or key")?;
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(server_config)
+}
+
+/// Serves `app` over TLS on `listener`, exposing the client certificate as a [`ClientIdentity`] extension.
+///
+/// On shutdown the listener is closed and open connections finish their in-flight requests before this returns.
+pub async fn serve_tls(listener: TcpListener, app: Router, tls: Arc<TlsState>) -> std::io::Result<()> {
+    let mut shutdown = pin!(shutdown_signal());
+    let (draining, drain) = watch::channel(());
+    let mut connections = JoinSet::new();
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!("failed to accept connection: {}", err);
+                    continue;
+                }
+            },
+            // Reap finished connections so the set does not grow with the server's lifetime.
+            Some(_) = connections.join_next() => continue,
+            _ = &mut shutdown => break,
+        };
+
+        connections.spawn(serve_connection(stream, peer, app.clone(), tls.acceptor(), drain.clone()));
+    }
+
+    drop(listener);
+    let _ = draining.send(());
+    while connections.join_next().await.is_some() {}
+
+    Ok(())
+}
+
+async fn serve_connection(
+    stream: tokio::net::TcpStream,
+    peer: SocketAddr,
+    app: Router,
+    acceptor: TlsAcceptor,
+    mut drain: watch::Receiver<()>,
+) {
+    let stream = match timeout(Duration::from_secs(HANDSHAKE_TIMEOUT_SECS), acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(err)) => {
+            debug!("TLS handshake with {} failed: {}", peer, err);
+            return;
+        }
+        Err(_) => {
+            debug!("TLS handshake with {} timed out", peer);
+            return;
+        }
+    };
+
+    let identity = match stream.get_ref().1.peer_certificates().and_then(|certs| certs.first()) {
+        Some(cert) => match ClientIdentity::from_certificate(cert) {
+            Ok(identity) => Some(identity),
+            Err(err) => {
+                debug!("rejecting {}: {:#}", peer, err);
+                return;
+            }
+        },
+        None => None,
+    };
+
+    // The router is shared by every connection; the identity only travels with the requests of this one.
+    let service = service_fn(move |mut request: Request<Incoming>| {
+        if let Some(identity) = &identity {
+            request.extensions_mut().insert(identity.clone());
+        }
+        app.clone().oneshot(request)
+    });
+
+    let mut conn = pin!(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+    let result = tokio::select! {
+        result = conn.as_mut() => result,
+        _ = drain.changed() => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+    if let Err(err) = result {
+        debug!("connection with {} closed: {}", peer, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::get;
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedKey, DnType, IsCa, KeyPair};
+    use rustls::{pki_types::ServerName, ClientConfig};
+    use tempfile::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+
+    use super::*;
+
+    fn ca(name: &str) -> CertifiedKey {
+        let key_pair = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.distinguished_name.push(DnType::CommonName, name);
+        let cert = params.self_signed(&key_pair).unwrap();
+
+        CertifiedKey { cert, key_pair }
+    }
+
+    /// A certificate for `common_name` and `alt_names`, signed by `ca`.
+    fn leaf(ca: &CertifiedKey, common_name: &str, alt_names: &[&str]) -> CertifiedKey {
+        let key_pair = KeyPair::generate().unwrap();
+        let alt_names = alt_names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        let mut params = CertificateParams::new(alt_names).unwrap();
+        params.distinguished_name.push(DnType::CommonName, common_name);
+        let cert = params.signed_by(&key_pair, &ca.cert, &ca.key_pair).unwrap();
+
+        CertifiedKey { cert, key_pair }
+    }
+
+    /// Writes the server certificate and key, and `client_ca` if any, to `dir`.
+    fn write_config(dir: &TempDir, server: &CertifiedKey, client_ca: Option<&CertifiedKey>) -> TlsConfig {
+        let config = TlsConfig {
+            cert: dir.path().join("server.pem"),
+            key: dir.path().join("server.key"),
+            client_ca: client_ca.map(|_| dir.path().join("ca.pem")),
+        };
+
+        fs::write(&config.cert, server.cert.pem()).unwrap();
+        fs::write(&config.key, server.key_pair.serialize_pem()).unwrap();
+        if let Some(client_ca) = client_ca {
+            fs::write(dir.path().join("ca.pem"), client_ca.cert.pem()).unwrap();
+        }
+
+        config
+    }
+
+    fn connector(ca: &CertifiedKey, client: Option<&CertifiedKey>) -> TlsConnector {
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.cert.der().clone()).unwrap();
+
+        let builder = ClientConfig::builder().with_root_certificates(roots);
+        let config = match client {
+            Some(client) => {
+                let key = PrivateKeyDer::try_from(client.key_pair.serialize_der()).unwrap();
+                builder.with_client_auth_cert(vec![client.cert.der().clone()], key).unwrap()
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        TlsConnector::from(Arc::new(config))
+    }
+
+    /// Responds with the common names of the client certificate, or `anonymous` without one.
+    async fn serve(tls: Arc<TlsState>) -> SocketAddr {
+        let app = Router::new().route(
+            "/",
+            get(|identity: Option<axum::Extension<ClientIdentity>>| async move {
+                match identity {
+                    Some(axum::Extension(identity)) => identity.common_names.join(","),
+                    None => "anonymous".to_string(),
+                }
+            }),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(serve_tls(listener, app, tls));
+
+        address
+    }
+
+    /// Sends `GET /` over TLS and returns the response, or the error of the handshake or the exchange.
+    async fn get_root(address: SocketAddr, connector: &TlsConnector) -> std::io::Result<String> {
+        let stream = tokio::net::TcpStream::connect(address).await?;
+        let mut stream = connector.connect(ServerName::try_from("localhost").unwrap(), stream).await?;
+
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+
+        Ok(response)
+    }
+
+    #[test]
+    fn extracts_client_identity() {
+        let ca = ca("test ca");
+        let client = leaf(&ca, "batcher", &["batcher.internal", "10.0.0.7", "::1"]);
+
+        let identity = ClientIdentity::from_certificate(client.cert.der()).unwrap();
+        assert_eq!(identity.common_names, ["batcher"]);
+        assert_eq!(identity.alt_names, ["batcher.internal", "10.0.0.7", "::1"]);
+        assert!(identity.matches("batcher"));
+        assert!(identity.matches("10.0.0.7"));
+        assert!(!identity.matches("proposer"));
+
+        assert!(ClientIdentity::from_certificate(&CertificateDer::from(vec![0x30, 0x00])).is_err());
+    }
+
+    #[tokio::test]
+    async fn completes_mutual_tls_handshake() {
+        let dir = TempDir::new().unwrap();
+        let ca = ca("test ca");
+        let server = leaf(&ca, "signer-proxy", &["localhost"]);
+        let tls = TlsState::new(write_config(&dir, &server, Some(&ca))).unwrap();
+        let address = serve(tls).await;
+
+        let client = leaf(&ca, "batcher", &["batcher.internal"]);
+        let response = get_root(address, &connector(&ca, Some(&client))).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("\r\n\r\nbatcher"), "{}", response);
+
+        // Each connection only sees its own identity.
+        let client = leaf(&ca, "proposer", &[]);
+        let response = get_root(address, &connector(&ca, Some(&client))).await.unwrap();
+        assert!(response.ends_with("\r\n\r\nproposer"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn rejects_untrusted_clients() {
+        let dir = TempDir::new().unwrap();
+        let ca = ca("test ca");
+        let server = leaf(&ca, "signer-proxy", &["localhost"]);
+        let tls = TlsState::new(write_config(&dir, &server, Some(&ca))).unwrap();
+        let address = serve(tls).await;
+
+        let other_ca = self::ca("other ca");
+        let client = leaf(&other_ca, "batcher", &[]);
+        assert!(get_root(address, &connector(&ca, Some(&client))).await.is_err());
+        assert!(get_root(address, &connector(&ca, None)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn serves_anonymous_clients_without_client_ca() {
+        let dir = TempDir::new().unwrap();
+        let ca = ca("test ca");
+        let server = leaf(&ca, "signer-proxy", &["localhost"]);
+        let tls = TlsState::new(write_config(&dir, &server, None)).unwrap();
+        let address = serve(tls).await;
+
+        let response = get_root(address, &connector(&ca, None)).await.unwrap();
+        assert!(response.ends_with("\r\n\r\nanonymous"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn reloads_certificates() {
+        let dir = TempDir::new().unwrap();
+        let old_ca = ca("old ca");
+        let config = write_config(&dir, &leaf(&old_ca, "signer-proxy", &["localhost"]), None);
+        let tls = TlsState::new(config.clone()).unwrap();
+        let address = serve(tls.clone()).await;
+        assert!(get_root(address, &connector(&old_ca, None)).await.is_ok());
+
+        let new_ca = ca("new ca");
+        write_config(&dir, &leaf(&new_ca, "signer-proxy", &["localhost"]), None);
+        tls.reload().unwrap();
+        assert!(get_root(address, &connector(&old_ca, None)).await.is_err());
+        assert!(get_root(address, &connector(&new_ca, None)).await.is_ok());
+
+        // A broken file keeps the previous certificates in use.
+        fs::write(&config.key, "not a key").unwrap();
+        assert!(tls.reload().is_err());
+        assert!(get_root(address, &connector(&new_ca, None)).await.is_ok());
+    }
+}